@@ -1,23 +1,31 @@
+use crate::clipboard;
 use crate::config::{self, Config};
+use crate::discover::{discover_in, is_markdown_file};
+use crate::graphics::{self, Graphics, Protocol};
+use crate::history::{self, History, Position};
+use crate::keys;
 use crate::markdown::{
-    parse_markdown, wrap_document, Heading, MarkdownStyles, ParsedDocument, RenderedDocument,
+    match_char_ranges, parse_markdown, rewrap_document, search_regex, slugify_anchor,
+    wrap_document, Heading, ImageBoxes, MarkdownStyles, ParsedDocument, RenderedDocument,
+    TextEdit,
 };
-use crate::theme::{ThemeManager, UiPalette};
+use crate::motion::{self, last_line, line_len, Motion, MotionKind, TextObject};
+use crate::theme::{styles_from_palette, ThemeManager, UiPalette};
+use crate::undo::UndoTree;
 use anyhow::{Context, Result};
 use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
 use crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
 use crossterm::{execute, ExecutableCommand};
-use notify::{RecursiveMode, Watcher};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
 use ratatui::backend::CrosstermBackend;
-use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::layout::{Constraint, Direction, Layout, Margin, Rect};
 use ratatui::style::{Color, Modifier, Style};
 use ratatui::text::{Line, Span, Text};
 use ratatui::widgets::{Block, BorderType, Clear, List, ListItem, ListState, Paragraph};
 use ratatui::Terminal;
 use ropey::Rope;
 use std::cmp::Ordering;
-use std::collections::{HashMap, HashSet};
-use std::env;
+use std::collections::HashMap;
 use std::fs;
 use std::io::{self, Stdout};
 use std::ops::Range;
@@ -28,31 +36,74 @@ use syntect::easy::HighlightLines;
 use syntect::highlighting::FontStyle;
 use syntect::parsing::SyntaxSet;
 use unicode_width::UnicodeWidthChar;
-use walkdir::WalkDir;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum AppExit {
-    Quit,
-    Discover,
+pub fn run_app(path: PathBuf, line: Option<usize>, mut config: Config) -> Result<()> {
+    let theme_manager = load_theme_manager(&mut config)?;
+    let markdown = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let _ = history::record_visit(&path);
+    let mut app = App::new(path, markdown, config, theme_manager)?;
+    app.remember_history = true;
+    app.system_clipboard = true;
+    app.load_undo();
+    match line {
+        Some(line) => app.goto_line(line),
+        None => app.restore_position(&History::load()),
+    }
+    run_loop(app)
+}
+
+/// Opens piped markdown in a read-only viewer. Keys are read from the
+/// controlling terminal since stdin has already been consumed.
+pub fn run_pager(markdown: String, mut config: Config) -> Result<()> {
+    ensure_tty()?;
+    let theme_manager = load_theme_manager(&mut config)?;
+    let mut app = App::new(PathBuf::from("-"), markdown, config, theme_manager)?;
+    app.read_only = true;
+    app.system_clipboard = true;
+    app.preview_full = true;
+    app.show_preview = true;
+    app.status = Some("read-only (:w <path> saves a copy)".to_string());
+    run_loop(app)
 }
 
-pub fn run_app(path: PathBuf, mut config: Config) -> Result<AppExit> {
-    let theme_manager = ThemeManager::load(&config)?;
+fn load_theme_manager(config: &mut Config) -> Result<ThemeManager> {
+    let theme_manager = ThemeManager::load(config)?;
     if !theme_manager.theme_names().iter().any(|t| t == &config.theme) {
         config.theme = theme_manager.fallback_name().to_string();
-        config::write_config(&config)?;
+        config::write_config(config)?;
     }
+    Ok(theme_manager)
+}
+
+#[cfg(unix)]
+fn ensure_tty() -> Result<()> {
+    fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .context("Failed to open /dev/tty for keyboard input")?;
+    Ok(())
+}
 
-    let mut app = App::new(path, config, theme_manager)?;
+#[cfg(not(unix))]
+fn ensure_tty() -> Result<()> {
+    Ok(())
+}
 
+fn run_loop(mut app: App) -> Result<()> {
     let mut terminal = setup_terminal()?;
     let _guard = TerminalGuard;
 
     let (tx, rx) = mpsc::channel();
-    let mut watcher = notify::recommended_watcher(move |res| {
+    let watcher = notify::recommended_watcher(move |res| {
         let _ = tx.send(res);
     })?;
-    watcher.watch(&app.file_path, RecursiveMode::NonRecursive)?;
+    app.watcher = Some(watcher);
+    if !app.read_only {
+        let path = app.file_path.clone();
+        app.watch_file(&path);
+    }
 
     let tick_rate = Duration::from_millis(50);
 
@@ -65,6 +116,18 @@ pub fn run_app(path: PathBuf, mut config: Config) -> Result<AppExit> {
         let render_height = layout.preview_height.unwrap_or(layout.editor_height);
         app.ensure_rendered(render_width);
         app.sync_render_from_rope();
+        if app.reveal_cursor {
+            app.reveal_cursor = false;
+            app.ensure_cursor_visible(layout.editor_height);
+            if let Some(scroll) = app.pending_scroll.take() {
+                app.scroll = scroll;
+            } else if app.preview_full {
+                app.update_render_cursor_line();
+                if let Some(line) = app.render_cursor_line {
+                    app.scroll = line;
+                }
+            }
+        }
         app.clamp_scroll(render_height);
         if app.preview_full {
             app.render_cursor_from_scroll();
@@ -76,11 +139,32 @@ pub fn run_app(path: PathBuf, mut config: Config) -> Result<AppExit> {
         }
 
         terminal.draw(|f| ui(f, &mut app, &layout))?;
+        if app.graphics.needs_clear() {
+            terminal.clear()?;
+            terminal.draw(|f| ui(f, &mut app, &layout))?;
+        }
+        app.graphics.flush(terminal.backend_mut())?;
 
-        if event::poll(tick_rate)? {
-            if let Event::Key(key) = event::read()? {
-                if app.handle_key(key, layout.editor_height) {
-                    break;
+        if event::poll(tick_rate)?
+            && let Event::Key(key) = event::read()?
+            && app.handle_key(key, layout.editor_height)
+        {
+            if !app.request_discover {
+                break;
+            }
+            app.request_discover = false;
+            // Retract terminal image placements before discover takes over the screen.
+            app.graphics.begin_frame();
+            app.graphics.flush(terminal.backend_mut())?;
+            terminal.clear()?;
+            let picked = discover_in(&mut terminal, &app.config, &app.theme_manager)?;
+            terminal.clear()?;
+            if let Some(pick) = picked {
+                app.open_buffer(pick.path.clone());
+                if let Some(line) = pick.line
+                    && same_file(&app.file_path, &pick.path)
+                {
+                    app.goto_line(line);
                 }
             }
         }
@@ -94,396 +178,13 @@ pub fn run_app(path: PathBuf, mut config: Config) -> Result<AppExit> {
         app.handle_pending_reload();
     }
 
-    if app.request_discover {
-        Ok(AppExit::Discover)
-    } else {
-        Ok(AppExit::Quit)
-    }
-}
-
-pub fn run_discover(config: &Config) -> Result<Option<PathBuf>> {
-    let theme_manager = ThemeManager::load(config)?;
-    let ui = theme_manager.ui_palette(&config.theme);
-    let (base_style, _) = styles_from_palette(ui);
-
-    let files = collect_markdown_files(config);
-    if files.is_empty() {
-        return Ok(None);
-    }
-
-    let mut state = DiscoverState::new(files, ui, base_style);
-    let mut terminal = setup_terminal()?;
-    let _guard = TerminalGuard;
-
-    let tick_rate = Duration::from_millis(50);
-    loop {
-        let size = terminal.size()?;
-        terminal.draw(|f| discover_ui(f, &mut state, size))?;
-
-        if event::poll(tick_rate)? {
-            if let Event::Key(key) = event::read()? {
-                if let Some(selected) = state.handle_key(key) {
-                    return Ok(selected);
-                }
-            }
-        }
-    }
-}
-
-#[derive(Debug, Clone)]
-struct DiscoverItem {
-    path: PathBuf,
-    display: String,
-}
-
-struct DiscoverState {
-    items: Vec<DiscoverItem>,
-    filtered: Vec<usize>,
-    selected: usize,
-    scroll: usize,
-    ui: UiPalette,
-    base_style: Style,
-    status: Option<String>,
-    query: String,
-    filter_mode: bool,
-    show_help: bool,
-}
-
-impl DiscoverState {
-    fn new(items: Vec<DiscoverItem>, ui: UiPalette, base_style: Style) -> Self {
-        let filtered = (0..items.len()).collect::<Vec<_>>();
-        Self {
-            items,
-            filtered,
-            selected: 0,
-            scroll: 0,
-            ui,
-            base_style,
-            status: None,
-            query: String::new(),
-            filter_mode: false,
-            show_help: false,
-        }
-    }
-
-    fn handle_key(&mut self, key: KeyEvent) -> Option<Option<PathBuf>> {
-        if self.show_help {
-            match key.code {
-                KeyCode::Esc | KeyCode::Char('?') => {
-                    self.show_help = false;
-                    return None;
-                }
-                _ => return None,
-            }
-        }
-
-        if self.filter_mode {
-            match key.code {
-                KeyCode::Esc => {
-                    self.filter_mode = false;
-                    return None;
-                }
-                KeyCode::Enter => {
-                    self.filter_mode = false;
-                    return None;
-                }
-                KeyCode::Backspace => {
-                    self.query.pop();
-                    self.apply_filter();
-                    return None;
-                }
-                KeyCode::Char('u') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                    self.query.clear();
-                    self.apply_filter();
-                    return None;
-                }
-                KeyCode::Up => {
-                    self.selected = self.selected.saturating_sub(1);
-                    return None;
-                }
-                KeyCode::Down => {
-                    if self.selected + 1 < self.filtered.len() {
-                        self.selected += 1;
-                    }
-                    return None;
-                }
-                KeyCode::PageDown => {
-                    if !self.filtered.is_empty() {
-                        self.selected =
-                            (self.selected + 10).min(self.filtered.len().saturating_sub(1));
-                    }
-                    return None;
-                }
-                KeyCode::PageUp => {
-                    self.selected = self.selected.saturating_sub(10);
-                    return None;
-                }
-                KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
-                    self.query.push(c);
-                    self.apply_filter();
-                    return None;
-                }
-                _ => {}
-            }
-        }
-
-        match key.code {
-            KeyCode::Esc | KeyCode::Char('q') => return Some(None),
-            KeyCode::Enter => {
-                let idx = *self.filtered.get(self.selected)?;
-                let path = self.items.get(idx).map(|i| i.path.clone());
-                return Some(path);
-            }
-            KeyCode::Char('j') | KeyCode::Down => {
-                if self.selected + 1 < self.filtered.len() {
-                    self.selected += 1;
-                }
-            }
-            KeyCode::Char('k') | KeyCode::Up => {
-                self.selected = self.selected.saturating_sub(1);
-            }
-            KeyCode::PageDown => {
-                if !self.filtered.is_empty() {
-                    self.selected = (self.selected + 10).min(self.filtered.len().saturating_sub(1));
-                }
-            }
-            KeyCode::PageUp => {
-                self.selected = self.selected.saturating_sub(10);
-            }
-            KeyCode::Char('g') => self.selected = 0,
-            KeyCode::Char('G') => {
-                if !self.filtered.is_empty() {
-                    self.selected = self.filtered.len() - 1;
-                }
-            }
-            KeyCode::Char('?') => {
-                self.show_help = true;
-            }
-            KeyCode::Char('/') => {
-                self.filter_mode = true;
-            }
-            _ => {}
-        }
-        None
-    }
-
-    fn apply_filter(&mut self) {
-        let query = self.query.trim().to_ascii_lowercase();
-        if query.is_empty() {
-            self.filtered = (0..self.items.len()).collect();
-        } else {
-            self.filtered = self
-                .items
-                .iter()
-                .enumerate()
-                .filter_map(|(idx, item)| {
-                    if item.display.to_ascii_lowercase().contains(&query) {
-                        Some(idx)
-                    } else {
-                        None
-                    }
-                })
-                .collect();
-        }
-        if self.filtered.is_empty() {
-            self.selected = 0;
-            self.scroll = 0;
-        } else if self.selected >= self.filtered.len() {
-            self.selected = self.filtered.len() - 1;
-        }
-    }
-}
-
-fn discover_ui(f: &mut ratatui::Frame, state: &mut DiscoverState, size: Rect) {
-    let vertical = Layout::default()
-        .direction(Direction::Vertical)
-        .constraints([Constraint::Min(1), Constraint::Length(1)])
-        .split(size);
-    let main = vertical[0];
-    let status = vertical[1];
-
-    let content_height = main.height.saturating_sub(2).max(1) as usize;
-    if state.selected < state.scroll {
-        state.scroll = state.selected;
-    } else if state.selected >= state.scroll + content_height {
-        state.scroll = state.selected.saturating_sub(content_height.saturating_sub(1));
-    }
-
-    let highlight_fg = state.ui.base_bg.unwrap_or(state.ui.base_fg);
-    let highlight = Style::default().bg(state.ui.accent).fg(highlight_fg);
-
-    let lines: Vec<Line> = state
-        .filtered
-        .iter()
-        .enumerate()
-        .map(|(idx, item_idx)| {
-            let item = &state.items[*item_idx];
-            let style = if idx == state.selected {
-                highlight
-            } else {
-                state.base_style
-            };
-            Line::from(Span::styled(item.display.clone(), style))
-        })
-        .collect();
-
-    let paragraph = Paragraph::new(Text::from(lines))
-        .block(
-            Block::bordered()
-                .title(" Discover ")
-                .border_type(BorderType::Rounded)
-                .border_style(Style::default().fg(state.ui.border))
-                .style(state.base_style),
-        )
-        .style(state.base_style)
-        .scroll((state.scroll as u16, 0));
-    f.render_widget(paragraph, main);
-
-    if state.show_help {
-        let popup = centered_rect(70, 60, main);
-        f.render_widget(Clear, popup);
-        let help_lines = vec![
-            Line::from(Span::styled("Discover Help", Style::default().fg(state.ui.accent).add_modifier(Modifier::BOLD))),
-            Line::from(""),
-            Line::from("j/k or arrows: move selection"),
-            Line::from("PageUp/PageDown: scroll"),
-            Line::from("Enter: open file"),
-            Line::from("q or Esc: quit"),
-            Line::from("/ : filter mode"),
-            Line::from("?: toggle help"),
-            Line::from(""),
-            Line::from("Filter mode: type to filter, Backspace delete, Ctrl+U clear, Enter/Esc exit"),
-        ];
-        let help = Paragraph::new(Text::from(help_lines))
-            .block(
-                Block::bordered()
-                    .title(" Help ")
-                    .border_type(BorderType::Rounded)
-                    .border_style(Style::default().fg(state.ui.border))
-                    .style(state.base_style),
-            )
-            .style(state.base_style);
-        f.render_widget(help, popup);
-    }
-
-    let status_text = state.status.clone().unwrap_or_else(|| {
-        let filter_hint = if state.filter_mode {
-            "filter: ".to_string() + &state.query
-        } else if state.query.is_empty() {
-            "/ filter".to_string()
-        } else {
-            format!("filter: {}", state.query)
-        };
-        format!(
-            "{} files | {} matches | {} | j/k move | Enter open | q quit",
-            state.items.len(),
-            state.filtered.len(),
-            filter_hint
-        )
-    });
-    f.render_widget(
-        Paragraph::new(Line::from(vec![
-            Span::styled("mark", Style::default().fg(state.ui.accent).add_modifier(Modifier::BOLD)),
-            Span::styled(" | ", Style::default().fg(state.ui.muted)),
-            Span::styled(status_text, state.base_style),
-        ]))
-        .style(state.base_style)
-        .block(Block::default().style(state.base_style)),
-        status,
-    );
-}
-
-fn collect_markdown_files(config: &Config) -> Vec<DiscoverItem> {
-    let cwd = env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
-    let home = dirs::home_dir();
-    let mut roots = Vec::new();
-    roots.push(cwd.clone());
-    for path in &config.forced_discover_dirs {
-        roots.push(expand_discover_dir(path, &cwd, home.as_deref()));
-    }
-
-    let mut seen = HashSet::new();
-    let mut items = Vec::new();
-    for root in roots {
-        if !root.exists() {
-            continue;
-        }
-        for entry in WalkDir::new(&root).follow_links(false) {
-            let entry = match entry {
-                Ok(e) => e,
-                Err(_) => continue,
-            };
-            if !entry.file_type().is_file() {
-                continue;
-            }
-            let path = entry.path();
-            if !is_markdown_file(path) {
-                continue;
-            }
-            let path = path.to_path_buf();
-            if !seen.insert(path.clone()) {
-                continue;
-            }
-            let display = display_path(&path, &cwd, home.as_deref());
-            items.push(DiscoverItem { path, display });
-        }
-    }
-    items.sort_by(|a, b| a.display.cmp(&b.display));
-    items
-}
-
-fn is_markdown_file(path: &Path) -> bool {
-    let Some(ext) = path.extension().and_then(|s| s.to_str()) else {
-        return false;
-    };
-    matches!(
-        ext.to_ascii_lowercase().as_str(),
-        "md" | "markdown" | "mdx"
-    )
-}
-
-fn expand_discover_dir(path: &Path, cwd: &Path, home: Option<&Path>) -> PathBuf {
-    let raw = path.to_string_lossy();
-    if raw == "~" {
-        if let Some(home) = home {
-            return home.to_path_buf();
-        }
-    }
-    if let Some(rest) = raw.strip_prefix("~/") {
-        if let Some(home) = home {
-            return home.join(rest);
-        }
-    }
-    if path.is_absolute() {
-        path.to_path_buf()
-    } else {
-        cwd.join(path)
-    }
-}
-
-fn display_path(path: &Path, cwd: &Path, home: Option<&Path>) -> String {
-    if let Ok(rel) = path.strip_prefix(cwd) {
-        let rel_str = rel.display().to_string();
-        return if rel_str.is_empty() {
-            "./".to_string()
-        } else {
-            format!("./{rel_str}")
-        };
-    }
-    if let Some(home) = home {
-        if let Ok(rel) = path.strip_prefix(home) {
-            let rel_str = rel.display().to_string();
-            return if rel_str.is_empty() {
-                "~".to_string()
-            } else {
-                format!("~/{rel_str}")
-            };
-        }
+    if app.remember_history {
+        let _ = history::remember_positions(app.saved_positions());
     }
-    path.display().to_string()
+    Ok(())
 }
 
-struct TerminalGuard;
+pub struct TerminalGuard;
 
 impl Drop for TerminalGuard {
     fn drop(&mut self) {
@@ -493,7 +194,7 @@ impl Drop for TerminalGuard {
     }
 }
 
-fn setup_terminal() -> Result<Terminal<CrosstermBackend<Stdout>>> {
+pub fn setup_terminal() -> Result<Terminal<CrosstermBackend<Stdout>>> {
     enable_raw_mode()?;
     let mut stdout = io::stdout();
     execute!(stdout, EnterAlternateScreen)?;
@@ -517,6 +218,8 @@ enum Mode {
     VisualChar,
     VisualLine,
     CommandInput,
+    BufferPicker,
+    ConfirmSubstitute,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -526,6 +229,21 @@ enum PendingOp {
     Yank,
 }
 
+/// What an operator acts on: the span a motion moves over or a text object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OperatorTarget {
+    Motion(Motion),
+    Object { object: TextObject, inner: bool },
+}
+
+/// A motion or object prefix still waiting for its second key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PendingMotion {
+    Find { forward: bool, till: bool },
+    G,
+    Object { inner: bool },
+}
+
 #[derive(Debug, Clone)]
 struct Register {
     text: String,
@@ -540,12 +258,101 @@ enum LastChange {
     Paste { text: String, linewise: bool },
     ReplaceChar(char),
     ChangeLines { insert: String, count: usize },
+    Operator {
+        op: PendingOp,
+        target: OperatorTarget,
+        count: Option<usize>,
+        insert: String,
+    },
+}
+
+/// First and last line of an ex range, inclusive and 0-based.
+type LineRange = (usize, usize);
+
+/// A `:s` command split into its parts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Substitute {
+    pattern: String,
+    replacement: String,
+    global: bool,
+    confirm: bool,
+    case: Option<bool>,
+}
+
+/// One replacement planned by `:s`, in char offsets of the rope it was planned on.
+#[derive(Debug, Clone)]
+struct SubstituteEdit {
+    start: usize,
+    end: usize,
+    text: String,
+    line: usize,
+}
+
+/// State of a `:s///c` run while it waits for y/n/a/q/l on each match.
+#[derive(Debug, Clone)]
+struct PendingSubstitute {
+    replacement: String,
+    edits: Vec<SubstituteEdit>,
+    next: usize,
+    shift: isize,
+    replaced: usize,
+    lines: usize,
+    last_line: Option<usize>,
+}
+
+impl PendingSubstitute {
+    fn current(&self) -> Option<(usize, usize)> {
+        let edit = self.edits.get(self.next)?;
+        let start = edit.start.saturating_add_signed(self.shift);
+        let end = edit.end.saturating_add_signed(self.shift);
+        Some((start, end))
+    }
+}
+
+/// What `/` looks through: the markdown source or text shown in the preview.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SearchScope {
+    Source,
+    Rendered,
+    Headings,
+    Code,
+}
+
+impl SearchScope {
+    fn next(self) -> Self {
+        match self {
+            SearchScope::Source => SearchScope::Rendered,
+            SearchScope::Rendered => SearchScope::Headings,
+            SearchScope::Headings => SearchScope::Code,
+            SearchScope::Code => SearchScope::Source,
+        }
+    }
+
+    fn prev(self) -> Self {
+        match self {
+            SearchScope::Source => SearchScope::Code,
+            SearchScope::Rendered => SearchScope::Source,
+            SearchScope::Headings => SearchScope::Rendered,
+            SearchScope::Code => SearchScope::Headings,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            SearchScope::Source => "source",
+            SearchScope::Rendered => "rendered",
+            SearchScope::Headings => "headings",
+            SearchScope::Code => "code",
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 struct RawMatch {
     line: usize,
     start: usize,
+    // Preview line of the hit when the scope searches rendered text.
+    rendered: Option<usize>,
 }
 
 #[derive(Debug, Clone)]
@@ -567,6 +374,41 @@ struct LayoutInfo {
     preview_height: Option<u16>,
 }
 
+const JUMP_LIST_LIMIT: usize = 100;
+/// Stops a macro that keeps calling itself.
+const MAX_MACRO_DEPTH: usize = 100;
+
+#[derive(Debug, Clone)]
+struct Jump {
+    path: PathBuf,
+    cursor_char: usize,
+    scroll: usize,
+}
+
+/// Per-file state parked while another buffer is active. The active buffer
+/// lives directly in `App`'s fields and is swapped in and out on switch.
+struct Buffer {
+    file_path: PathBuf,
+    source: String,
+    rope: Rope,
+    parsed: ParsedDocument,
+    rendered: RenderedDocument,
+    theme: String,
+    scroll: usize,
+    edit_scroll: usize,
+    cursor_char: usize,
+    preferred_col: Option<usize>,
+    dirty: bool,
+    render_dirty: bool,
+    read_only: bool,
+    undo: UndoTree,
+    current_match: usize,
+    /// The search active when the buffer was left, saved with its position.
+    search_query: String,
+    last_reload: SystemTime,
+    changed_on_disk: bool,
+}
+
 struct App {
     file_path: PathBuf,
     config: Config,
@@ -584,23 +426,40 @@ struct App {
     preferred_col: Option<usize>,
     dirty: bool,
     render_dirty: bool,
+    /// The span edited since the last render, or `None` when some change since
+    /// then wasn't tracked and the parse has to diff for it.
+    render_edit: Option<TextEdit>,
     pending_op: Option<PendingOp>,
+    pending_motion: Option<PendingMotion>,
+    last_find: Option<(char, bool, bool)>,
+    op_count: Option<usize>,
     pending_register: Option<char>,
     register_waiting: bool,
     registers: HashMap<char, Register>,
+    /// `q` or `@` typed in normal mode, waiting for the register name, with
+    /// the count typed before `@`.
+    macro_waiting: Option<(char, usize)>,
+    /// The register being recorded into and the keys typed so far.
+    recording: Option<(char, Vec<KeyEvent>)>,
+    last_macro: Option<char>,
+    /// How many macros are replaying; their keys aren't recorded again.
+    macro_depth: usize,
     count: Option<usize>,
     last_change: Option<LastChange>,
-    undo_stack: Vec<Rope>,
-    redo_stack: Vec<Rope>,
+    undo: UndoTree,
     insert_record: Option<String>,
     visual_anchor: Option<usize>,
+    visual_lines: Option<LineRange>,
+    substitute: Option<PendingSubstitute>,
     replace_pending: bool,
     pending_change_lines: Option<usize>,
+    pending_change_op: Option<(OperatorTarget, Option<usize>)>,
     show_outline: bool,
     show_preview: bool,
     mode: Mode,
     search_query: String,
     search_input: String,
+    search_scope: SearchScope,
     command_input: String,
     current_match: usize,
     search_matches: Vec<RawMatch>,
@@ -625,17 +484,34 @@ struct App {
     last_editor_width: u16,
     show_help: bool,
     request_discover: bool,
+    reveal_cursor: bool,
+    pending_scroll: Option<usize>,
+    remember_history: bool,
+    /// Whether `"+` and `"*` reach the system clipboard. Off in tests.
+    system_clipboard: bool,
     preview_full: bool,
     preview_was_open: bool,
     preview_ratio: u16,
+    graphics: Graphics,
+    read_only: bool,
+    buffers: Vec<Option<Buffer>>,
+    active_buffer: usize,
+    buffer_selected: usize,
+    watcher: Option<RecommendedWatcher>,
+    jumps: Vec<Jump>,
+    jump_index: usize,
+    pending_g: bool,
     rope: Rope,
 }
 
 impl App {
-    fn new(path: PathBuf, config: Config, theme_manager: ThemeManager) -> Result<Self> {
+    fn new(
+        path: PathBuf,
+        markdown: String,
+        config: Config,
+        theme_manager: ThemeManager,
+    ) -> Result<Self> {
         let syntax_set = theme_manager.syntax_set().clone();
-        let markdown = fs::read_to_string(&path)
-            .with_context(|| format!("Failed to read {}", path.display()))?;
         let theme = theme_manager.get(&config.theme);
         let ui = theme_manager.ui_palette(&config.theme);
         let (base_style, markdown_styles) = styles_from_palette(ui);
@@ -646,8 +522,21 @@ impl App {
             &markdown_styles,
             config.tab_width,
         )?;
-        let rendered = wrap_document(&parsed, 80, None, config.search_case_sensitive);
+        let graphics = Graphics::new(Protocol::from_config(&config.image_protocol));
+        let image_base = image_base(&path);
+        let images = ImageBoxes {
+            rows: image_rows_for(&config, &graphics),
+            base: &image_base,
+        };
+        let rendered = wrap_document(
+            &parsed,
+            80,
+            None,
+            config.search_case_sensitive,
+            Some(images),
+        );
         let rope = Rope::from_str(&markdown);
+        let undo = UndoTree::new(&rope, config.undo_levels);
         let show_outline = config.show_outline;
 
         let theme_selected = theme_manager
@@ -682,23 +571,34 @@ impl App {
             preferred_col: None,
             dirty: false,
             render_dirty: false,
+            render_edit: None,
             pending_op: None,
+            pending_motion: None,
+            last_find: None,
+            op_count: None,
             pending_register: None,
             register_waiting: false,
             registers,
+            macro_waiting: None,
+            recording: None,
+            last_macro: None,
+            macro_depth: 0,
             count: None,
             last_change: None,
-            undo_stack: Vec::new(),
-            redo_stack: Vec::new(),
+            undo,
             insert_record: None,
             visual_anchor: None,
+            visual_lines: None,
+            substitute: None,
             replace_pending: false,
             pending_change_lines: None,
+            pending_change_op: None,
             show_outline,
             show_preview: false,
             mode: Mode::Normal,
             search_query: String::new(),
             search_input: String::new(),
+            search_scope: SearchScope::Source,
             command_input: String::new(),
             current_match: 0,
             search_matches: Vec::new(),
@@ -723,9 +623,22 @@ impl App {
             last_editor_width: 0,
             show_help: false,
             request_discover: false,
+            reveal_cursor: false,
+            pending_scroll: None,
+            remember_history: false,
+            system_clipboard: false,
             preview_full: false,
             preview_was_open: false,
             preview_ratio,
+            graphics,
+            read_only: false,
+            buffers: vec![None],
+            active_buffer: 0,
+            buffer_selected: 0,
+            watcher: None,
+            jumps: Vec::new(),
+            jump_index: 0,
+            pending_g: false,
             rope,
         })
     }
@@ -794,17 +707,28 @@ impl App {
     }
 
     fn refresh_render(&mut self, width: u16) {
+        self.rewrap(None, width);
+    }
+
+    fn rewrap(&mut self, previous: Option<RenderedDocument>, width: u16) {
         let query = if self.search_query.is_empty() {
             None
         } else {
             Some(self.search_query.as_str())
         };
         let width = if self.config.wrap { width } else { u16::MAX };
-        self.rendered = wrap_document(
+        let image_base = image_base(&self.file_path);
+        let images = ImageBoxes {
+            rows: image_rows_for(&self.config, &self.graphics),
+            base: &image_base,
+        };
+        self.rendered = rewrap_document(
             &self.parsed,
+            previous,
             width,
             query,
             self.config.search_case_sensitive,
+            Some(images),
         );
         self.render_cursor_line = None;
         if !self.rendered.matches.is_empty() && self.current_match >= self.rendered.matches.len() {
@@ -824,22 +748,45 @@ impl App {
     }
 
     fn handle_key(&mut self, key: KeyEvent, content_height: u16) -> bool {
+        // Keys replayed from a macro, and the `q` that stops recording, stay
+        // out of the recording.
+        let recording = self.recording.is_some() && self.macro_depth == 0;
+        let quit = self.dispatch_key(key, content_height);
+        if recording && let Some((_, keys)) = self.recording.as_mut() {
+            keys.push(key);
+        }
+        quit
+    }
+
+    fn dispatch_key(&mut self, key: KeyEvent, content_height: u16) -> bool {
         if self.preview_full
             && !matches!(
                 self.mode,
-                Mode::CommandInput | Mode::SearchInput | Mode::ThemePicker
+                Mode::CommandInput
+                    | Mode::SearchInput
+                    | Mode::ThemePicker
+                    | Mode::BufferPicker
+                    | Mode::ConfirmSubstitute
             )
         {
             return self.handle_preview_navigation(key, content_height);
         }
-        match self.mode {
-            Mode::SearchInput => return self.handle_search_input(key),
-            Mode::ThemePicker => return self.handle_theme_picker(key),
-            Mode::CommandInput => return self.handle_command_input(key),
+        let quit = match self.mode {
+            Mode::SearchInput => self.handle_search_input(key),
+            Mode::ThemePicker => self.handle_theme_picker(key),
+            Mode::BufferPicker => self.handle_buffer_picker(key),
+            Mode::CommandInput => self.handle_command_input(key),
+            Mode::ConfirmSubstitute => self.handle_substitute_confirm(key),
             Mode::Normal | Mode::Insert | Mode::VisualChar | Mode::VisualLine => {
-                return self.handle_editor_input(key, content_height)
+                self.handle_editor_input(key, content_height)
             }
+        };
+        // A change ends with the key that made it, unless it goes on through
+        // an insert session or a confirmed substitution.
+        if self.undo.in_change() && !matches!(self.mode, Mode::Insert | Mode::ConfirmSubstitute) {
+            self.undo.commit(&self.rope);
         }
+        quit
     }
 
     fn handle_search_input(&mut self, key: KeyEvent) -> bool {
@@ -854,14 +801,25 @@ impl App {
                 self.search_input.clear();
                 self.refresh_render(self.last_width.max(1));
                 self.search_dirty = true;
-                self.update_search_matches();
-                if self.search_matches.is_empty() && !self.search_query.is_empty() {
+                if let Err(err) = self.update_search_matches() {
+                    // Regex errors span several lines; the last one names the problem.
+                    let err = err.to_string();
+                    let reason = err.lines().last().unwrap_or_default();
+                    let reason = reason.trim_start_matches("error: ");
+                    self.status = Some(format!("Invalid pattern: {reason}"));
+                } else if self.search_matches.is_empty() && !self.search_query.is_empty() {
                     self.status = Some("No matches".to_string());
                 } else if !self.search_matches.is_empty() {
                     self.current_match = 0;
                     self.jump_to_match(self.current_match);
                 }
             }
+            KeyCode::Tab => {
+                self.search_scope = self.search_scope.next();
+            }
+            KeyCode::BackTab => {
+                self.search_scope = self.search_scope.prev();
+            }
             KeyCode::Backspace => {
                 self.search_input.pop();
             }
@@ -882,25 +840,21 @@ impl App {
         match key.code {
             KeyCode::Esc => {
                 self.mode = Mode::Normal;
-                if let Some(original) = self.theme_before_picker.take() {
-                    if self.config.theme != original {
-                        self.config.theme = original;
-                        self.apply_theme_styles();
-                        self.reparse_with_theme(false);
-                    }
+                if let Some(original) = self.theme_before_picker.take()
+                    && self.config.theme != original
+                {
+                    self.config.theme = original;
+                    self.apply_theme_styles();
+                    self.reparse_with_theme(false);
                 }
             }
-            KeyCode::Up => {
-                if self.theme_selected > 0 {
-                    self.theme_selected -= 1;
-                    self.preview_theme_selection();
-                }
+            KeyCode::Up if self.theme_selected > 0 => {
+                self.theme_selected -= 1;
+                self.preview_theme_selection();
             }
-            KeyCode::Down => {
-                if self.theme_selected + 1 < total {
-                    self.theme_selected += 1;
-                    self.preview_theme_selection();
-                }
+            KeyCode::Down if self.theme_selected + 1 < total => {
+                self.theme_selected += 1;
+                self.preview_theme_selection();
             }
             KeyCode::PageUp => {
                 self.theme_selected = self.theme_selected.saturating_sub(10);
@@ -925,13 +879,43 @@ impl App {
         false
     }
 
+    fn open_buffer_picker(&mut self) {
+        self.mode = Mode::BufferPicker;
+        self.buffer_selected = self.active_buffer;
+    }
+
+    fn handle_buffer_picker(&mut self, key: KeyEvent) -> bool {
+        let total = self.buffer_count();
+        match key.code {
+            KeyCode::Esc | KeyCode::Char('q') => {
+                self.mode = Mode::Normal;
+            }
+            KeyCode::Up | KeyCode::Char('k') if self.buffer_selected > 0 => {
+                self.buffer_selected -= 1;
+            }
+            KeyCode::Down | KeyCode::Char('j') if self.buffer_selected + 1 < total => {
+                self.buffer_selected += 1;
+            }
+            KeyCode::Char('d') => {
+                self.delete_buffer(self.buffer_selected, false);
+                self.mode = Mode::BufferPicker;
+            }
+            KeyCode::Enter => {
+                self.mode = Mode::Normal;
+                self.switch_buffer(self.buffer_selected);
+            }
+            _ => {}
+        }
+        false
+    }
+
     fn jump_heading(&mut self, delta: isize) {
         if self.rendered.headings.is_empty() {
             return;
         }
         let anchor = self
             .render_cursor_line
-            .or_else(|| self.compute_rendered_cursor_line_col(self.scroll).map(|(line, _)| line))
+            .or_else(|| self.compute_rendered_cursor_line_col().map(|(line, _)| line))
             .unwrap_or(self.scroll);
         let current = current_heading_index(anchor, &self.rendered.headings);
         let next = match delta.cmp(&0) {
@@ -960,6 +944,10 @@ impl App {
     }
 
     fn jump_to_anchor_target(&mut self, target: &str) -> bool {
+        self.goto_anchor(target, true)
+    }
+
+    fn goto_anchor(&mut self, target: &str, record_jump: bool) -> bool {
         let Some(anchor) = normalize_anchor_target(target) else {
             self.status = Some(format!("Invalid anchor target: {target}"));
             return false;
@@ -969,6 +957,9 @@ impl App {
             return false;
         };
 
+        if record_jump {
+            self.push_jump();
+        }
         self.set_rendered_cursor_line(line);
         if self.show_preview || self.preview_full {
             self.ensure_rendered_cursor_visible(self.last_height.max(1));
@@ -977,7 +968,7 @@ impl App {
         true
     }
 
-    fn follow_anchor_under_cursor(&mut self) {
+    fn follow_link_under_cursor(&mut self) {
         let (line_idx, col) = self.cursor_line_col();
         let mut line = self.rope.line(line_idx).to_string();
         if line.ends_with('\n') {
@@ -986,10 +977,127 @@ impl App {
                 line.pop();
             }
         }
-        let Some(target) = find_anchor_link_under_cursor(&line, col) else {
+        let Some(target) =
+            find_link_under_cursor(&line, col).or_else(|| find_url_under_cursor(&line, col))
+        else {
             return;
         };
-        self.jump_to_anchor_target(&target);
+        self.follow_link(&target);
+    }
+
+    fn follow_link(&mut self, target: &str) {
+        let base_dir = self
+            .file_path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        match classify_link(target, &base_dir) {
+            LinkTarget::Anchor(anchor) => {
+                self.jump_to_anchor_target(&anchor);
+            }
+            LinkTarget::File { path, fragment } => {
+                if !is_markdown_file(&path) {
+                    self.status = Some(format!("Not a markdown file: {}", path.display()));
+                    return;
+                }
+                if !path.is_file() {
+                    self.status = Some(format!("File not found: {}", path.display()));
+                    return;
+                }
+                self.push_jump();
+                self.open_buffer(path);
+                if let Some(fragment) = fragment {
+                    self.goto_anchor(&fragment, false);
+                }
+            }
+            LinkTarget::External(url) => self.open_external(&url),
+        }
+    }
+
+    fn open_external(&mut self, url: &str) {
+        let lower = url.to_ascii_lowercase();
+        if !["http://", "https://", "mailto:"]
+            .iter()
+            .any(|scheme| lower.starts_with(scheme))
+        {
+            self.status = Some(format!("Unsupported link: {url}"));
+            return;
+        }
+        self.status = Some(match config::open_url(&self.config.open_command, url) {
+            Ok(()) => format!("Opened {url}"),
+            Err(err) => format!("Failed to open {url}: {err}"),
+        });
+    }
+
+    fn open_url_under_cursor(&mut self) {
+        let (line_idx, col) = self.cursor_line_col();
+        let line = self.rope.line(line_idx).to_string();
+        let line = line.trim_end_matches(['\n', '\r']);
+        let target = find_link_under_cursor(line, col)
+            .or_else(|| find_url_under_cursor(line, col));
+        match target.map(|target| classify_link(&target, Path::new(""))) {
+            Some(LinkTarget::External(url)) => self.open_external(&url),
+            _ => self.status = Some("No URL under cursor".to_string()),
+        }
+    }
+
+    fn current_jump(&self) -> Jump {
+        Jump {
+            path: self.file_path.clone(),
+            cursor_char: self.cursor_char,
+            scroll: self.scroll,
+        }
+    }
+
+    /// Records the current location before a jump, dropping any forward history.
+    fn push_jump(&mut self) {
+        self.jumps.truncate(self.jump_index);
+        self.jumps.push(self.current_jump());
+        if self.jumps.len() > JUMP_LIST_LIMIT {
+            self.jumps.remove(0);
+        }
+        self.jump_index = self.jumps.len();
+    }
+
+    fn jump_back(&mut self) {
+        if self.jump_index == 0 {
+            self.status = Some("Already at oldest jump".to_string());
+            return;
+        }
+        if self.jump_index == self.jumps.len() {
+            self.jumps.push(self.current_jump());
+        }
+        self.jump_index -= 1;
+        let jump = self.jumps[self.jump_index].clone();
+        self.goto_jump(jump);
+    }
+
+    fn jump_forward(&mut self) {
+        if self.jump_index + 1 >= self.jumps.len() {
+            self.status = Some("Already at newest jump".to_string());
+            return;
+        }
+        self.jump_index += 1;
+        let jump = self.jumps[self.jump_index].clone();
+        self.goto_jump(jump);
+    }
+
+    fn goto_jump(&mut self, jump: Jump) {
+        if !same_file(&jump.path, &self.file_path) {
+            self.open_buffer(jump.path.clone());
+            if !same_file(&jump.path, &self.file_path) {
+                return;
+            }
+        }
+        self.cursor_char = jump.cursor_char.min(self.rope.len_chars());
+        self.preferred_col = None;
+        self.scroll = jump.scroll;
+        self.render_cursor_line = None;
+        self.status = Some(format!(
+            "Jump {}/{}",
+            self.jump_index + 1,
+            self.jumps.len()
+        ));
     }
 
     fn request_reload(&mut self) {
@@ -997,11 +1105,25 @@ impl App {
         self.reload.deadline = Some(Instant::now() + Duration::from_millis(150));
     }
 
-    fn on_fs_event(&mut self, _event: notify::Event) {
+    fn on_fs_event(&mut self, event: notify::Event) {
+        let touches_active =
+            event.paths.is_empty() || event.paths.iter().any(|p| same_file(p, &self.file_path));
+        for buffer in self.buffers.iter_mut().flatten() {
+            if event.paths.iter().any(|p| same_file(p, &buffer.file_path)) {
+                buffer.changed_on_disk = true;
+            }
+        }
+        if !touches_active {
+            return;
+        }
         if self.dirty
             || matches!(
                 self.mode,
-                Mode::Insert | Mode::VisualChar | Mode::VisualLine | Mode::CommandInput
+                Mode::Insert
+                    | Mode::VisualChar
+                    | Mode::VisualLine
+                    | Mode::CommandInput
+                    | Mode::ConfirmSubstitute
             )
         {
             self.status = Some("External change ignored (editing)".to_string());
@@ -1020,10 +1142,10 @@ impl App {
         if !self.reload.pending {
             return;
         }
-        if let Some(deadline) = self.reload.deadline {
-            if Instant::now() < deadline {
-                return;
-            }
+        if let Some(deadline) = self.reload.deadline
+            && Instant::now() < deadline
+        {
+            return;
         }
         self.reload.pending = false;
         self.reload.deadline = None;
@@ -1054,9 +1176,13 @@ impl App {
         ) {
             Ok(parsed) => {
                 self.source = markdown;
+                // The reload lands as one undoable change, so edits made
+                // elsewhere can be stepped back through like local ones.
+                self.undo.begin(&self.rope, self.cursor_char);
                 self.rope = Rope::from_str(&self.source);
-                self.undo_stack.clear();
-                self.redo_stack.clear();
+                self.undo.commit(&self.rope);
+                self.cursor_char = self.cursor_char.min(self.rope.len_chars());
+                self.persist_undo();
                 self.editor_cache_dirty = true;
                 self.parsed = parsed;
                 self.refresh_render(self.last_width.max(1));
@@ -1064,6 +1190,7 @@ impl App {
                 self.render_dirty = false;
                 self.render_cursor_line = None;
                 self.search_dirty = true;
+                self.graphics.invalidate();
                 self.status = Some("Reloaded".to_string());
                 if let Some(idx) = find_anchor(&anchor, &self.rendered.plain_lines, self.scroll) {
                     self.scroll = idx;
@@ -1074,7 +1201,7 @@ impl App {
     }
 
     fn reparse_with_theme(&mut self, announce: bool) {
-        let source = self.source.clone();
+        let source = self.rope.to_string();
         self.reparse_with_text(&source, announce);
     }
 
@@ -1118,12 +1245,52 @@ impl App {
         }
     }
 
+    /// Records an edit of the rope for the next incremental parse.
+    fn note_edit(&mut self, edit: TextEdit) {
+        self.render_edit = match self.render_edit {
+            _ if !self.render_dirty => Some(edit),
+            Some(previous) => Some(previous.then(edit)),
+            None => None,
+        };
+        self.mark_render_dirty();
+    }
+
+    /// Marks a change to the rope whose span isn't known.
+    fn forget_edit(&mut self) {
+        self.render_edit = None;
+        self.mark_render_dirty();
+    }
+
+    fn rope_insert(&mut self, at: usize, text: &str) {
+        self.rope.insert(at, text);
+        self.note_edit(TextEdit::new(at, 0, text.chars().count()));
+    }
+
+    fn rope_remove(&mut self, range: std::ops::Range<usize>) {
+        self.note_edit(TextEdit::new(range.start, range.len(), 0));
+        self.rope.remove(range);
+    }
+
     fn sync_render_from_rope(&mut self) {
         if !self.render_dirty {
             return;
         }
-        let text = self.rope.to_string();
-        self.reparse_with_text(&text, false);
+        let edit = self.render_edit.take();
+        let theme = self.theme_manager.get(&self.config.theme);
+        match self.parsed.update(
+            &self.rope,
+            edit,
+            &self.syntax_set,
+            theme,
+            &self.markdown_styles,
+            self.config.tab_width,
+        ) {
+            Ok(()) => {
+                let previous = std::mem::take(&mut self.rendered);
+                self.rewrap(Some(previous), self.last_width.max(1));
+            }
+            Err(err) => self.status = Some(format!("Parse error: {err}")),
+        }
         let max_scroll = self.rendered.lines.len().saturating_sub(1);
         if self.scroll > max_scroll {
             self.scroll = max_scroll;
@@ -1133,12 +1300,12 @@ impl App {
     }
 
     fn preview_theme_selection(&mut self) {
-        if let Some(theme) = self.theme_manager.theme_names().get(self.theme_selected) {
-            if self.config.theme != *theme {
-                self.config.theme = theme.clone();
-                self.apply_theme_styles();
-                self.reparse_with_theme(false);
-            }
+        if let Some(theme) = self.theme_manager.theme_names().get(self.theme_selected)
+            && self.config.theme != *theme
+        {
+            self.config.theme = theme.clone();
+            self.apply_theme_styles();
+            self.reparse_with_theme(false);
         }
     }
 
@@ -1175,6 +1342,23 @@ impl App {
         if self.consume_register_wait(key) {
             return false;
         }
+        if let Some((prefix, count)) = self.macro_waiting.take() {
+            if let KeyCode::Char(reg) = key.code {
+                if prefix == 'q' {
+                    self.start_recording(reg);
+                } else {
+                    return self.play_macro(reg, count, content_height);
+                }
+            }
+            return false;
+        }
+        let awaiting_motion = self.pending_op.is_some() || self.pending_motion.is_some();
+        if self.read_only && !self.pending_g && !awaiting_motion && is_edit_key(key) {
+            self.clear_pending();
+            self.count = None;
+            self.deny_read_only();
+            return false;
+        }
         if key.modifiers.contains(KeyModifiers::ALT) {
             match key.code {
                 KeyCode::Left | KeyCode::Char('h') => {
@@ -1222,9 +1406,21 @@ impl App {
                     self.request_discover = true;
                     return true;
                 }
+                KeyCode::Char('o') => {
+                    self.jump_back();
+                    handled_ctrl_move = true;
+                }
+                KeyCode::Char('i') => {
+                    self.jump_forward();
+                    handled_ctrl_move = true;
+                }
                 _ => {}
             }
         }
+        if key.code == KeyCode::Tab {
+            self.jump_forward();
+            handled_ctrl_move = true;
+        }
         if handled_ctrl_move {
             self.ensure_cursor_visible(content_height);
             if !self.preview_full {
@@ -1238,27 +1434,60 @@ impl App {
             return false;
         }
 
-        if let KeyCode::Char(c) = key.code {
-            if let Some(digit) = c.to_digit(10) {
-                if digit == 0 && self.count.is_none() {
-                    self.move_cursor_line_start();
-                    self.ensure_cursor_visible(content_height);
+        if let Some(pending) = self.pending_motion.take() {
+            let target = self.resolve_pending_motion(pending, key);
+            match (self.pending_op.take(), target) {
+                (Some(op), Some(target)) => self.apply_operator(op, target),
+                (None, Some(OperatorTarget::Motion(motion))) => {
+                    let count = self.count.take();
+                    self.move_by_motion(motion, count);
+                }
+                _ => self.clear_pending(),
+            }
+            self.sync_cursor_view(content_height);
+            return false;
+        }
+
+        if let KeyCode::Char(c) = key.code
+            && let Some(digit) = c.to_digit(10)
+        {
+            if digit == 0 && self.count.is_none() {
+                if let Some(op) = self.pending_op.take() {
+                    self.apply_operator(op, OperatorTarget::Motion(Motion::LineStart));
+                    self.sync_cursor_view(content_height);
                     return false;
                 }
-                self.push_count(digit as usize);
+                self.move_cursor_line_start();
+                self.ensure_cursor_visible(content_height);
                 return false;
             }
+            self.push_count(digit as usize);
+            return false;
+        }
+
+        if self.pending_g {
+            self.pending_g = false;
+            match key.code {
+                KeyCode::Char('g') => self.move_cursor_file_start(),
+                KeyCode::Char('x') => self.open_url_under_cursor(),
+                KeyCode::Char('-') => self.undo_in_time(true),
+                KeyCode::Char('+') => self.undo_in_time(false),
+                _ => {}
+            }
+            self.count = None;
+            self.ensure_cursor_visible(content_height);
+            return false;
         }
 
         if let Some(op) = self.pending_op {
-            self.pending_op = None;
             if matches!(
                 (op, key.code),
                 (PendingOp::Delete, KeyCode::Char('d'))
                     | (PendingOp::Change, KeyCode::Char('c'))
                     | (PendingOp::Yank, KeyCode::Char('y'))
             ) {
-                let count = self.take_count();
+                self.pending_op = None;
+                let count = self.take_operator_count().unwrap_or(1);
                 match op {
                     PendingOp::Delete => self.delete_lines(count),
                     PendingOp::Change => {
@@ -1266,7 +1495,28 @@ impl App {
                     }
                     PendingOp::Yank => self.yank_lines(count),
                 }
+                self.sync_cursor_view(content_height);
+                return false;
+            }
+            let pending = match key.code {
+                KeyCode::Char('i') => Some(PendingMotion::Object { inner: true }),
+                KeyCode::Char('a') => Some(PendingMotion::Object { inner: false }),
+                KeyCode::Char('g') => Some(PendingMotion::G),
+                KeyCode::Char(c) => find_prefix(c),
+                _ => None,
+            };
+            if pending.is_some() {
+                self.pending_motion = pending;
+                return false;
+            }
+            self.pending_op = None;
+            match key.code {
+                KeyCode::Char(c) if let Some(motion) = self.motion_for_key(c) => {
+                    self.apply_operator(op, OperatorTarget::Motion(motion));
+                }
+                _ => self.clear_pending(),
             }
+            self.sync_cursor_view(content_height);
             return false;
         }
 
@@ -1275,13 +1525,16 @@ impl App {
                 self.clear_pending();
             }
             KeyCode::Char('q') => {
-                if self.dirty {
-                    self.status =
-                        Some("No write since last change (use :q! to discard)".to_string());
+                if self.recording.is_some() {
+                    self.stop_recording();
                 } else {
-                    return true;
+                    self.macro_waiting = Some(('q', 1));
                 }
             }
+            KeyCode::Char('@') => {
+                let count = self.take_count();
+                self.macro_waiting = Some(('@', count));
+            }
             KeyCode::Char('"') => {
                 self.register_waiting = true;
             }
@@ -1342,12 +1595,15 @@ impl App {
             }
             KeyCode::Char('d') => {
                 self.pending_op = Some(PendingOp::Delete);
+                self.op_count = self.count.take();
             }
             KeyCode::Char('c') => {
                 self.pending_op = Some(PendingOp::Change);
+                self.op_count = self.count.take();
             }
             KeyCode::Char('y') => {
                 self.pending_op = Some(PendingOp::Yank);
+                self.op_count = self.count.take();
             }
             KeyCode::Char('p') => {
                 let count = self.take_count();
@@ -1363,7 +1619,11 @@ impl App {
                 self.replace_pending = true;
             }
             KeyCode::Char('R') => {
-                self.request_reload();
+                if self.read_only {
+                    self.status = Some("Nothing to reload (stdin)".to_string());
+                } else {
+                    self.request_reload();
+                }
             }
             KeyCode::Char(':') => {
                 self.mode = Mode::CommandInput;
@@ -1382,7 +1642,7 @@ impl App {
             KeyCode::Char('[') => self.jump_heading(-1),
             KeyCode::Char(']') => self.jump_heading(1),
             KeyCode::Char('g') => {
-                self.move_cursor_file_start();
+                self.pending_g = true;
             }
             KeyCode::Char('G') => {
                 self.move_cursor_file_end();
@@ -1393,26 +1653,98 @@ impl App {
             }
             KeyCode::Char('n') => self.jump_match(1),
             KeyCode::Char('N') => self.jump_match(-1),
-            KeyCode::Enter => self.follow_anchor_under_cursor(),
-            KeyCode::Char('t') => {
-                self.mode = Mode::ThemePicker;
-                self.theme_before_picker = Some(self.config.theme.clone());
-                self.theme_selected = self
-                    .theme_manager
-                    .theme_names()
-                    .iter()
-                    .position(|name| name == &self.config.theme)
-                    .unwrap_or(0);
-            }
+            KeyCode::Enter => self.follow_link_under_cursor(),
             KeyCode::Char('?') => {
                 self.show_help = true;
             }
+            KeyCode::Char(c) => self.handle_motion_key(c),
             _ => {}
         }
 
-        if self.pending_op.is_none() && !self.register_waiting {
+        if self.pending_op.is_none() && self.pending_motion.is_none() && !self.register_waiting {
             self.count = None;
         }
+        self.sync_cursor_view(content_height);
+        false
+    }
+
+    fn open_theme_picker(&mut self) {
+        self.mode = Mode::ThemePicker;
+        self.theme_before_picker = Some(self.config.theme.clone());
+        self.theme_selected = self
+            .theme_manager
+            .theme_names()
+            .iter()
+            .position(|name| name == &self.config.theme)
+            .unwrap_or(0);
+    }
+
+    /// The motion a key makes on its own; `;` and `,` repeat the last `f`/`t`
+    /// in the same and the opposite direction.
+    fn motion_for_key(&self, key: char) -> Option<Motion> {
+        match key {
+            ';' | ',' => self.last_find.map(|(ch, forward, till)| Motion::Find {
+                ch,
+                forward: forward == (key == ';'),
+                till,
+            }),
+            _ => Motion::from_key(key),
+        }
+    }
+
+    /// Completes a two-key prefix (`f`, `t`, `g`, `i`, `a`) with its second key.
+    fn resolve_pending_motion(
+        &mut self,
+        pending: PendingMotion,
+        key: KeyEvent,
+    ) -> Option<OperatorTarget> {
+        let KeyCode::Char(c) = key.code else {
+            return None;
+        };
+        match pending {
+            PendingMotion::Find { forward, till } => {
+                self.last_find = Some((c, forward, till));
+                Some(OperatorTarget::Motion(Motion::Find { ch: c, forward, till }))
+            }
+            PendingMotion::G => (c == 'g').then_some(OperatorTarget::Motion(Motion::FileStart)),
+            PendingMotion::Object { inner } => {
+                TextObject::from_key(c).map(|object| OperatorTarget::Object { object, inner })
+            }
+        }
+    }
+
+    /// Cursor motions shared by normal and visual mode. Other keys are ignored.
+    fn handle_motion_key(&mut self, key: char) {
+        if let Some(pending) = find_prefix(key) {
+            self.pending_motion = Some(pending);
+            return;
+        }
+        let Some(motion) = self.motion_for_key(key) else {
+            return;
+        };
+        let mut count = self.count.take();
+        // A repeated `t` right before its char would not move, so it skips that one.
+        if matches!(key, ';' | ',')
+            && let Motion::Find { till: true, .. } = motion
+            && motion.apply(&self.rope, self.cursor_char, count) == Some(self.cursor_char)
+        {
+            count = Some(count.unwrap_or(1) + 1);
+        }
+        self.move_by_motion(motion, count);
+    }
+
+    fn move_by_motion(&mut self, motion: Motion, count: Option<usize>) {
+        let Some(target) = motion.apply(&self.rope, self.cursor_char, count) else {
+            return;
+        };
+        let last = last_line(&self.rope);
+        let buffer_end = self.rope.line_to_char(last) + line_len(&self.rope, last);
+        self.cursor_char = target.min(buffer_end);
+        // After `$`, moving up or down keeps to the end of each line.
+        self.preferred_col = (motion == Motion::LineEnd).then_some(usize::MAX);
+    }
+
+    fn sync_cursor_view(&mut self, content_height: u16) {
         self.ensure_cursor_visible(content_height);
         if self.show_preview || self.show_outline {
             self.update_render_cursor_line();
@@ -1420,7 +1752,6 @@ impl App {
         if self.show_preview {
             self.ensure_rendered_cursor_visible(content_height);
         }
-        false
     }
 
     fn handle_insert_mode(&mut self, key: KeyEvent, content_height: u16) -> bool {
@@ -1457,10 +1788,8 @@ impl App {
                 let spaces = " ".repeat(self.config.tab_width.max(1));
                 self.insert_str(&spaces);
             }
-            KeyCode::Char(c) => {
-                if !key.modifiers.contains(KeyModifiers::CONTROL) {
-                    self.insert_char(c);
-                }
+            KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.insert_char(c);
             }
             _ => {}
         }
@@ -1482,16 +1811,52 @@ impl App {
         if self.consume_register_wait(key) {
             return false;
         }
+        let pending = self.pending_motion.take();
+        if let Some(pending) = pending
+            && let Some(OperatorTarget::Motion(motion)) = self.resolve_pending_motion(pending, key)
+        {
+            let count = self.count.take();
+            self.move_by_motion(motion, count);
+        } else if let KeyCode::Char(c) = key.code
+            && let Some(digit) = c.to_digit(10)
+            && (digit != 0 || self.count.is_some())
+        {
+            self.push_count(digit as usize);
+            return false;
+        }
         match key.code {
+            _ if pending.is_some() => {}
             KeyCode::Esc => {
                 self.exit_visual_mode();
             }
-            KeyCode::Char('h') | KeyCode::Left => self.move_cursor_left(),
-            KeyCode::Char('j') | KeyCode::Down => self.move_cursor_down(),
-            KeyCode::Char('k') | KeyCode::Up => self.move_cursor_up(),
-            KeyCode::Char('l') | KeyCode::Right => self.move_cursor_right(),
+            KeyCode::Char('"') => {
+                self.register_waiting = true;
+            }
+            KeyCode::Char('h') | KeyCode::Left => {
+                for _ in 0..self.take_count() {
+                    self.move_cursor_left();
+                }
+            }
+            KeyCode::Char('j') | KeyCode::Down => {
+                for _ in 0..self.take_count() {
+                    self.move_cursor_down();
+                }
+            }
+            KeyCode::Char('k') | KeyCode::Up => {
+                for _ in 0..self.take_count() {
+                    self.move_cursor_up();
+                }
+            }
+            KeyCode::Char('l') | KeyCode::Right => {
+                for _ in 0..self.take_count() {
+                    self.move_cursor_right();
+                }
+            }
             KeyCode::Char('0') => self.move_cursor_line_start(),
-            KeyCode::Char('$') => self.move_cursor_line_end(),
+            KeyCode::Char('d') | KeyCode::Char('c') if self.read_only => {
+                self.deny_read_only();
+                self.exit_visual_mode();
+            }
             KeyCode::Char('d') => {
                 self.delete_selection();
                 self.exit_visual_mode();
@@ -1506,11 +1871,24 @@ impl App {
                 self.enter_insert_mode();
             }
             KeyCode::Char(':') => {
+                if let Some(anchor) = self.visual_anchor {
+                    let anchor_line = self.rope.char_to_line(anchor);
+                    let cursor_line = self.rope.char_to_line(self.cursor_char);
+                    self.visual_lines =
+                        Some((anchor_line.min(cursor_line), anchor_line.max(cursor_line)));
+                }
+                self.exit_visual_mode();
+                self.status = None;
                 self.mode = Mode::CommandInput;
-                self.command_input.clear();
+                self.command_input = "'<,'>".to_string();
             }
+            KeyCode::Char('g') => self.pending_motion = Some(PendingMotion::G),
+            KeyCode::Char(c) => self.handle_motion_key(c),
             _ => {}
         }
+        if self.pending_motion.is_none() {
+            self.count = None;
+        }
         self.ensure_cursor_visible(content_height);
         if !self.preview_full {
             if self.show_preview || self.show_outline {
@@ -1562,6 +1940,14 @@ impl App {
                     self.preview_scroll_by(-half, content_height);
                     return false;
                 }
+                KeyCode::Char('o') => {
+                    self.jump_back();
+                    return false;
+                }
+                KeyCode::Char('i') => {
+                    self.jump_forward();
+                    return false;
+                }
                 KeyCode::Char('b') => {
                     if self.preview_full {
                         self.preview_full = false;
@@ -1589,14 +1975,14 @@ impl App {
             KeyCode::PageUp => self.preview_scroll_by(-(content_height as isize), content_height),
             KeyCode::Char('[') => self.jump_heading_preview(-1, content_height),
             KeyCode::Char(']') => self.jump_heading_preview(1, content_height),
-            KeyCode::Char('q') => {
-                if self.dirty {
-                    self.status =
-                        Some("No write since last change (use :q! to discard)".to_string());
-                } else {
-                    return true;
-                }
+            KeyCode::Char('/') => {
+                self.search_input = self.search_query.clone();
+                self.mode = Mode::SearchInput;
             }
+            KeyCode::Char('n') => self.jump_match(1),
+            KeyCode::Char('N') => self.jump_match(-1),
+            KeyCode::Tab => self.jump_forward(),
+            KeyCode::Char('q') if self.can_quit() => return true,
             KeyCode::Char('B') => {
                 self.show_preview = !self.show_preview;
                 if !self.show_preview {
@@ -1626,7 +2012,7 @@ impl App {
             .len()
             .saturating_sub(height.max(1));
         let next = if delta.is_negative() {
-            self.scroll.saturating_sub(delta.unsigned_abs() as usize)
+            self.scroll.saturating_sub(delta.unsigned_abs())
         } else {
             (self.scroll + delta as usize).min(max_scroll)
         };
@@ -1666,7 +2052,7 @@ impl App {
     }
 
     fn insert_char(&mut self, c: char) {
-        self.rope.insert_char(self.cursor_char, c);
+        self.rope_insert(self.cursor_char, c.encode_utf8(&mut [0; 4]));
         self.cursor_char = self.cursor_char.saturating_add(1);
         self.preferred_col = None;
         if let Some(record) = self.insert_record.as_mut() {
@@ -1677,7 +2063,7 @@ impl App {
     }
 
     fn insert_str(&mut self, text: &str) {
-        self.rope.insert(self.cursor_char, text);
+        self.rope_insert(self.cursor_char, text);
         self.cursor_char = self.cursor_char.saturating_add(text.chars().count());
         self.preferred_col = None;
         if let Some(record) = self.insert_record.as_mut() {
@@ -1692,13 +2078,13 @@ impl App {
             return;
         }
         let prev = self.cursor_char - 1;
-        self.rope.remove(prev..self.cursor_char);
+        self.rope_remove(prev..self.cursor_char);
         self.cursor_char = prev;
         self.preferred_col = None;
-        if let Some(record) = self.insert_record.as_mut() {
-            if !record.is_empty() {
-                record.pop();
-            }
+        if let Some(record) = self.insert_record.as_mut()
+            && !record.is_empty()
+        {
+            record.pop();
         }
         self.mark_render_dirty();
         self.dirty = true;
@@ -1709,7 +2095,7 @@ impl App {
             return;
         }
         let next = self.cursor_char + 1;
-        self.rope.remove(self.cursor_char..next);
+        self.rope_remove(self.cursor_char..next);
         self.preferred_col = None;
         self.mark_render_dirty();
         self.dirty = true;
@@ -1736,7 +2122,7 @@ impl App {
         }
         let target_line = line - 1;
         let desired = self.preferred_col.unwrap_or(col);
-        let target_col = desired.min(line_len_chars(&self.rope, target_line));
+        let target_col = desired.min(line_len(&self.rope, target_line));
         self.cursor_char = self.rope.line_to_char(target_line) + target_col;
         self.preferred_col = Some(desired);
     }
@@ -1749,7 +2135,7 @@ impl App {
         }
         let target_line = line + 1;
         let desired = self.preferred_col.unwrap_or(col);
-        let target_col = desired.min(line_len_chars(&self.rope, target_line));
+        let target_col = desired.min(line_len(&self.rope, target_line));
         self.cursor_char = self.rope.line_to_char(target_line) + target_col;
         self.preferred_col = Some(desired);
     }
@@ -1758,12 +2144,12 @@ impl App {
         let (line, col) = self.cursor_line_col();
         let max_line = self.rope.len_lines().saturating_sub(1);
         let target_line = if delta.is_negative() {
-            line.saturating_sub(delta.unsigned_abs() as usize)
+            line.saturating_sub(delta.unsigned_abs())
         } else {
             (line + delta as usize).min(max_line)
         };
         let desired = self.preferred_col.unwrap_or(col);
-        let target_col = desired.min(line_len_chars(&self.rope, target_line));
+        let target_col = desired.min(line_len(&self.rope, target_line));
         self.cursor_char = self.rope.line_to_char(target_line) + target_col;
         self.preferred_col = Some(desired);
     }
@@ -1776,7 +2162,7 @@ impl App {
 
     fn move_cursor_line_end(&mut self) {
         let (line, _) = self.cursor_line_col();
-        let len = line_len_chars(&self.rope, line);
+        let len = line_len(&self.rope, line);
         self.cursor_char = self.rope.line_to_char(line) + len;
         self.preferred_col = None;
     }
@@ -1846,9 +2232,7 @@ impl App {
     fn ensure_rendered_cursor_visible(&mut self, height: u16) {
         let line = if let Some(line) = self.render_cursor_line {
             line
-        } else if let Some((line, _)) =
-            self.compute_rendered_cursor_line_col(self.approximate_rendered_line())
-        {
+        } else if let Some((line, _)) = self.compute_rendered_cursor_line_col() {
             self.render_cursor_line = Some(line);
             line
         } else {
@@ -1882,14 +2266,20 @@ impl App {
         if self.rendered.plain_lines.is_empty() {
             return;
         }
-        let anchor = self
-            .render_cursor_line
-            .unwrap_or_else(|| self.approximate_rendered_line());
-        if let Some((line, _)) = self.compute_rendered_cursor_line_col(anchor) {
+        if let Some((line, _)) = self.compute_rendered_cursor_line_col() {
             self.render_cursor_line = Some(line);
         }
     }
 
+    /// Scrolls the full-screen preview so `line` is on screen.
+    fn reveal_rendered_line(&mut self, line: usize, height: u16) {
+        let height = height as usize;
+        self.render_cursor_line = Some(line);
+        if line < self.scroll || line >= self.scroll + height {
+            self.scroll = line.saturating_sub(cursor_margin(height));
+        }
+    }
+
     fn render_cursor_from_scroll(&mut self) {
         if self.rendered.plain_lines.is_empty() {
             return;
@@ -1898,18 +2288,9 @@ impl App {
         self.render_cursor_line = Some(self.scroll.min(max));
     }
 
-    fn approximate_rendered_line(&self) -> usize {
-        if self.rendered.plain_lines.is_empty() {
-            return 0;
-        }
-        let src_total = self.rope.len_lines().max(1);
-        let src_line = self.rope.char_to_line(self.cursor_char).min(src_total - 1);
-        let ratio = src_line as f32 / src_total as f32;
-        let target = (ratio * self.rendered.plain_lines.len() as f32).round() as usize;
-        target.min(self.rendered.plain_lines.len().saturating_sub(1))
-    }
-
-    fn update_search_matches(&mut self) {
+    /// Finds the matches of the search query in its scope. An invalid pattern
+    /// leaves no matches and is returned for the caller to report.
+    fn update_search_matches(&mut self) -> Result<(), regex::Error> {
         self.search_matches.clear();
         self.search_match_map.clear();
         let query = self.search_query.trim();
@@ -1917,14 +2298,24 @@ impl App {
             self.search_dirty = false;
             self.current_match = 0;
             self.editor_cache_dirty = true;
-            return;
+            return Ok(());
         }
-        let needle = if self.config.search_case_sensitive {
-            query.to_string()
-        } else {
-            query.to_ascii_lowercase()
+        let regex = match search_regex(query, self.config.search_case_sensitive) {
+            Ok(regex) => regex,
+            Err(err) => {
+                self.search_dirty = false;
+                self.current_match = 0;
+                self.editor_cache_dirty = true;
+                return Err(err);
+            }
         };
+        if self.search_scope != SearchScope::Source {
+            self.collect_rendered_matches();
+        }
         for (line_idx, line) in self.rope.lines().enumerate() {
+            if self.search_scope != SearchScope::Source {
+                break;
+            }
             let mut text = line.to_string();
             if text.ends_with('\n') {
                 text.pop();
@@ -1932,33 +2323,13 @@ impl App {
                     text.pop();
                 }
             }
-            if text.is_empty() || needle.is_empty() {
-                continue;
-            }
-            let hay = if self.config.search_case_sensitive {
-                text.clone()
-            } else {
-                text.to_ascii_lowercase()
-            };
-            let mut cursor = 0usize;
-            while cursor < hay.len() {
-                if let Some(found) = hay[cursor..].find(&needle) {
-                    let start_byte = cursor + found;
-                    let end_byte = start_byte + needle.len();
-                    let start_char = hay[..start_byte].chars().count();
-                    let end_char = hay[..end_byte].chars().count();
-                    self.search_matches.push(RawMatch {
-                        line: line_idx,
-                        start: start_char,
-                    });
-                    self.search_match_map
-                        .entry(line_idx)
-                        .or_default()
-                        .push(start_char..end_char);
-                    cursor = end_byte;
-                } else {
-                    break;
-                }
+            for range in match_char_ranges(&regex, &text) {
+                self.search_matches.push(RawMatch {
+                    line: line_idx,
+                    start: range.start,
+                    rendered: None,
+                });
+                self.search_match_map.entry(line_idx).or_default().push(range);
             }
         }
         for ranges in self.search_match_map.values_mut() {
@@ -1969,16 +2340,63 @@ impl App {
         if self.current_match >= self.search_matches.len() {
             self.current_match = 0;
         }
+        Ok(())
     }
 
-    fn jump_to_match(&mut self, idx: usize) {
-        if let Some(m) = self.search_matches.get(idx) {
-            let line_start = self.rope.line_to_char(m.line);
-            self.cursor_char = line_start + m.start;
-            self.preferred_col = None;
-            self.ensure_cursor_visible(self.last_height.max(1));
-            if self.show_preview || self.show_outline {
-                self.update_render_cursor_line();
+    /// Maps the preview's matches inside the search scope back to the source,
+    /// so `n`/`N` move the editor cursor as they do for source matches.
+    fn collect_rendered_matches(&mut self) {
+        self.sync_render_from_rope();
+        let rendered = &self.rendered;
+        let in_scope = |line: usize| match self.search_scope {
+            SearchScope::Source | SearchScope::Rendered => true,
+            SearchScope::Headings => {
+                rendered.headings.iter().any(|h| (h.line..h.line + h.rows).contains(&line))
+            }
+            SearchScope::Code => rendered.code_blocks.iter().any(|lines| lines.contains(&line)),
+        };
+        let len_chars = self.rope.len_chars();
+        for m in rendered.matches.iter().filter(|m| in_scope(m.line)) {
+            let Some(start) = rendered.source_offset(m.line, m.start) else {
+                continue;
+            };
+            let start = start.min(len_chars);
+            let line = self.rope.char_to_line(start);
+            let line_start = self.rope.line_to_char(line);
+            self.search_matches.push(RawMatch {
+                line,
+                start: start - line_start,
+                rendered: Some(m.line),
+            });
+            let end = rendered
+                .source_offset(m.line, m.end.saturating_sub(1))
+                .map_or(start, |end| end + 1)
+                .min(len_chars);
+            let line_end = line_start + line_len(&self.rope, line);
+            if end > start && end <= line_end {
+                let range = start - line_start..end - line_start;
+                self.search_match_map.entry(line).or_default().push(range);
+            }
+        }
+    }
+
+    fn jump_to_match(&mut self, idx: usize) {
+        if let Some(m) = self.search_matches.get(idx) {
+            let line_start = self.rope.line_to_char(m.line);
+            self.cursor_char = line_start + m.start;
+            self.preferred_col = None;
+            if self.preview_full {
+                let line = m.rendered.or_else(|| {
+                    self.rendered.locate_source(self.cursor_char).map(|(line, _)| line)
+                });
+                if let Some(line) = line {
+                    self.reveal_rendered_line(line, self.last_height.max(1));
+                }
+                return;
+            }
+            self.ensure_cursor_visible(self.last_height.max(1));
+            if self.show_preview || self.show_outline {
+                self.update_render_cursor_line();
             }
             if self.show_preview {
                 self.ensure_rendered_cursor_visible(self.last_height.max(1));
@@ -1986,163 +2404,206 @@ impl App {
         }
     }
 
-    fn compute_rendered_cursor_line_col(&self, anchor: usize) -> Option<(usize, usize)> {
-        if self.rendered.plain_lines.is_empty() {
-            return None;
+    fn compute_rendered_cursor_line_col(&self) -> Option<(usize, usize)> {
+        self.rendered.locate_source(self.cursor_char)
+    }
+
+    fn move_cursor_to_rendered_line(&mut self, rendered_line: usize) {
+        let sources = &self.rendered.sources;
+        if rendered_line >= sources.len() {
+            return;
         }
-        let (src_line, src_col) = self.cursor_line_col();
-        let mut line_str = self.rope.line(src_line).to_string();
-        if line_str.ends_with('\n') {
-            line_str.pop();
-            if line_str.ends_with('\r') {
-                line_str.pop();
-            }
+        // Blank separator lines carry no source; use the nearest block after, then before.
+        let Some(line) = (rendered_line..sources.len())
+            .find(|&idx| sources[idx].is_some())
+            .or_else(|| (0..rendered_line).rev().find(|&idx| sources[idx].is_some()))
+        else {
+            return;
+        };
+        if let Some(offset) = self.rendered.source_offset(line, 0) {
+            self.cursor_char = offset.min(self.rope.len_chars().saturating_sub(1));
+            self.preferred_col = None;
+            self.render_cursor_line = Some(rendered_line);
         }
+    }
 
-        let normalized = normalize_markdown_line(&line_str);
-        let visible_col = normalize_prefix_for_col(&line_str, src_col);
-        let anchor = anchor.min(self.rendered.plain_lines.len().saturating_sub(1));
-        if normalized.trim().is_empty() {
-            return Some((self.approximate_rendered_line(), 0));
+    fn save_buffer(&mut self) {
+        let text = self.rope.to_string();
+        if let Err(err) = fs::write(&self.file_path, &text) {
+            self.status = Some(format!("Save failed: {err}"));
+            return;
         }
+        self.source = text;
+        self.render_cursor_line = None;
+        self.dirty = false;
+        self.suppress_reload_until = Some(Instant::now() + Duration::from_millis(300));
+        self.status = Some("Saved".to_string());
+        self.persist_undo();
+    }
 
-        let candidates = build_match_candidates(&normalized, visible_col);
-        if let Some((line, match_col)) = self.find_best_rendered_match(&candidates, anchor) {
-            let line_len = self
-                .rendered
-                .plain_lines
-                .get(line)
-                .map(|l| l.chars().count())
-                .unwrap_or(0);
-            let col = match_col.unwrap_or(visible_col).min(line_len);
-            return Some((line, col));
+    fn write_copy(&mut self, path: &Path) {
+        if !self.read_only && path == self.file_path {
+            self.save_buffer();
+            return;
+        }
+        let text = self.rope.to_string();
+        match fs::write(path, &text) {
+            Ok(()) => self.status = Some(format!("Wrote {}", path.display())),
+            Err(err) => self.status = Some(format!("Save failed: {err}")),
         }
-
-        let fallback = self.approximate_rendered_line();
-        let line_len = self
-            .rendered
-            .plain_lines
-            .get(fallback)
-            .map(|l| l.chars().count())
-            .unwrap_or(0);
-        Some((fallback, visible_col.min(line_len)))
     }
 
-    fn find_best_rendered_match(
-        &self,
-        candidates: &[String],
-        anchor: usize,
-    ) -> Option<(usize, Option<usize>)> {
-        if candidates.is_empty() {
-            return None;
-        }
-        let total = self.rendered.plain_lines.len();
-        if total == 0 {
-            return None;
+    /// Runs `:s` over `lines` (inclusive, 0-based). Without the `c` flag every
+    /// replacement lands at once as a single undo step.
+    fn substitute(&mut self, sub: &Substitute, lines: LineRange) {
+        if self.read_only {
+            self.deny_read_only();
+            return;
         }
-        let anchor = anchor.min(total - 1);
-        let window = 200usize;
-        let ranges = [(
-            anchor.saturating_sub(window),
-            (anchor + window + 1).min(total),
-        )];
-
-        let mut best: Option<(usize, i64, Option<usize>)> = None;
-        for (start, end) in ranges {
-            for idx in start..end {
-                let line = &self.rendered.plain_lines[idx];
-                if let Some((len, col)) = match_line(line, candidates) {
-                    let dist = if idx > anchor {
-                        idx - anchor
-                    } else {
-                        anchor - idx
-                    };
-                    let score = dist as i64 * 1000 - len as i64;
-                    let replace = match best {
-                        Some((_, best_score, _)) => score < best_score,
-                        None => true,
-                    };
-                    if replace {
-                        best = Some((idx, score, col));
-                    }
-                }
+        let pattern = if sub.pattern.is_empty() {
+            if self.search_query.is_empty() {
+                self.status = Some("No previous search pattern".to_string());
+                return;
             }
-            if best.is_some() {
-                break;
+            self.search_query.clone()
+        } else {
+            sub.pattern.clone()
+        };
+        let case_sensitive = self.config.search_case_sensitive;
+        let regex = match sub.case {
+            Some(true) => search_regex(&format!("\\C{pattern}"), case_sensitive),
+            Some(false) => search_regex(&format!("\\c{pattern}"), case_sensitive),
+            None => search_regex(&pattern, case_sensitive),
+        };
+        let regex = match regex {
+            Ok(regex) => regex,
+            Err(err) => {
+                let err = err.to_string();
+                let reason = err.lines().last().unwrap_or_default();
+                let reason = reason.trim_start_matches("error: ");
+                self.status = Some(format!("Invalid pattern: {reason}"));
+                return;
             }
+        };
+        let template = replacement_template(&sub.replacement);
+        let edits = substitute_edits(&self.rope, &regex, &template, sub.global, lines);
+        if edits.is_empty() {
+            self.status = Some(format!("Pattern not found: {pattern}"));
+            return;
         }
-        best.map(|(idx, _score, col)| (idx, col))
-    }
 
-    fn move_cursor_to_rendered_line(&mut self, rendered_line: usize) {
-        let Some(text) = self.rendered.plain_lines.get(rendered_line) else {
-            return;
+        let mut pending = PendingSubstitute {
+            replacement: sub.replacement.clone(),
+            edits,
+            next: 0,
+            shift: 0,
+            replaced: 0,
+            lines: 0,
+            last_line: None,
         };
-        let anchor = self.rope.char_to_line(self.cursor_char);
-        if let Some(src_line) = self.find_source_line_for_text(text, anchor) {
-            self.cursor_char = self.rope.line_to_char(src_line);
+        if sub.confirm {
+            self.cursor_char = pending.edits[0].start;
             self.preferred_col = None;
-            self.render_cursor_line = Some(rendered_line);
+            self.substitute = Some(pending);
+            self.mode = Mode::ConfirmSubstitute;
+            self.status = None;
+            return;
         }
-    }
-
-    fn find_source_line_for_text(&self, text: &str, anchor: usize) -> Option<usize> {
-        let trimmed = text.trim();
-        if trimmed.is_empty() {
-            return None;
+        self.push_undo();
+        for edit in pending.edits.iter().rev() {
+            self.undo.replace(&mut self.rope, edit.start..edit.end, &edit.text);
+            let inserted = edit.text.chars().count();
+            self.note_edit(TextEdit::new(edit.start, edit.end - edit.start, inserted));
         }
-        let candidates = build_match_candidates(trimmed, 0);
-        if candidates.is_empty() {
-            return None;
+        pending.replaced = pending.edits.len();
+        for edit in &pending.edits {
+            if pending.last_line != Some(edit.line) {
+                pending.lines += 1;
+                pending.last_line = Some(edit.line);
+            }
         }
+        self.finish_substitute(pending);
+    }
 
-        let total = self.rope.len_lines();
-        if total == 0 {
-            return None;
+    fn handle_substitute_confirm(&mut self, key: KeyEvent) -> bool {
+        let Some(mut pending) = self.substitute.take() else {
+            self.mode = Mode::Normal;
+            return false;
+        };
+        let (replace, stop) = match key.code {
+            KeyCode::Char('y') => (1, false),
+            KeyCode::Char('l') => (1, true),
+            KeyCode::Char('a') => (pending.edits.len() - pending.next, true),
+            KeyCode::Char('n') => (0, false),
+            KeyCode::Char('q') | KeyCode::Esc => (0, true),
+            _ => {
+                self.substitute = Some(pending);
+                return false;
+            }
+        };
+        for _ in 0..replace {
+            let Some((start, end)) = pending.current() else {
+                break;
+            };
+            let edit = &pending.edits[pending.next];
+            if pending.replaced == 0 {
+                self.push_undo();
+            }
+            self.undo.replace(&mut self.rope, start..end, &edit.text);
+            let inserted = edit.text.chars().count();
+            self.note_edit(TextEdit::new(start, end - start, inserted));
+            pending.shift += inserted as isize - (end - start) as isize;
+            pending.replaced += 1;
+            if pending.last_line != Some(edit.line) {
+                pending.lines += 1;
+                pending.last_line = Some(edit.line);
+            }
+            pending.next += 1;
+            self.mark_render_dirty();
         }
-        let anchor = anchor.min(total.saturating_sub(1));
-        let mut best: Option<(usize, i64)> = None;
-
-        for line_idx in 0..total {
-            let mut line_str = self.rope.line(line_idx).to_string();
-            if line_str.ends_with('\n') {
-                line_str.pop();
-                if line_str.ends_with('\r') {
-                    line_str.pop();
-                }
+        if replace == 0 {
+            pending.next += 1;
+        }
+        match pending.current() {
+            Some((start, _)) if !stop => {
+                self.cursor_char = start;
+                self.preferred_col = None;
+                self.substitute = Some(pending);
+                self.ensure_cursor_visible(self.last_height.max(1));
             }
-            let normalized = normalize_markdown_line(&line_str);
-            if let Some((len, _)) = match_line(&normalized, &candidates) {
-                let dist = if line_idx > anchor {
-                    line_idx - anchor
-                } else {
-                    anchor - line_idx
-                };
-                let score = dist as i64 * 1000 - len as i64;
-                let replace = match best {
-                    Some((_, best_score)) => score < best_score,
-                    None => true,
-                };
-                if replace {
-                    best = Some((line_idx, score));
-                }
+            _ => {
+                self.mode = Mode::Normal;
+                self.finish_substitute(pending);
             }
         }
-
-        best.map(|(idx, _)| idx)
+        false
     }
 
-    fn save_buffer(&mut self) {
-        let text = self.rope.to_string();
-        if let Err(err) = fs::write(&self.file_path, &text) {
-            self.status = Some(format!("Save failed: {err}"));
+    fn finish_substitute(&mut self, pending: PendingSubstitute) {
+        let Some(line) = pending.last_line else {
+            self.status = Some("No substitutions".to_string());
             return;
-        }
-        self.source = text;
-        self.render_cursor_line = None;
-        self.dirty = false;
-        self.suppress_reload_until = Some(Instant::now() + Duration::from_millis(300));
-        self.status = Some("Saved".to_string());
+        };
+        self.cursor_char = self.rope.line_to_char(line.min(last_line(&self.rope)));
+        self.move_cursor_first_non_ws();
+        self.mark_render_dirty();
+        self.update_dirty();
+        let plural = |n: usize, word: &str| {
+            if n == 1 {
+                format!("1 {word}")
+            } else {
+                format!("{n} {word}s")
+            }
+        };
+        self.status = Some(format!(
+            "{} on {}",
+            plural(pending.replaced, "substitution"),
+            plural(pending.lines, "line")
+        ));
+    }
+
+    fn deny_read_only(&mut self) {
+        self.status = Some("Read-only: use :w <path> to save a copy".to_string());
     }
 
     fn execute_command(&mut self, command: &str) -> bool {
@@ -2152,6 +2613,68 @@ impl App {
             return false;
         }
 
+        let current = self.rope.char_to_line(self.cursor_char);
+        let last = last_line(&self.rope);
+        let (range, rest) = match parse_line_range(cmd, current, last, self.visual_lines) {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                self.mode = Mode::Normal;
+                self.status = Some(err);
+                return false;
+            }
+        };
+        if let Some(parsed) = parse_substitute(rest) {
+            self.mode = Mode::Normal;
+            match parsed {
+                Ok(sub) => self.substitute(&sub, range.unwrap_or((current, current))),
+                Err(err) => self.status = Some(err),
+            }
+            return false;
+        }
+
+        if let Some(rest) = cmd.strip_prefix("w ").or_else(|| cmd.strip_prefix("write ")) {
+            self.mode = Mode::Normal;
+            let target = rest.trim();
+            if target.is_empty() {
+                self.status = Some("Usage: :w <path>".to_string());
+            } else {
+                self.write_copy(Path::new(target));
+            }
+            return false;
+        }
+
+        if let Some(rest) = cmd.strip_prefix("e ").or_else(|| cmd.strip_prefix("edit ")) {
+            self.mode = Mode::Normal;
+            let target = rest.trim();
+            if target.is_empty() {
+                self.status = Some("Usage: :e <path>".to_string());
+            } else {
+                self.open_buffer(expand_user_path(target));
+            }
+            return false;
+        }
+
+        if let Some((name, arg)) = cmd.split_once(' ')
+            && matches!(name, "b" | "buffer" | "bd" | "bdelete" | "bd!" | "bdelete!")
+        {
+            self.mode = Mode::Normal;
+            let Some(idx) = arg
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .filter(|n| (1..=self.buffer_count()).contains(n))
+            else {
+                self.status = Some(format!("No such buffer: {}", arg.trim()));
+                return false;
+            };
+            if name.starts_with("bd") {
+                self.delete_buffer(idx - 1, name.ends_with('!'));
+            } else {
+                self.switch_buffer(idx - 1);
+            }
+            return false;
+        }
+
         if let Some(rest) = cmd.strip_prefix("anchor ") {
             self.mode = Mode::Normal;
             let target = rest.trim();
@@ -2170,37 +2693,33 @@ impl App {
                 self.request_discover = true;
                 return true;
             }
-            if target.contains('#') {
-                self.jump_to_anchor_target(target);
-            } else {
-                self.status = Some(
-                    "Use :open for discover or :open #anchor for in-document navigation"
-                        .to_string(),
-                );
-            }
+            self.follow_link(target);
             return false;
         }
 
         match cmd {
+            "w" | "write" | "w!" if self.read_only => {
+                self.deny_read_only();
+                self.mode = Mode::Normal;
+            }
             "w" | "write" | "w!" => {
                 self.save_buffer();
                 self.mode = Mode::Normal;
             }
             "wq" | "x" => {
-                self.save_buffer();
-                if !self.dirty {
+                if !self.read_only {
+                    self.save_buffer();
+                }
+                if self.can_quit() {
                     return true;
-                } else {
-                    self.mode = Mode::Normal;
                 }
+                self.mode = Mode::Normal;
             }
             "q" | "quit" => {
-                if self.dirty {
-                    self.status = Some("No write since last change (add ! to override)".to_string());
-                    self.mode = Mode::Normal;
-                } else {
+                if self.can_quit() {
                     return true;
                 }
+                self.mode = Mode::Normal;
             }
             "q!" | "quit!" => {
                 self.discard_changes();
@@ -2210,6 +2729,31 @@ impl App {
                 self.status = Some("Usage: :anchor #section".to_string());
                 self.mode = Mode::Normal;
             }
+            "e" | "edit" | "e!" | "edit!" => {
+                self.mode = Mode::Normal;
+                if self.read_only {
+                    self.status = Some("Nothing to reload (stdin)".to_string());
+                } else if self.dirty && !cmd.ends_with('!') {
+                    self.status = Some("No write since last change (add ! to override)".to_string());
+                } else {
+                    self.dirty = false;
+                    self.reload_file();
+                }
+            }
+            "bn" | "bnext" => {
+                self.mode = Mode::Normal;
+                self.cycle_buffer(1);
+            }
+            "bp" | "bprevious" | "bN" | "bNext" => {
+                self.mode = Mode::Normal;
+                self.cycle_buffer(-1);
+            }
+            "bd" | "bdelete" | "bd!" | "bdelete!" => {
+                self.mode = Mode::Normal;
+                self.delete_buffer(self.active_buffer, cmd.ends_with('!'));
+            }
+            "ls" | "buffers" => self.open_buffer_picker(),
+            "theme" | "colorscheme" => self.open_theme_picker(),
             "discover" | "files" | "open" => {
                 self.request_discover = true;
                 return true;
@@ -2224,10 +2768,80 @@ impl App {
 
     fn clear_pending(&mut self) {
         self.pending_op = None;
+        self.pending_motion = None;
+        self.op_count = None;
         self.count = None;
         self.pending_register = None;
         self.register_waiting = false;
+        self.macro_waiting = None;
         self.replace_pending = false;
+        self.pending_g = false;
+    }
+
+    /// `q{reg}`: records keys into `reg`, appending for an uppercase name.
+    fn start_recording(&mut self, reg: char) {
+        if !reg.is_ascii_alphanumeric() {
+            self.status = Some(format!("Invalid register: {reg}"));
+            return;
+        }
+        let mut keys = Vec::new();
+        if reg.is_ascii_uppercase()
+            && let Some(existing) = self.registers.get(&reg.to_ascii_lowercase())
+        {
+            keys = keys::decode(&existing.text);
+        }
+        self.recording = Some((reg.to_ascii_lowercase(), keys));
+        self.status = Some(format!("recording @{}", reg.to_ascii_lowercase()));
+    }
+
+    fn stop_recording(&mut self) {
+        if let Some((reg, keys)) = self.recording.take() {
+            let text = keys::encode(&keys);
+            self.registers.insert(reg, Register { text, linewise: false });
+            self.status = None;
+        }
+    }
+
+    /// `@{reg}` / `@@`: replays the keys in `reg` through `handle_key`,
+    /// `count` times. Returns true when the macro quits.
+    fn play_macro(&mut self, reg: char, count: usize, content_height: u16) -> bool {
+        let reg = match reg {
+            '@' => match self.last_macro {
+                Some(reg) => reg,
+                None => {
+                    self.status = Some("No previous macro".to_string());
+                    return false;
+                }
+            },
+            reg => reg.to_ascii_lowercase(),
+        };
+        if self.macro_depth >= MAX_MACRO_DEPTH {
+            self.status = Some("Macro recursion too deep".to_string());
+            return false;
+        }
+        let Some(register) = self.registers.get(&reg) else {
+            self.status = Some(format!("Register {reg} is empty"));
+            return false;
+        };
+        // A line yanked to edit the macro replays without its newline.
+        let text = match register.text.strip_suffix('\n') {
+            Some(text) if register.linewise => text,
+            _ => register.text.as_str(),
+        };
+        let keys = keys::decode(text);
+        self.last_macro = Some(reg);
+        self.macro_depth += 1;
+        let mut quit = false;
+        'replay: for _ in 0..count {
+            for key in &keys {
+                if self.handle_key(*key, content_height) {
+                    quit = true;
+                    break 'replay;
+                }
+            }
+        }
+        self.macro_depth -= 1;
+        quit
     }
 
     fn push_count(&mut self, digit: usize) {
@@ -2241,6 +2855,14 @@ impl App {
         count
     }
 
+    /// The count typed before an operator times the one typed before its motion.
+    fn take_operator_count(&mut self) -> Option<usize> {
+        match (self.op_count.take(), self.count.take()) {
+            (None, None) => None,
+            (before, after) => Some(before.unwrap_or(1) * after.unwrap_or(1)),
+        }
+    }
+
     fn consume_register_wait(&mut self, key: KeyEvent) -> bool {
         if !self.register_waiting {
             return false;
@@ -2252,13 +2874,25 @@ impl App {
         true
     }
 
+    /// The register named with `"x`, or the default one: `+` with
+    /// `clipboard = "unnamedplus"`, `*` with `"unnamed"`, `"` otherwise.
     fn consume_active_register(&mut self) -> char {
-        self.pending_register.take().unwrap_or('"')
+        self.pending_register.take().unwrap_or(match self.config.clipboard.as_str() {
+            "unnamedplus" => '+',
+            "unnamed" => '*',
+            _ => '"',
+        })
     }
 
     fn set_register(&mut self, text: String, linewise: bool, is_yank: bool) {
         let reg = Register { text: text.clone(), linewise };
         let target = self.consume_active_register();
+        if self.system_clipboard
+            && matches!(target, '+' | '*')
+            && let Err(err) = clipboard::copy(&text, target == '*')
+        {
+            self.status = Some(format!("Clipboard: {err}"));
+        }
         self.registers.insert(target, reg.clone());
         self.registers.insert('"', reg.clone());
         if is_yank {
@@ -2266,9 +2900,32 @@ impl App {
         }
     }
 
+    /// Contents of the register to paste from. `+` and `*` read the system
+    /// clipboard, keeping the linewise flag when it still holds our own yank.
+    fn read_register(&mut self) -> Option<Register> {
+        let name = self.consume_active_register();
+        if self.system_clipboard
+            && matches!(name, '+' | '*')
+            && let Some(text) = clipboard::paste(name == '*')
+        {
+            let linewise = match self.registers.get(&name) {
+                Some(reg) if reg.text == text => reg.linewise,
+                _ => text.ends_with('\n'),
+            };
+            return Some(Register { text, linewise });
+        }
+        self.registers
+            .get(&name)
+            .cloned()
+            .or_else(|| self.registers.get(&'"').cloned())
+    }
+
     fn enter_insert_mode(&mut self) {
         if self.mode != Mode::Insert {
-            self.push_undo();
+            // `o`, `cw` and friends already started the change this insert finishes.
+            if !self.undo.in_change() {
+                self.push_undo();
+            }
             self.insert_record = Some(String::new());
         }
         self.mode = Mode::Insert;
@@ -2282,8 +2939,17 @@ impl App {
             self.cursor_char = self.cursor_char.saturating_sub(1);
         }
         let pending_change = self.pending_change_lines.take();
-        if let Some(record) = self.insert_record.take() {
-            if !record.is_empty() {
+        let pending_operator = self.pending_change_op.take();
+        match (self.insert_record.take(), pending_operator) {
+            (Some(insert), Some((target, count))) => {
+                self.last_change = Some(LastChange::Operator {
+                    op: PendingOp::Change,
+                    target,
+                    count,
+                    insert,
+                });
+            }
+            (Some(record), None) if !record.is_empty() => {
                 if let Some(count) = pending_change {
                     self.last_change = Some(LastChange::ChangeLines {
                         insert: record,
@@ -2293,6 +2959,7 @@ impl App {
                     self.last_change = Some(LastChange::Insert(record));
                 }
             }
+            _ => {}
         }
         self.mode = Mode::Normal;
         self.status = Some("NORMAL".to_string());
@@ -2323,37 +2990,68 @@ impl App {
     }
 
     fn push_undo(&mut self) {
-        self.undo_stack.push(self.rope.clone());
-        self.redo_stack.clear();
+        self.undo.begin(&self.rope, self.cursor_char);
     }
 
     fn undo(&mut self) {
-        if let Some(prev) = self.undo_stack.pop() {
-            self.redo_stack.push(self.rope.clone());
-            self.rope = prev;
-            self.cursor_char = self.cursor_char.min(self.rope.len_chars());
-            self.mark_render_dirty();
-            self.update_dirty();
+        match self.undo.undo(&mut self.rope) {
+            Some(cursor) => self.restore_undo_cursor(cursor),
+            None => self.status = Some("Already at oldest change".to_string()),
         }
     }
 
     fn redo(&mut self) {
-        if let Some(next) = self.redo_stack.pop() {
-            self.undo_stack.push(self.rope.clone());
-            self.rope = next;
-            self.cursor_char = self.cursor_char.min(self.rope.len_chars());
-            self.mark_render_dirty();
-            self.update_dirty();
+        match self.undo.redo(&mut self.rope) {
+            Some(cursor) => self.restore_undo_cursor(cursor),
+            None => self.status = Some("Already at newest change".to_string()),
+        }
+    }
+
+    /// `g-` / `g+`: steps to the text as it was one change earlier or later
+    /// in time, crossing undo branches.
+    fn undo_in_time(&mut self, back: bool) {
+        match self.undo.step_in_time(&mut self.rope, back) {
+            Some(cursor) => {
+                self.restore_undo_cursor(cursor);
+                self.status = Some(format!("Change {}", self.undo.current()));
+            }
+            None if back => self.status = Some("Already at oldest change".to_string()),
+            None => self.status = Some("Already at newest change".to_string()),
+        }
+    }
+
+    fn restore_undo_cursor(&mut self, cursor: usize) {
+        self.cursor_char = cursor.min(self.rope.len_chars());
+        self.preferred_col = None;
+        self.forget_edit();
+        self.update_dirty();
+    }
+
+    /// Saves the undo history next to the config when `persist_undo` is on.
+    /// Called whenever the buffer matches the file on disk.
+    fn persist_undo(&mut self) {
+        if !self.remember_history || !self.config.persist_undo || self.read_only {
+            return;
+        }
+        let max_bytes = self.config.undo_file_max_kb.saturating_mul(1024);
+        if let Err(err) = self.undo.save(&self.file_path, &self.rope, max_bytes) {
+            self.status = Some(format!("Undo history not saved: {err}"));
+        }
+    }
+
+    fn load_undo(&mut self) {
+        if self.remember_history && self.config.persist_undo && !self.read_only {
+            self.undo = UndoTree::load(&self.file_path, &self.rope, self.config.undo_levels);
         }
     }
 
     fn update_dirty(&mut self) {
-        self.dirty = self.rope.to_string() != self.source;
+        self.dirty = self.rope != self.source;
     }
 
     fn is_at_line_end(&self) -> bool {
         let (line, col) = self.cursor_line_col();
-        col >= line_len_chars(&self.rope, line)
+        col >= line_len(&self.rope, line)
     }
 
     fn move_cursor_first_non_ws(&mut self) {
@@ -2381,7 +3079,7 @@ impl App {
             self.rope.line_to_char(line + 1)
         };
         self.push_undo();
-        self.rope.insert(insert_at, "\n");
+        self.rope_insert(insert_at, "\n");
         self.cursor_char = insert_at + 1;
         self.mark_render_dirty();
         self.dirty = true;
@@ -2391,7 +3089,7 @@ impl App {
         let line = self.rope.char_to_line(self.cursor_char);
         let insert_at = self.rope.line_to_char(line);
         self.push_undo();
-        self.rope.insert(insert_at, "\n");
+        self.rope_insert(insert_at, "\n");
         self.cursor_char = insert_at;
         self.mark_render_dirty();
         self.dirty = true;
@@ -2405,7 +3103,7 @@ impl App {
         self.push_undo();
         let text = self.rope.slice(self.cursor_char..end).to_string();
         self.set_register(text.clone(), false, false);
-        self.rope.remove(self.cursor_char..end);
+        self.rope_remove(self.cursor_char..end);
         self.last_change = Some(LastChange::DeleteChars(count));
         self.mark_render_dirty();
         self.dirty = true;
@@ -2432,7 +3130,7 @@ impl App {
         self.push_undo();
         let text = self.rope.slice(start..end).to_string();
         self.set_register(text, true, false);
-        self.rope.remove(start..end);
+        self.rope_remove(start..end);
         self.cursor_char = self.rope.line_to_char(line.min(self.rope.len_lines().saturating_sub(1)));
         self.last_change = Some(LastChange::DeleteLines(count));
         self.mark_render_dirty();
@@ -2454,14 +3152,137 @@ impl App {
         self.enter_insert_mode();
     }
 
-    fn paste_after(&mut self, count: usize) {
-        let reg_char = self.consume_active_register();
-        let reg = match self
-            .registers
-            .get(&reg_char)
-            .cloned()
-            .or_else(|| self.registers.get(&'"').cloned())
+    fn apply_operator(&mut self, op: PendingOp, target: OperatorTarget) {
+        let count = self.take_operator_count();
+        if !self.run_operator(op, target, count) {
+            self.clear_pending();
+        }
+    }
+
+    /// Runs `op` over `target` from the cursor. Returns false when the target
+    /// does not exist here, e.g. `f` finds no char or there is no link to pick.
+    fn run_operator(
+        &mut self,
+        op: PendingOp,
+        target: OperatorTarget,
+        count: Option<usize>,
+    ) -> bool {
+        // Like Vim, `cw` on a word changes to its end and keeps the blank after it.
+        let target = match (op, target) {
+            (PendingOp::Change, OperatorTarget::Motion(Motion::WordForward { big }))
+                if self
+                    .rope
+                    .get_char(self.cursor_char)
+                    .is_some_and(|c| !c.is_whitespace()) =>
+            {
+                OperatorTarget::Motion(Motion::WordEnd { big })
+            }
+            _ => target,
+        };
+        let Some((start, end, linewise)) = self.operator_range(target, count) else {
+            return false;
+        };
+        if start == end && op != PendingOp::Change {
+            return false;
+        }
+        let text = self.rope.slice(start..end).to_string();
+        match op {
+            PendingOp::Yank => {
+                let lines = text.lines().count();
+                self.set_register(text, linewise, true);
+                if !linewise || self.rope.char_to_line(start) < self.cursor_line_col().0 {
+                    self.cursor_char = start;
+                }
+                if linewise {
+                    self.status = Some(format!("Yanked {lines} line(s)"));
+                }
+            }
+            PendingOp::Delete => {
+                self.push_undo();
+                self.set_register(text, linewise, false);
+                self.rope_remove(start..end);
+                self.cursor_char = start.min(self.rope.len_chars());
+                self.last_change = Some(LastChange::Operator {
+                    op,
+                    target,
+                    count,
+                    insert: String::new(),
+                });
+                self.mark_render_dirty();
+                self.dirty = true;
+            }
+            PendingOp::Change => {
+                let keep_newline = linewise && text.ends_with('\n');
+                self.set_register(text, linewise, false);
+                self.enter_insert_mode();
+                let end = if keep_newline { end - 1 } else { end };
+                self.rope_remove(start..end);
+                self.cursor_char = start;
+                self.pending_change_op = Some((target, count));
+                self.mark_render_dirty();
+                self.dirty = true;
+            }
+        }
+        self.preferred_col = None;
+        true
+    }
+
+    /// The span an operator covers as (start, end, linewise), following Vim's
+    /// rules for exclusive motions that end at the start of a line.
+    fn operator_range(
+        &self,
+        target: OperatorTarget,
+        count: Option<usize>,
+    ) -> Option<(usize, usize, bool)> {
+        let rope = &self.rope;
+        let cursor = self.cursor_char.min(rope.len_chars());
+        let motion = match target {
+            OperatorTarget::Motion(motion) => motion,
+            OperatorTarget::Object { object, inner } => {
+                let mut selection = object.select(rope, cursor, inner)?;
+                if matches!(object, TextObject::Word { .. } | TextObject::Paragraph) {
+                    for _ in 1..count.unwrap_or(1) {
+                        match object.select(rope, selection.end, inner) {
+                            Some(next) if next.end > selection.end => selection.end = next.end,
+                            _ => break,
+                        }
+                    }
+                }
+                return Some((selection.start, selection.end, selection.linewise));
+            }
+        };
+        let target = motion.apply(rope, cursor, count)?;
+        let (start, mut end) = (cursor.min(target), cursor.max(target));
+        let start_line = rope.char_to_line(start);
+        let mut end_line = rope.char_to_line(end);
+        match motion.kind() {
+            MotionKind::Linewise => {
+                let (start, end) = self.line_range(start_line, end_line - start_line + 1);
+                return Some((start, end, true));
+            }
+            MotionKind::Inclusive => return Some((start, (end + 1).min(rope.len_chars()), false)),
+            MotionKind::Exclusive => {}
+        }
+        if let Motion::WordForward { .. } = motion
+            && end_line > start_line
+            && rope.slice(rope.line_to_char(end_line)..end).chars().all(char::is_whitespace)
         {
+            // `dw` on the last word of a line stops at the line end.
+            end = rope.line_to_char(end_line);
+        }
+        if end_line > start_line && end == rope.line_to_char(end_line) {
+            end_line -= 1;
+            if start <= motion::first_non_blank(rope, start_line) {
+                let (start, end) = self.line_range(start_line, end_line - start_line + 1);
+                return Some((start, end, true));
+            }
+            end = rope.line_to_char(end_line) + line_len(rope, end_line);
+        }
+        Some((start, end, false))
+    }
+
+    fn paste_after(&mut self, count: usize) {
+        let reg = match self.read_register() {
             Some(r) => r,
             None => return,
         };
@@ -2477,13 +3298,13 @@ impl App {
                 self.rope.line_to_char(line + 1)
             };
             for _ in 0..count {
-                self.rope.insert(insert_at, &reg.text);
+                self.rope_insert(insert_at, &reg.text);
             }
             self.cursor_char = insert_at;
         } else {
             let mut insert_at = (self.cursor_char + 1).min(self.rope.len_chars());
             for _ in 0..count {
-                self.rope.insert(insert_at, &reg.text);
+                self.rope_insert(insert_at, &reg.text);
                 insert_at += reg.text.chars().count();
             }
             self.cursor_char = insert_at.saturating_sub(1);
@@ -2497,13 +3318,7 @@ impl App {
     }
 
     fn paste_before(&mut self, count: usize) {
-        let reg_char = self.consume_active_register();
-        let reg = match self
-            .registers
-            .get(&reg_char)
-            .cloned()
-            .or_else(|| self.registers.get(&'"').cloned())
-        {
+        let reg = match self.read_register() {
             Some(r) => r,
             None => return,
         };
@@ -2515,13 +3330,13 @@ impl App {
             let line = self.rope.char_to_line(self.cursor_char);
             let insert_at = self.rope.line_to_char(line);
             for _ in 0..count {
-                self.rope.insert(insert_at, &reg.text);
+                self.rope_insert(insert_at, &reg.text);
             }
             self.cursor_char = insert_at;
         } else {
             let mut insert_at = self.cursor_char;
             for _ in 0..count {
-                self.rope.insert(insert_at, &reg.text);
+                self.rope_insert(insert_at, &reg.text);
                 insert_at += reg.text.chars().count();
             }
             self.cursor_char = insert_at.saturating_sub(1);
@@ -2535,6 +3350,10 @@ impl App {
     }
 
     fn selection_range(&self) -> Option<(usize, usize, bool)> {
+        if matches!(self.mode, Mode::ConfirmSubstitute) {
+            let (start, end) = self.substitute.as_ref()?.current()?;
+            return Some((start, end, false));
+        }
         let anchor = self.visual_anchor?;
         let cursor = self.cursor_char;
         if matches!(self.mode, Mode::VisualLine) {
@@ -2570,7 +3389,7 @@ impl App {
             };
             let char_count = if linewise { 0 } else { end.saturating_sub(start).max(1) };
             self.set_register(text, linewise, false);
-            self.rope.remove(start..end);
+            self.rope_remove(start..end);
             self.cursor_char = start.min(self.rope.len_chars());
             if linewise {
                 self.last_change = Some(LastChange::DeleteLines(line_count.max(1)));
@@ -2587,8 +3406,8 @@ impl App {
             return;
         }
         self.push_undo();
-        self.rope.remove(self.cursor_char..self.cursor_char + 1);
-        self.rope.insert_char(self.cursor_char, c);
+        self.rope_remove(self.cursor_char..self.cursor_char + 1);
+        self.rope_insert(self.cursor_char, c.encode_utf8(&mut [0; 4]));
         self.last_change = Some(LastChange::ReplaceChar(c));
         self.mark_render_dirty();
         self.dirty = true;
@@ -2606,7 +3425,7 @@ impl App {
                 }
                 self.push_undo();
                 let insert_at = (self.cursor_char + 1).min(self.rope.len_chars());
-                self.rope.insert(insert_at, &text);
+                self.rope_insert(insert_at, &text);
                 self.cursor_char = insert_at + text.chars().count().saturating_sub(1);
                 self.mark_render_dirty();
                 self.dirty = true;
@@ -2631,13 +3450,26 @@ impl App {
             LastChange::ReplaceChar(c) => {
                 self.replace_char(c);
             }
+            LastChange::Operator {
+                op,
+                target,
+                count,
+                insert,
+            } => {
+                let count = self.count.take().or(count);
+                if !self.run_operator(op, target, count) || op != PendingOp::Change {
+                    return;
+                }
+                self.insert_str(&insert);
+                self.exit_insert_mode();
+            }
             LastChange::ChangeLines { insert, count } => {
                 self.delete_lines(count);
                 if insert.is_empty() {
                     return;
                 }
-                self.rope.insert(self.cursor_char, &insert);
-                self.cursor_char = self.cursor_char + insert.chars().count().saturating_sub(1);
+                self.rope_insert(self.cursor_char, &insert);
+                self.cursor_char += insert.chars().count().saturating_sub(1);
                 self.last_change = Some(LastChange::ChangeLines { insert, count });
                 self.mark_render_dirty();
                 self.dirty = true;
@@ -2648,10 +3480,545 @@ impl App {
     fn discard_changes(&mut self) {
         self.rope = Rope::from_str(&self.source);
         self.cursor_char = self.cursor_char.min(self.rope.len_chars());
-        self.mark_render_dirty();
+        self.forget_edit();
+        self.dirty = false;
+    }
+
+    fn park_buffer(&mut self) -> Buffer {
+        Buffer {
+            file_path: std::mem::take(&mut self.file_path),
+            source: std::mem::take(&mut self.source),
+            rope: std::mem::take(&mut self.rope),
+            parsed: std::mem::take(&mut self.parsed),
+            rendered: std::mem::take(&mut self.rendered),
+            theme: self.config.theme.clone(),
+            scroll: self.scroll,
+            edit_scroll: self.edit_scroll,
+            cursor_char: self.cursor_char,
+            preferred_col: self.preferred_col,
+            dirty: self.dirty,
+            render_dirty: self.render_dirty,
+            read_only: self.read_only,
+            undo: std::mem::take(&mut self.undo),
+            current_match: self.current_match,
+            search_query: self.search_query.clone(),
+            last_reload: self.last_reload,
+            changed_on_disk: false,
+        }
+    }
+
+    fn restore_buffer(&mut self, buffer: Buffer) {
+        self.file_path = buffer.file_path;
+        self.source = buffer.source;
+        self.rope = buffer.rope;
+        self.parsed = buffer.parsed;
+        self.rendered = buffer.rendered;
+        self.scroll = buffer.scroll;
+        self.edit_scroll = buffer.edit_scroll;
+        self.cursor_char = buffer.cursor_char;
+        self.preferred_col = buffer.preferred_col;
+        self.dirty = buffer.dirty;
+        self.render_dirty = buffer.render_dirty;
+        self.render_edit = None;
+        self.read_only = buffer.read_only;
+        self.undo = buffer.undo;
+        self.current_match = buffer.current_match;
+        self.last_reload = buffer.last_reload;
+        self.reset_buffer_view();
+        if buffer.theme != self.config.theme {
+            self.reparse_with_theme(false);
+        } else {
+            self.refresh_render(self.last_width.max(1));
+        }
+        if buffer.changed_on_disk {
+            if self.dirty {
+                self.status = Some("File changed on disk (buffer modified)".to_string());
+            } else {
+                self.request_reload();
+            }
+        }
+    }
+
+    /// Clears view state that belongs to whichever buffer was shown last.
+    fn reset_buffer_view(&mut self) {
+        self.mode = Mode::Normal;
+        self.visual_anchor = None;
+        self.visual_lines = None;
+        self.substitute = None;
+        self.insert_record = None;
+        self.clear_pending();
+        self.count = None;
+        self.render_cursor_line = None;
+        self.reload = FsReload::default();
+        self.suppress_reload_until = None;
+        self.search_matches.clear();
+        self.search_match_map.clear();
+        self.search_dirty = !self.search_query.is_empty();
+        self.editor_cache_dirty = true;
+        self.editor_wrap_dirty = true;
+        self.graphics.invalidate();
+    }
+
+    fn buffer_count(&self) -> usize {
+        self.buffers.len()
+    }
+
+    fn buffer_path(&self, idx: usize) -> &Path {
+        if idx == self.active_buffer {
+            return &self.file_path;
+        }
+        self.buffers[idx]
+            .as_ref()
+            .map(|buffer| buffer.file_path.as_path())
+            .unwrap_or(Path::new(""))
+    }
+
+    fn buffer_is_dirty(&self, idx: usize) -> bool {
+        if idx == self.active_buffer {
+            return self.dirty;
+        }
+        self.buffers[idx].as_ref().is_some_and(|buffer| buffer.dirty)
+    }
+
+    fn buffer_label(&self, idx: usize) -> String {
+        let read_only = if idx == self.active_buffer {
+            self.read_only
+        } else {
+            self.buffers[idx].as_ref().is_some_and(|buffer| buffer.read_only)
+        };
+        if read_only {
+            "[stdin]".to_string()
+        } else {
+            self.buffer_path(idx).display().to_string()
+        }
+    }
+
+    fn find_buffer(&self, path: &Path) -> Option<usize> {
+        (0..self.buffer_count()).find(|&idx| same_file(self.buffer_path(idx), path))
+    }
+
+    /// Moves the cursor to the start of a source line and scrolls it into
+    /// view on the next frame.
+    fn goto_line(&mut self, line: usize) {
+        let line = line.min(self.rope.len_lines().saturating_sub(1));
+        self.cursor_char = self.rope.line_to_char(line);
+        self.preferred_col = None;
+        self.reveal_cursor = true;
+    }
+
+    /// Resumes where the file was left last session: cursor, preview
+    /// scroll and search query.
+    fn restore_position(&mut self, history: &History) {
+        let Some(position) = history.position(&self.file_path) else {
+            return;
+        };
+        let line = position.line.min(self.rope.len_lines().saturating_sub(1));
+        let line_len = line_len(&self.rope, line);
+        self.cursor_char = self.rope.line_to_char(line) + position.column.min(line_len);
+        self.preferred_col = None;
+        self.reveal_cursor = true;
+        self.pending_scroll = Some(position.scroll);
+        if !position.query.is_empty() {
+            self.search_query = position.query;
+            self.search_dirty = true;
+            self.refresh_render(self.last_width.max(1));
+        }
+    }
+
+    /// Positions of every file-backed buffer, for saving on exit.
+    fn saved_positions(&self) -> Vec<(PathBuf, Position)> {
+        let mut positions = Vec::new();
+        if !self.read_only {
+            positions.push((
+                self.file_path.clone(),
+                buffer_position(&self.rope, self.cursor_char, self.scroll, &self.search_query),
+            ));
+        }
+        for buffer in self.buffers.iter().flatten() {
+            if !buffer.read_only {
+                positions.push((
+                    buffer.file_path.clone(),
+                    buffer_position(
+                        &buffer.rope,
+                        buffer.cursor_char,
+                        buffer.scroll,
+                        &buffer.search_query,
+                    ),
+                ));
+            }
+        }
+        positions
+    }
+
+    fn open_buffer(&mut self, path: PathBuf) {
+        if let Some(idx) = self.find_buffer(&path) {
+            self.switch_buffer(idx);
+            return;
+        }
+        let markdown = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) => {
+                self.status = Some(format!("Failed to open {}: {err}", path.display()));
+                return;
+            }
+        };
+        let parked = self.park_buffer();
+        self.buffers[self.active_buffer] = Some(parked);
+        self.buffers.push(None);
+        self.active_buffer = self.buffers.len() - 1;
+
+        self.file_path = path.clone();
+        self.rope = Rope::from_str(&markdown);
+        self.undo = UndoTree::new(&self.rope, self.config.undo_levels);
+        self.source = markdown;
+        self.scroll = 0;
+        self.edit_scroll = 0;
+        self.cursor_char = 0;
+        self.preferred_col = None;
         self.dirty = false;
+        self.render_dirty = false;
+        self.read_only = false;
+        self.current_match = 0;
+        self.last_reload = SystemTime::now();
+        self.reset_buffer_view();
+        self.reparse_with_theme(false);
+        if self.remember_history {
+            let _ = history::record_visit(&path);
+            self.restore_position(&History::load());
+            self.load_undo();
+        }
+        self.watch_file(&path);
+        self.status = Some(format!("\"{}\" [{}]", path.display(), self.active_buffer + 1));
+    }
+
+    fn switch_buffer(&mut self, idx: usize) {
+        if idx == self.active_buffer || idx >= self.buffer_count() {
+            return;
+        }
+        let Some(next) = self.buffers[idx].take() else {
+            return;
+        };
+        let parked = self.park_buffer();
+        self.buffers[self.active_buffer] = Some(parked);
+        self.active_buffer = idx;
+        let label = if next.read_only {
+            "[stdin]".to_string()
+        } else {
+            next.file_path.display().to_string()
+        };
+        self.status = Some(format!("\"{label}\" [{}]", idx + 1));
+        self.restore_buffer(next);
+    }
+
+    fn cycle_buffer(&mut self, delta: isize) {
+        let len = self.buffer_count() as isize;
+        if len <= 1 {
+            self.status = Some("Only one buffer open".to_string());
+            return;
+        }
+        let next = (self.active_buffer as isize + delta).rem_euclid(len) as usize;
+        self.switch_buffer(next);
+    }
+
+    fn delete_buffer(&mut self, idx: usize, force: bool) {
+        if idx >= self.buffer_count() {
+            return;
+        }
+        if self.buffer_is_dirty(idx) && !force {
+            self.status = Some(format!(
+                "No write since last change for buffer {} (add ! to override)",
+                idx + 1
+            ));
+            return;
+        }
+        if self.buffer_count() == 1 {
+            self.status = Some("Cannot delete the last buffer".to_string());
+            return;
+        }
+        let path = self.buffer_path(idx).to_path_buf();
+        if idx == self.active_buffer {
+            let next = if idx + 1 < self.buffer_count() { idx + 1 } else { idx - 1 };
+            self.switch_buffer(next);
+        }
+        let removed = self.buffers.remove(idx);
+        if self.remember_history
+            && let Some(buffer) = removed
+            && !buffer.read_only
+        {
+            let query = &self.search_query;
+            let position = buffer_position(&buffer.rope, buffer.cursor_char, buffer.scroll, query);
+            let _ = history::remember_positions(vec![(path.clone(), position)]);
+        }
+        if self.active_buffer > idx {
+            self.active_buffer -= 1;
+        }
+        if self.find_buffer(&path).is_none() {
+            self.unwatch_file(&path);
+        }
+        self.buffer_selected = self.buffer_selected.min(self.buffer_count() - 1);
+    }
+
+    fn watch_file(&mut self, path: &Path) {
+        if let Some(watcher) = self.watcher.as_mut()
+            && let Err(err) = watcher.watch(path, RecursiveMode::NonRecursive)
+        {
+            self.status = Some(format!("Failed to watch {}: {err}", path.display()));
+        }
+    }
+
+    fn unwatch_file(&mut self, path: &Path) {
+        if let Some(watcher) = self.watcher.as_mut() {
+            let _ = watcher.unwatch(path);
+        }
+    }
+
+    /// Reports the first modified buffer so quitting can be refused.
+    fn first_dirty_buffer(&self) -> Option<usize> {
+        (0..self.buffer_count()).find(|&idx| self.buffer_is_dirty(idx))
+    }
+
+    fn can_quit(&mut self) -> bool {
+        match self.first_dirty_buffer() {
+            None => true,
+            Some(idx) if idx == self.active_buffer => {
+                self.status = Some("No write since last change (add ! to override)".to_string());
+                false
+            }
+            Some(idx) => {
+                self.status = Some(format!(
+                    "No write since last change for buffer {} (add ! to override)",
+                    idx + 1
+                ));
+                false
+            }
+        }
+    }
+
+}
+
+/// Splits a leading line range (`%`, `N`, `N,M`, `.`, `$`, `'<,'>`) off an ex
+/// command. Line numbers are 1-based in the command and 0-based in the result.
+fn parse_line_range(
+    cmd: &str,
+    current: usize,
+    last: usize,
+    visual: Option<LineRange>,
+) -> Result<(Option<LineRange>, &str), String> {
+    if let Some(rest) = cmd.strip_prefix('%') {
+        return Ok((Some((0, last)), rest));
+    }
+    let Some((start, rest)) = parse_address(cmd, current, last, visual)? else {
+        return Ok((None, cmd));
+    };
+    let Some(after_comma) = rest.strip_prefix(',') else {
+        return Ok((Some((start, start)), rest));
+    };
+    let Some((end, rest)) = parse_address(after_comma, current, last, visual)? else {
+        return Err(format!("Invalid range: {cmd}"));
+    };
+    Ok((Some((start.min(end), start.max(end))), rest))
+}
+
+fn parse_address(
+    text: &str,
+    current: usize,
+    last: usize,
+    visual: Option<LineRange>,
+) -> Result<Option<(usize, &str)>, String> {
+    if let Some(rest) = text.strip_prefix('.') {
+        return Ok(Some((current, rest)));
+    }
+    if let Some(rest) = text.strip_prefix('$') {
+        return Ok(Some((last, rest)));
+    }
+    for (mark, pick) in [("'<", true), ("'>", false)] {
+        if let Some(rest) = text.strip_prefix(mark) {
+            let Some((start, end)) = visual else {
+                return Err("Mark not set".to_string());
+            };
+            let line = if pick { start } else { end };
+            return Ok(Some((line.min(last), rest)));
+        }
+    }
+    let digits = text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    if digits == 0 {
+        return Ok(None);
+    }
+    let line = text[..digits]
+        .parse::<usize>()
+        .map_err(|_| format!("Invalid range: {text}"))?;
+    Ok(Some((line.saturating_sub(1).min(last), &text[digits..])))
+}
+
+/// Parses `s/pattern/replacement/flags` (any punctuation works as the
+/// delimiter). Returns `None` when `cmd` is not a substitute command.
+fn parse_substitute(cmd: &str) -> Option<Result<Substitute, String>> {
+    let rest = cmd.strip_prefix("substitute").or_else(|| cmd.strip_prefix('s'))?;
+    let delim = rest.chars().next()?;
+    if delim.is_alphanumeric() || delim.is_whitespace() || matches!(delim, '\\' | '"' | '|') {
+        return None;
+    }
+    let mut parts = vec![String::new()];
+    let mut chars = rest[delim.len_utf8()..].chars();
+    while let Some(ch) = chars.next() {
+        if ch == delim && parts.len() < 3 {
+            parts.push(String::new());
+            continue;
+        }
+        let part = parts.last_mut()?;
+        if ch == '\\' {
+            match chars.next() {
+                Some(next) if next == delim => part.push(next),
+                Some(next) => {
+                    part.push('\\');
+                    part.push(next);
+                }
+                None => part.push('\\'),
+            }
+        } else {
+            part.push(ch);
+        }
+    }
+    let mut parts = parts.into_iter();
+    let mut sub = Substitute {
+        pattern: parts.next().unwrap_or_default(),
+        replacement: parts.next().unwrap_or_default(),
+        global: false,
+        confirm: false,
+        case: None,
+    };
+    for flag in parts.next().unwrap_or_default().trim().chars() {
+        match flag {
+            'g' => sub.global = true,
+            'c' => sub.confirm = true,
+            'i' => sub.case = Some(false),
+            'I' => sub.case = Some(true),
+            _ => return Some(Err(format!("Invalid flag: {flag}"))),
+        }
+    }
+    Some(Ok(sub))
+}
+
+/// Turns a vim replacement (`\1`, `&`, `\n`) into a `regex` expansion template.
+fn replacement_template(replacement: &str) -> String {
+    let mut out = String::with_capacity(replacement.len());
+    let mut chars = replacement.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '&' => out.push_str("${0}"),
+            '$' => out.push_str("$$"),
+            '\\' => match chars.next() {
+                Some(digit @ '0'..='9') => {
+                    out.push_str("${");
+                    out.push(digit);
+                    out.push('}');
+                }
+                Some('n' | 'r') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some('$') => out.push_str("$$"),
+                Some(other) => out.push(other),
+                None => out.push('\\'),
+            },
+            _ => out.push(ch),
+        }
+    }
+    out
+}
+
+/// Plans the replacements of `regex` on `lines` (inclusive), first match per
+/// line unless `global`.
+fn substitute_edits(
+    rope: &Rope,
+    regex: &regex::Regex,
+    template: &str,
+    global: bool,
+    lines: LineRange,
+) -> Vec<SubstituteEdit> {
+    let mut edits = Vec::new();
+    let end_line = lines.1.min(rope.len_lines().saturating_sub(1));
+    for line in lines.0..=end_line {
+        let text = rope.line(line).to_string();
+        let text = text.trim_end_matches(['\n', '\r']);
+        let line_start = rope.line_to_char(line);
+        let mut chars = 0usize;
+        let mut byte = 0usize;
+        for caps in regex.captures_iter(text) {
+            let Some(found) = caps.get(0) else {
+                continue;
+            };
+            chars += text[byte..found.start()].chars().count();
+            let start = line_start + chars;
+            chars += found.as_str().chars().count();
+            byte = found.end();
+            let mut replaced = String::new();
+            caps.expand(template, &mut replaced);
+            edits.push(SubstituteEdit {
+                start,
+                end: line_start + chars,
+                text: replaced,
+                line,
+            });
+            if !global {
+                break;
+            }
+        }
+    }
+    edits
+}
+
+fn expand_user_path(path: &str) -> PathBuf {
+    if let Some(rest) = path.strip_prefix("~/")
+        && let Some(home) = dirs::home_dir()
+    {
+        return home.join(rest);
+    }
+    PathBuf::from(path)
+}
+
+fn buffer_position(rope: &Rope, cursor_char: usize, scroll: usize, query: &str) -> Position {
+    let cursor_char = cursor_char.min(rope.len_chars());
+    let line = rope.char_to_line(cursor_char);
+    Position {
+        line,
+        column: cursor_char - rope.line_to_char(line),
+        scroll,
+        query: query.to_string(),
+    }
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    if a == b {
+        return true;
     }
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
 
+/// The find motion a key starts, waiting for the char to find.
+fn find_prefix(key: char) -> Option<PendingMotion> {
+    let (forward, till) = match key {
+        'f' => (true, false),
+        't' => (true, true),
+        'F' => (false, false),
+        'T' => (false, true),
+        _ => return None,
+    };
+    Some(PendingMotion::Find { forward, till })
+}
+
+/// Keys that modify the buffer in normal mode; rejected in read-only views.
+fn is_edit_key(key: KeyEvent) -> bool {
+    if key.modifiers.contains(KeyModifiers::CONTROL) {
+        return matches!(key.code, KeyCode::Char('r'));
+    }
+    matches!(
+        key.code,
+        KeyCode::Char(
+            'i' | 'a' | 'I' | 'A' | 'o' | 'O' | 'x' | 'd' | 'c' | 'p' | 'P' | 'u' | '.' | 'r'
+        )
+    )
 }
 
 fn current_heading_index(scroll: usize, headings: &[Heading]) -> usize {
@@ -2670,11 +4037,7 @@ fn find_anchor(anchor: &str, lines: &[String], prev_scroll: usize) -> Option<usi
     let mut best: Option<(usize, usize)> = None;
     for (idx, line) in lines.iter().enumerate() {
         if line == anchor {
-            let dist = if idx > prev_scroll {
-                idx - prev_scroll
-            } else {
-                prev_scroll - idx
-            };
+            let dist = idx.abs_diff(prev_scroll);
             match best {
                 Some((_, best_dist)) if dist >= best_dist => {}
                 _ => best = Some((idx, dist)),
@@ -2763,26 +4126,7 @@ fn find_heading_anchor_line(anchor: &str, headings: &[Heading], prev_scroll: usi
     best.map(|(line, _)| line)
 }
 
-fn slugify_anchor(text: &str) -> String {
-    let mut out = String::new();
-    let mut last_dash = false;
-    for ch in text.chars().flat_map(char::to_lowercase) {
-        if ch.is_alphanumeric() {
-            out.push(ch);
-            last_dash = false;
-            continue;
-        }
-        if ch.is_whitespace() || ch == '-' || ch == '_' {
-            if !out.is_empty() && !last_dash {
-                out.push('-');
-                last_dash = true;
-            }
-        }
-    }
-    out.trim_matches('-').to_string()
-}
-
-fn find_anchor_link_under_cursor(line: &str, cursor_col: usize) -> Option<String> {
+fn find_link_under_cursor(line: &str, cursor_col: usize) -> Option<String> {
     if line.is_empty() {
         return None;
     }
@@ -2806,8 +4150,8 @@ fn find_anchor_link_under_cursor(line: &str, cursor_col: usize) -> Option<String
                 if let Some(end_rel) = line[target_start..].find(')') {
                     let target_end = target_start + end_rel;
                     if cursor_byte >= i && cursor_byte <= target_end {
-                        let target = line[target_start..target_end].trim();
-                        if target.contains('#') {
+                        let target = link_destination(&line[target_start..target_end]);
+                        if !target.is_empty() {
                             return Some(target.to_string());
                         }
                     }
@@ -2823,6 +4167,91 @@ fn find_anchor_link_under_cursor(line: &str, cursor_col: usize) -> Option<String
     None
 }
 
+/// Finds an autolink (`<https://...>`) or bare http(s)/mailto URL under the cursor.
+fn find_url_under_cursor(line: &str, cursor_col: usize) -> Option<String> {
+    let cursor_byte = char_to_byte_idx(line, cursor_col);
+    let lower = line.to_ascii_lowercase();
+    let mut search = 0;
+    while search < line.len() {
+        let start = ["https://", "http://", "mailto:"]
+            .iter()
+            .filter_map(|scheme| lower[search..].find(scheme).map(|idx| search + idx))
+            .min()?;
+        let mut end = line[start..]
+            .find(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | '"' | '`'))
+            .map_or(line.len(), |idx| start + idx);
+        while end > start && line[..end].ends_with(['.', ',', ';', ':', '!', '?', ')', ']']) {
+            end -= 1;
+        }
+        if cursor_byte >= start.saturating_sub(1) && cursor_byte <= end {
+            return Some(line[start..end].to_string());
+        }
+        search = end.max(start + 1);
+    }
+    None
+}
+
+/// Strips an optional `"title"` and `<...>` wrapping from a link destination.
+fn link_destination(raw: &str) -> &str {
+    let raw = raw.trim();
+    if let Some(rest) = raw.strip_prefix('<')
+        && let Some(end) = rest.find('>')
+    {
+        return &rest[..end];
+    }
+    raw.split_whitespace().next().unwrap_or("")
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum LinkTarget {
+    Anchor(String),
+    File {
+        path: PathBuf,
+        fragment: Option<String>,
+    },
+    External(String),
+}
+
+fn classify_link(target: &str, base_dir: &Path) -> LinkTarget {
+    let target = target.trim();
+    if target.starts_with('#') {
+        return LinkTarget::Anchor(target.to_string());
+    }
+    if target.contains("://") || target.starts_with("mailto:") {
+        return LinkTarget::External(target.to_string());
+    }
+    let (path, fragment) = match target.split_once('#') {
+        Some((path, frag)) => (path, Some(frag.to_string()).filter(|f| !f.is_empty())),
+        None => (target, None),
+    };
+    let path = PathBuf::from(percent_decode(path));
+    let path = if path.is_absolute() {
+        path
+    } else {
+        base_dir.join(path)
+    };
+    LinkTarget::File { path, fragment }
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(hex) = text.get(i + 1..i + 3)
+            && let Ok(value) = u8::from_str_radix(hex, 16)
+        {
+            out.push(value);
+            i += 3;
+            continue;
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
 fn char_to_byte_idx(text: &str, col: usize) -> usize {
     if col == 0 {
         return 0;
@@ -2833,7 +4262,54 @@ fn char_to_byte_idx(text: &str, col: usize) -> usize {
         .unwrap_or(text.len())
 }
 
+fn image_rows_for(config: &Config, graphics: &Graphics) -> u16 {
+    if graphics.protocol() == Protocol::Off {
+        0
+    } else {
+        config.image_rows.max(1)
+    }
+}
+
+/// Directory the images of the document at `path` are resolved against.
+fn image_base(path: &Path) -> PathBuf {
+    path.parent().map(Path::to_path_buf).unwrap_or_default()
+}
+
+/// Draws the images visible in the bordered preview `area` over their reserved rows.
+fn draw_preview_images(f: &mut ratatui::Frame, app: &mut App, area: Rect) {
+    if app.show_help || matches!(app.mode, Mode::ThemePicker | Mode::BufferPicker) {
+        return;
+    }
+    let inner = area.inner(&Margin {
+        horizontal: 1,
+        vertical: 1,
+    });
+    let base = image_base(&app.file_path);
+    let top = app.scroll;
+    let bottom = top + inner.height as usize;
+    for image in &app.rendered.images {
+        let end = image.line + image.rows;
+        if end <= top || image.line >= bottom {
+            continue;
+        }
+        let Some(path) = graphics::resolve_local_image(&base, &image.dest) else {
+            continue;
+        };
+        let first = image.line.max(top);
+        let rect = Rect {
+            x: inner.x,
+            y: inner.y + (first - top) as u16,
+            width: inner.width,
+            height: (end.min(bottom) - first) as u16,
+        };
+        let skip = (first - image.line) as u16;
+        app.graphics
+            .draw(f.buffer_mut(), &path, rect, image.rows as u16, skip);
+    }
+}
+
 fn ui(f: &mut ratatui::Frame, app: &mut App, layout: &LayoutInfo) {
+    app.graphics.begin_frame();
     let highlight_fg = app.ui.base_bg.unwrap_or(app.ui.base_fg);
     let highlight_style = Style::default().bg(app.ui.accent).fg(highlight_fg);
 
@@ -2872,11 +4348,14 @@ fn ui(f: &mut ratatui::Frame, app: &mut App, layout: &LayoutInfo) {
         f.render_stateful_widget(list, outline_area, &mut state);
     }
 
-    let file_name = app
-        .file_path
-        .file_name()
-        .and_then(|s| s.to_str())
-        .unwrap_or("mark");
+    let file_name = if app.read_only {
+        "stdin"
+    } else {
+        app.file_path
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or("mark")
+    };
     let title = if app.dirty {
         format!(" *{file_name} ")
     } else {
@@ -2910,6 +4389,7 @@ fn ui(f: &mut ratatui::Frame, app: &mut App, layout: &LayoutInfo) {
             .style(app.base_style)
             .scroll((app.scroll as u16, 0));
         f.render_widget(preview_paragraph, layout.editor);
+        draw_preview_images(f, app, layout.editor);
     } else if let Some(preview_area) = layout.preview {
         let preview_paragraph = Paragraph::new(Text::from(app.rendered.lines.clone()))
             .block(
@@ -2922,6 +4402,7 @@ fn ui(f: &mut ratatui::Frame, app: &mut App, layout: &LayoutInfo) {
             .style(app.base_style)
             .scroll((app.scroll as u16, 0));
         f.render_widget(preview_paragraph, preview_area);
+        draw_preview_images(f, app, preview_area);
     }
 
     if app.show_help {
@@ -2937,22 +4418,40 @@ fn ui(f: &mut ratatui::Frame, app: &mut App, layout: &LayoutInfo) {
             Line::from("  j/k or arrows: move cursor"),
             Line::from("  h/l: move left/right"),
             Line::from("  gg/G: top/bottom"),
-            Line::from("  /: search"),
+            Line::from("  w/b/e (W/B/E): word motions, 0/^/$: line start/first char/end"),
+            Line::from("  f/t/F/T + char: find in line, ;/, repeat, %: matching bracket"),
+            Line::from("  {/}: prev/next paragraph (motions take counts, work in visual)"),
+            Line::from("  /: regex search (smart-case, \\c/\\C force case, \\<word\\>)"),
+            Line::from("  Tab in /: scope source, rendered, headings or code"),
             Line::from("  n/N: next/prev match"),
             Line::from("  [/]: prev/next heading"),
-            Line::from("  Enter on [link](#anchor): jump to anchor"),
+            Line::from("  Enter on [link](#anchor) or [link](file.md#id): follow link"),
+            Line::from("  Ctrl+O / Ctrl+I: jump back / forward"),
+            Line::from("  gx: open URL under cursor"),
             Line::from("  Shift+B: toggle preview pane"),
             Line::from("  Ctrl+B: preview full screen"),
             Line::from("  Alt+Left/Right: resize preview"),
             Line::from("  H: toggle outline"),
-            Line::from("  t: theme picker"),
+            Line::from("  :theme: theme picker"),
             Line::from("  :w/:q/:wq: save/quit"),
+            Line::from("  :w <path>: write a copy"),
+            Line::from("  :e path / :bn / :bp / :bd: open, next, prev, close buffer"),
+            Line::from("  :ls: buffer list"),
+            Line::from("  :s/pat/rep/gic, :%s, :10,20s, :'<,'>s: substitute"),
             Line::from("  :anchor #id or :open #id: jump to anchor"),
-            Line::from("  Ctrl+P or :open: discover files"),
+            Line::from("  :open docs/x.md#id: open a file relative to this one"),
+            Line::from("  Ctrl+P or :open: discover files (Tab: search contents)"),
+            Line::from("  d/c/y + motion (w b e $ 0 ^ f t { } gg G): operate, counts multiply"),
+            Line::from("  d/c/y + iw aw i\" i( ip ap: word, quote, bracket, paragraph"),
+            Line::from("  d/c/y + il iu ii ih ic: link text/URL, list item, section, code"),
+            Line::from("  .: repeat last change"),
+            Line::from("  qa ... q: record macro into a, @a / @@ / 3@a: replay it"),
+            Line::from("  \"+ / \"*: system clipboard / selection register (\"+yy, \"+p)"),
+            Line::from("  u / Ctrl+R: undo / redo, g- / g+: older / newer change in time"),
             Line::from("  i/a/o: insert"),
             Line::from("  v/V: visual"),
             Line::from("  ?: toggle help"),
-            Line::from("  q: quit (if clean)"),
+            Line::from("  q in full preview: quit (if clean)"),
         ];
         let help = Paragraph::new(Text::from(help_lines))
             .block(
@@ -2990,14 +4489,43 @@ fn ui(f: &mut ratatui::Frame, app: &mut App, layout: &LayoutInfo) {
         f.render_stateful_widget(list, popup, &mut state);
     }
 
-    if !app.preview_full {
-        if let Some((x, y)) = app.cursor_screen_position(layout) {
-            f.set_cursor(x, y);
-        }
+    if matches!(app.mode, Mode::BufferPicker) {
+        let popup = centered_rect(60, 50, layout.main);
+        f.render_widget(Clear, popup);
+        let items: Vec<ListItem> = (0..app.buffer_count())
+            .map(|idx| {
+                let active = if idx == app.active_buffer { '%' } else { ' ' };
+                let dirty = if app.buffer_is_dirty(idx) { " [+]" } else { "" };
+                ListItem::new(format!(
+                    "{:>3} {active} {}{dirty}",
+                    idx + 1,
+                    app.buffer_label(idx)
+                ))
+            })
+            .collect();
+        let mut state = ListState::default();
+        state.select(Some(app.buffer_selected));
+        let list = List::new(items)
+            .block(
+                Block::bordered()
+                    .title(" Buffers (Enter: open, d: delete, Esc: close) ")
+                    .border_type(BorderType::Rounded)
+                    .border_style(Style::default().fg(app.ui.border))
+                    .style(app.base_style),
+            )
+            .style(app.base_style)
+            .highlight_style(highlight_style);
+        f.render_stateful_widget(list, popup, &mut state);
+    }
+
+    if !app.preview_full
+        && let Some((x, y)) = app.cursor_screen_position(layout)
+    {
+        f.set_cursor(x, y);
     }
 }
 
-fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
+pub fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
     let popup_layout = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
@@ -3020,6 +4548,10 @@ impl App {
     fn status_line(&self) -> Line<'static> {
         if matches!(self.mode, Mode::SearchInput) {
             return Line::from(vec![
+                Span::styled(
+                    format!("[{}] ", self.search_scope.label()),
+                    Style::default().fg(self.ui.muted),
+                ),
                 Span::styled("/", Style::default().fg(self.ui.accent)),
                 Span::styled(self.search_input.clone(), self.base_style),
             ]);
@@ -3036,6 +4568,14 @@ impl App {
             ]);
         }
         parts.push(Span::styled(" | ", Style::default().fg(self.ui.muted)));
+        if let Some(pending) = &self.substitute
+            && matches!(self.mode, Mode::ConfirmSubstitute)
+        {
+            return Line::from(Span::styled(
+                format!("replace with {} (y/n/a/q/l)?", pending.replacement),
+                Style::default().fg(self.ui.accent),
+            ));
+        }
         let mode_label = match self.mode {
             Mode::Normal => "normal",
             Mode::SearchInput => "search",
@@ -3044,15 +4584,29 @@ impl App {
             Mode::VisualChar => "visual",
             Mode::VisualLine => "visual-line",
             Mode::CommandInput => "cmd",
+            Mode::BufferPicker => "buffers",
+            Mode::ConfirmSubstitute => "confirm",
         };
         parts.push(Span::styled(mode_label, Style::default().fg(self.ui.accent)));
+        if let Some((reg, _)) = &self.recording {
+            parts.push(Span::styled(
+                format!(" recording @{reg}"),
+                Style::default().fg(self.ui.accent),
+            ));
+        }
         parts.push(Span::styled(" | ", Style::default().fg(self.ui.muted)));
-        parts.push(Span::styled(
-            self.file_path
-                .to_string_lossy()
-                .to_string(),
-            self.base_style,
-        ));
+        let file_label = if self.read_only {
+            "[stdin] [RO]".to_string()
+        } else {
+            self.file_path.to_string_lossy().to_string()
+        };
+        parts.push(Span::styled(file_label, self.base_style));
+        if self.buffer_count() > 1 {
+            parts.push(Span::styled(
+                format!(" [{}/{}]", self.active_buffer + 1, self.buffer_count()),
+                Style::default().fg(self.ui.muted),
+            ));
+        }
         parts.push(Span::styled(" | ", Style::default().fg(self.ui.muted)));
         parts.push(Span::styled(
             format!("theme: {}", self.config.theme),
@@ -3063,7 +4617,7 @@ impl App {
             let current = if total == 0 { 0 } else { self.current_match + 1 };
             parts.push(Span::styled(" | ", Style::default().fg(self.ui.muted)));
             parts.push(Span::styled(
-                format!("search {current}/{total}"),
+                format!("search {current}/{total} ({})", self.search_scope.label()),
                 Style::default().fg(self.ui.muted),
             ));
         }
@@ -3076,7 +4630,8 @@ impl App {
 
     fn ensure_editor_cache(&mut self) {
         if !self.search_query.is_empty() && self.search_dirty {
-            self.update_search_matches();
+            // Bad patterns were reported when the search was entered.
+            let _ = self.update_search_matches();
         }
         if !self.editor_cache_dirty && !self.editor_lines.is_empty() {
             return;
@@ -3219,7 +4774,7 @@ impl App {
         } else {
             self.editor_lines.clone()
         };
-        if matches!(self.mode, Mode::VisualChar | Mode::VisualLine) {
+        if matches!(self.mode, Mode::VisualChar | Mode::VisualLine | Mode::ConfirmSubstitute) {
             lines = self.apply_selection_overlay(lines);
         }
         Text::from(lines)
@@ -3389,57 +4944,6 @@ impl App {
     }
 }
 
-fn styles_from_palette(ui: UiPalette) -> (Style, MarkdownStyles) {
-    let base_style = Style::default()
-        .fg(ui.base_fg)
-        .bg(bg_or_reset(ui.base_bg));
-
-    let heading = [
-        Style::default()
-            .fg(ui.accent)
-            .add_modifier(Modifier::BOLD | Modifier::UNDERLINED),
-        Style::default().fg(ui.accent).add_modifier(Modifier::BOLD),
-        Style::default().fg(ui.accent).add_modifier(Modifier::ITALIC),
-        Style::default().fg(ui.muted).add_modifier(Modifier::BOLD),
-        Style::default().fg(ui.muted),
-        Style::default().fg(ui.muted).add_modifier(Modifier::ITALIC),
-    ];
-
-    let inline_code_bg = tinted_code_bg(ui.code_bg.or(ui.base_bg), ui.base_fg)
-        .or(ui.base_bg)
-        .or_else(|| fallback_code_bg(ui.base_fg));
-    let code_block_bg = ui.base_bg;
-    let inline_code = Style::default()
-        .fg(ui.accent)
-        .bg(bg_or_reset(inline_code_bg));
-    let prefix = Style::default().fg(ui.muted);
-    let rule = Style::default().fg(ui.muted);
-    let code_border = Style::default().fg(ui.border).bg(bg_or_reset(code_block_bg));
-    let code_header = Style::default()
-        .fg(ui.accent)
-        .bg(bg_or_reset(code_block_bg))
-        .add_modifier(Modifier::BOLD);
-    let table_border = Style::default().fg(ui.border);
-    let table_header = Style::default().fg(ui.accent).add_modifier(Modifier::BOLD);
-
-    (
-        base_style,
-        MarkdownStyles {
-            base: base_style,
-            heading,
-            link_color: ui.accent,
-            inline_code,
-            prefix,
-            rule,
-            code_block_bg,
-            code_border,
-            code_header,
-            table_border,
-            table_header,
-        },
-    )
-}
-
 fn syntect_to_ratatui_style(
     style: syntect::highlighting::Style,
     base_bg: Option<Color>,
@@ -3496,7 +5000,7 @@ fn highlight_spans_with(
 }
 
 fn trim_line_breaks(text: &str) -> &str {
-    text.trim_end_matches(|c| c == '\n' || c == '\r')
+    text.trim_end_matches(['\n', '\r'])
 }
 
 fn resolve_syntax_for_lang<'a>(syntax_set: &'a SyntaxSet, lang: &str) -> &'a syntect::parsing::SyntaxReference {
@@ -3574,77 +5078,6 @@ fn apply_match_highlight(
     out
 }
 
-fn bg_or_reset(color: Option<Color>) -> Color {
-    color.unwrap_or(Color::Reset)
-}
-
-fn adjust_bg(color: Option<Color>, delta: f32) -> Option<Color> {
-    match color {
-        Some(Color::Rgb(r, g, b)) => {
-            let dr = adjust_channel(r, delta);
-            let dg = adjust_channel(g, delta);
-            let db = adjust_channel(b, delta);
-            Some(Color::Rgb(dr, dg, db))
-        }
-        _ => None,
-    }
-}
-
-fn tinted_code_bg(base: Option<Color>, tint: Color) -> Option<Color> {
-    let Some(Color::Rgb(r, g, b)) = base else {
-        return None;
-    };
-    let tinted = tint_color(Color::Rgb(r, g, b), tint, 0.06);
-    adjust_bg(Some(tinted), -0.12)
-}
-
-fn fallback_code_bg(fg: Color) -> Option<Color> {
-    let Color::Rgb(r, g, b) = fg else {
-        return None;
-    };
-    Some(Color::Rgb(
-        (r as f32 * 0.2) as u8,
-        (g as f32 * 0.2) as u8,
-        (b as f32 * 0.2) as u8,
-    ))
-}
-
-fn tint_color(base: Color, accent: Color, amount: f32) -> Color {
-    let Color::Rgb(r, g, b) = base else {
-        return base;
-    };
-    let Color::Rgb(ar, ag, ab) = accent else {
-        return base;
-    };
-    let mix = |base: u8, accent: u8| {
-        let base = base as f32;
-        let accent = accent as f32;
-        (base * (1.0 - amount) + accent * amount).round() as u8
-    };
-    Color::Rgb(mix(r, ar), mix(g, ag), mix(b, ab))
-}
-
-fn adjust_channel(value: u8, delta: f32) -> u8 {
-    let v = value as f32 / 255.0;
-    let adjusted = (v + delta).clamp(0.0, 1.0);
-    (adjusted * 255.0).round() as u8
-}
-
-fn line_len_chars(rope: &Rope, line: usize) -> usize {
-    if line >= rope.len_lines() {
-        return 0;
-    }
-    let slice = rope.line(line);
-    let mut len = slice.len_chars();
-    if len == 0 {
-        return 0;
-    }
-    if slice.char(len - 1) == '\n' {
-        len = len.saturating_sub(1);
-    }
-    len
-}
-
 fn width_of_chars(text: &str, start: usize, end: usize) -> usize {
     text.chars()
         .skip(start)
@@ -3706,7 +5139,7 @@ fn wrap_editor_line(
                         line_idx: usize,
                         col_start: usize,
                         col_end: usize| {
-        out.push(Line::from(current_spans.drain(..).collect::<Vec<_>>()));
+        out.push(Line::from(std::mem::take(current_spans)));
         segments.push(WrappedSegment {
             line_idx,
             col_start,
@@ -3777,223 +5210,271 @@ fn cursor_margin(height: usize) -> usize {
     }
 }
 
-fn slice_chars(text: &str, start: usize, end: usize) -> String {
-    text.chars()
-        .skip(start)
-        .take(end.saturating_sub(start))
-        .collect()
-}
-
-fn normalize_markdown_line(line: &str) -> String {
-    let trimmed = line.trim_end_matches(|c| c == '\n' || c == '\r');
-    let stripped = strip_line_prefix(trimmed);
-    let inline = strip_inline_markdown(&stripped);
-    inline.trim().to_string()
-}
-
-fn normalize_prefix_for_col(line: &str, col: usize) -> usize {
-    let prefix = slice_chars(line, 0, col);
-    let stripped = strip_line_prefix(&prefix);
-    let inline = strip_inline_markdown(&stripped);
-    inline.chars().count()
-}
-
-fn build_match_candidates(line: &str, cursor_col: usize) -> Vec<String> {
-    let trimmed = line.trim();
-    if trimmed.is_empty() {
-        return Vec::new();
-    }
-    let len = trimmed.chars().count();
-    let col = cursor_col.min(len);
-    let mut out = Vec::new();
-
-    if len > 0 {
-        let start = col.saturating_sub(8);
-        let end = (col + 8).min(len);
-        let snippet = slice_chars(trimmed, start, end);
-        if !snippet.trim().is_empty() {
-            out.push(snippet);
+#[cfg(test)]
+mod tests {
+    use super::{
+        classify_link, find_link_under_cursor, find_url_under_cursor, normalize_anchor_target,
+        slugify_anchor, App, LinkTarget, Mode, SearchScope,
+    };
+    use crate::config::Config;
+    use crate::history::History;
+    use crate::test_support::TempDir;
+    use crate::theme::ThemeManager;
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+    use std::path::PathBuf;
+
+    fn test_app(markdown: &str) -> App {
+        test_app_at(PathBuf::from("-"), markdown)
+    }
+
+    fn test_app_at(path: PathBuf, markdown: &str) -> App {
+        let config = Config::default();
+        let theme_manager = ThemeManager::load(&config).unwrap();
+        App::new(path, markdown.to_string(), config, theme_manager).unwrap()
+    }
+
+    fn press(app: &mut App, keys: &str) {
+        for c in keys.chars() {
+            let code = match c {
+                '\n' => KeyCode::Enter,
+                '\t' => KeyCode::Tab,
+                '\x1b' => KeyCode::Esc,
+                c => KeyCode::Char(c),
+            };
+            app.handle_key(KeyEvent::new(code, KeyModifiers::NONE), 20);
         }
     }
 
-    if let Some(word) = trimmed
-        .split_whitespace()
-        .max_by_key(|w| w.chars().count())
-    {
-        if word.chars().count() >= 4 {
-            out.push(word.to_string());
-        }
+    #[test]
+    fn search_matches_use_regex_and_char_ranges() {
+        let mut app = test_app("naïve café\nCafé CAFÉ\n");
+        press(&mut app, "/caf.\n");
+        assert_eq!(app.search_matches.len(), 3);
+        assert_eq!(app.search_match_map[&0], vec![6..10]);
+        assert_eq!(app.search_match_map[&1], vec![0..4, 5..9]);
+        assert_eq!(app.cursor_char, 6);
+
+        press(&mut app, "/");
+        app.search_input = "Café".to_string();
+        press(&mut app, "\n");
+        assert_eq!(app.search_matches.len(), 1);
+
+        press(&mut app, "/");
+        app.search_input = "[a".to_string();
+        press(&mut app, "\n");
+        assert!(app.search_matches.is_empty());
+        assert!(app.status.as_deref().unwrap_or_default().starts_with("Invalid pattern"));
     }
 
-    if len > 24 {
-        out.push(slice_chars(trimmed, 0, 24));
+    #[test]
+    fn search_scopes_pick_rendered_text_headings_and_code() {
+        let md = "# Setup **guide**\n\nSee [the guide](guide.md) for setup.\n\n\
+                  ```sh\nrun setup\n```\n";
+        let mut app = test_app(md);
+        app.ensure_rendered(60);
+        let search = |app: &mut App, query: &str| {
+            press(app, "/");
+            app.search_input = query.to_string();
+            press(app, "\n");
+            app.search_matches.len()
+        };
+
+        assert_eq!(search(&mut app, "guide"), 3);
+        assert_eq!(search(&mut app, "\\*\\*"), 2);
+
+        app.search_scope = SearchScope::Rendered;
+        assert_eq!(search(&mut app, "\\*\\*"), 0);
+        assert_eq!(search(&mut app, "guide"), 2);
+        let link = md.find("the guide").unwrap() + "the ".len();
+        press(&mut app, "n");
+        assert_eq!(app.cursor_char, link);
+
+        press(&mut app, "/\t");
+        assert_eq!(app.search_scope, SearchScope::Headings);
+        app.handle_key(KeyEvent::new(KeyCode::BackTab, KeyModifiers::SHIFT), 20);
+        assert_eq!(app.search_scope, SearchScope::Rendered);
+        press(&mut app, "\t");
+        assert_eq!(app.search_scope, SearchScope::Headings);
+        app.search_input = "setup".to_string();
+        press(&mut app, "\n");
+        assert_eq!(app.search_matches.len(), 1);
+        assert_eq!(app.cursor_char, 2);
+
+        app.search_scope = SearchScope::Code;
+        assert_eq!(search(&mut app, "setup"), 1);
+        assert_eq!(app.cursor_char, md.find("run setup").unwrap() + "run ".len());
+        assert!(app.status_line().to_string().contains("search 1/1 (code)"));
+
+        app.preview_full = true;
+        app.scroll = 0;
+        app.search_scope = SearchScope::Rendered;
+        press(&mut app, "/\n");
+        assert_eq!(app.search_matches.len(), 3);
+        press(&mut app, "nn");
+        let line = app.search_matches[2].rendered.unwrap();
+        assert_eq!(app.render_cursor_line, Some(line));
+        assert!(app.rendered.plain_lines[line].contains("run setup"));
     }
 
-    out.push(trimmed.to_string());
+    #[test]
+    fn substitute_commands_apply_ranges_flags_and_captures() {
+        let mut app = test_app("foo bar foo\nfoo baz\nFOO qux\n");
+        press(&mut app, ":%s/(foo) (\\w+)/\\2-\\1/gI\n");
+        assert_eq!(app.rope.to_string(), "bar-foo foo\nbaz-foo\nFOO qux\n");
+        assert_eq!(app.status.as_deref(), Some("2 substitutions on 2 lines"));
 
-    let mut unique = Vec::new();
-    for cand in out {
-        if !unique.iter().any(|v: &String| v == &cand) {
-            unique.push(cand);
-        }
-    }
-    unique
-}
+        press(&mut app, "u");
+        assert_eq!(app.rope.to_string(), "foo bar foo\nfoo baz\nFOO qux\n");
 
-fn match_line(line: &str, candidates: &[String]) -> Option<(usize, Option<usize>)> {
-    let mut best_len = 0usize;
-    let mut best_col = None;
-    for cand in candidates {
-        if cand.is_empty() {
-            continue;
-        }
-        if let Some(byte_idx) = line.find(cand) {
-            let len = cand.chars().count();
-            let col = line[..byte_idx].chars().count();
-            if len > best_len {
-                best_len = len;
-                best_col = Some(col);
-            }
-        }
-    }
-    if best_len > 0 {
-        Some((best_len, best_col))
-    } else {
-        None
-    }
-}
+        press(&mut app, ":2,3s/foo/[&]/i\n");
+        assert_eq!(app.rope.to_string(), "foo bar foo\n[foo] baz\n[FOO] qux\n");
+        press(&mut app, ":s#o#0#gI\n");
+        assert_eq!(app.rope.to_string(), "foo bar foo\n[foo] baz\n[FOO] qux\n");
+        assert_eq!(app.status.as_deref(), Some("Pattern not found: o"));
+        press(&mut app, ":1s/o/0/g\n");
+        assert_eq!(app.rope.to_string(), "f00 bar f00\n[foo] baz\n[FOO] qux\n");
 
-fn strip_line_prefix(line: &str) -> String {
-    let mut s = line.trim_start();
-    let trimmed = s.trim_start();
-    if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
-        return String::new();
-    }
+        press(&mut app, "jVj:");
+        assert_eq!(app.command_input, "'<,'>");
+        press(&mut app, "s/^/> /\n");
+        assert_eq!(app.rope.to_string(), "f00 bar f00\n> [foo] baz\n> [FOO] qux\n");
 
-    loop {
-        let t = s.trim_start();
-        if let Some(rest) = t.strip_prefix('>') {
-            s = rest.trim_start();
-            continue;
-        }
-        break;
+        press(&mut app, ":s/x/y/z\n");
+        assert_eq!(app.status.as_deref(), Some("Invalid flag: z"));
     }
 
-    let mut hash_count = 0usize;
-    let mut hash_end = 0usize;
-    for (idx, ch) in s.char_indices() {
-        if ch == '#' {
-            hash_count += 1;
-            hash_end = idx + ch.len_utf8();
-        } else {
-            break;
-        }
-    }
-    if hash_count > 0 && hash_count <= 6 {
-        let rest = s[hash_end..].trim_start();
-        let rest = rest.trim_end();
-        return rest.trim_end_matches('#').trim_end().to_string();
+    #[test]
+    fn substitute_confirm_asks_per_match_and_undoes_once() {
+        let mut app = test_app("a a a\na\n");
+        press(&mut app, ":%s/a/bb/gc\n");
+        assert!(matches!(app.mode, Mode::ConfirmSubstitute));
+        assert_eq!(app.selection_range(), Some((0, 1, false)));
+        press(&mut app, "yn");
+        assert_eq!(app.selection_range(), Some((5, 6, false)));
+        press(&mut app, "y");
+        assert_eq!(app.cursor_char, 8);
+        press(&mut app, "q");
+        assert!(matches!(app.mode, Mode::Normal));
+        assert_eq!(app.rope.to_string(), "bb a bb\na\n");
+        assert_eq!(app.status.as_deref(), Some("2 substitutions on 1 line"));
+
+        press(&mut app, "u");
+        assert_eq!(app.rope.to_string(), "a a a\na\n");
+
+        press(&mut app, ":%s/a/c/gc\nna");
+        assert_eq!(app.rope.to_string(), "a c c\nc\n");
     }
 
-    if let Some(rest) = strip_list_marker(s) {
-        return rest.to_string();
-    }
+    #[test]
+    fn buffers_keep_their_own_text_and_undo() {
+        let dir = TempDir::new("buffers");
+        let a = dir.join("a.md");
+        let b = dir.join("b.md");
+        std::fs::write(&a, "alpha\n").unwrap();
+        std::fs::write(&b, "beta\n").unwrap();
+
+        let mut app = test_app_at(a.clone(), "alpha\n");
+        app.search_query = "al".to_string();
+        press(&mut app, &format!(":e {}\n", b.display()));
+        assert_eq!(app.active_buffer, 1);
+        app.search_query = "be".to_string();
+        let queries: Vec<_> = app
+            .saved_positions()
+            .into_iter()
+            .map(|(path, position)| (path, position.query))
+            .collect();
+        assert_eq!(queries, vec![(b.clone(), "be".to_string()), (a.clone(), "al".to_string())]);
+        press(&mut app, "x");
+        assert_eq!(app.rope.to_string(), "eta\n");
 
-    s.to_string()
-}
+        press(&mut app, ":bp\n");
+        assert_eq!(app.file_path, a);
+        assert_eq!(app.rope.to_string(), "alpha\n");
+        assert!(!app.can_quit());
 
-fn strip_list_marker(s: &str) -> Option<&str> {
-    if let Some(rest) = s.strip_prefix("- ") {
-        return Some(rest);
-    }
-    if let Some(rest) = s.strip_prefix("* ") {
-        return Some(rest);
-    }
-    if let Some(rest) = s.strip_prefix("+ ") {
-        return Some(rest);
+        press(&mut app, ":bn\nu");
+        assert_eq!(app.rope.to_string(), "beta\n");
+        press(&mut app, &format!(":e {}\n", a.display()));
+        assert_eq!(app.buffer_count(), 2);
+        press(&mut app, ":bd\n");
+        assert_eq!(app.buffer_count(), 1);
+        assert_eq!(app.file_path, b);
     }
 
-    let mut end_digits = 0usize;
-    for (idx, ch) in s.char_indices() {
-        if ch.is_ascii_digit() {
-            end_digits = idx + ch.len_utf8();
-        } else {
-            break;
-        }
+    #[test]
+    fn classify_link_resolves_relative_files_and_fragments() {
+        let base = std::path::Path::new("docs");
+        assert_eq!(
+            classify_link("setup.md#install", base),
+            LinkTarget::File {
+                path: PathBuf::from("docs/setup.md"),
+                fragment: Some("install".to_string()),
+            }
+        );
+        assert_eq!(
+            classify_link("../My%20Notes.md", base),
+            LinkTarget::File {
+                path: PathBuf::from("docs/../My Notes.md"),
+                fragment: None,
+            }
+        );
+        assert_eq!(classify_link("#usage", base), LinkTarget::Anchor("#usage".to_string()));
+        assert!(matches!(classify_link("https://x.dev", base), LinkTarget::External(_)));
     }
-    if end_digits > 0 {
-        let rest = &s[end_digits..];
-        if let Some(r) = rest.strip_prefix(". ") {
-            return Some(r);
-        }
-        if let Some(r) = rest.strip_prefix(") ") {
-            return Some(r);
-        }
+
+    #[test]
+    fn find_url_under_cursor_handles_autolinks_and_bare_urls() {
+        let line = "Docs: <https://example.com/a>, or https://x.dev/b.";
+        assert_eq!(
+            find_url_under_cursor(line, 10),
+            Some("https://example.com/a".to_string())
+        );
+        assert_eq!(find_url_under_cursor(line, 40), Some("https://x.dev/b".to_string()));
+        assert_eq!(find_url_under_cursor(line, 2), None);
+        assert_eq!(
+            find_link_under_cursor("[site](https://x.dev \"Title\")", 2),
+            Some("https://x.dev".to_string())
+        );
     }
-    None
-}
 
-fn strip_inline_markdown(line: &str) -> String {
-    let mut out = String::new();
-    let mut chars = line.chars().peekable();
-    while let Some(ch) = chars.next() {
-        match ch {
-            '\\' => {
-                if let Some(next) = chars.next() {
-                    out.push(next);
-                }
-            }
-            '`' | '*' | '_' | '~' => {}
-            '[' => {
-                let mut text = String::new();
-                while let Some(next) = chars.next() {
-                    if next == ']' {
-                        break;
-                    }
-                    text.push(next);
-                }
-                out.push_str(&text);
-                if let Some('(') = chars.peek().copied() {
-                    let _ = chars.next();
-                    while let Some(next) = chars.next() {
-                        if next == ')' {
-                            break;
-                        }
-                    }
-                }
-            }
-            '!' => {
-                if let Some('[') = chars.peek().copied() {
-                    let _ = chars.next();
-                    let mut text = String::new();
-                    while let Some(next) = chars.next() {
-                        if next == ']' {
-                            break;
-                        }
-                        text.push(next);
-                    }
-                    out.push_str(&text);
-                    if let Some('(') = chars.peek().copied() {
-                        let _ = chars.next();
-                        while let Some(next) = chars.next() {
-                            if next == ')' {
-                                break;
-                            }
-                        }
-                    }
-                } else {
-                    out.push(ch);
-                }
-            }
-            _ => out.push(ch),
-        }
+    #[test]
+    fn following_file_links_records_jumps() {
+        let dir = TempDir::new("jumps");
+        std::fs::create_dir_all(dir.join("docs")).unwrap();
+        let index = dir.join("index.md");
+        let setup = dir.join("docs").join("setup.md");
+        let index_text = "# Index\n\nSee [setup](docs/setup.md#install).\n";
+        std::fs::write(&index, index_text).unwrap();
+        std::fs::write(&setup, "# Setup\n\nintro\n\n## Install\n\nsteps\n").unwrap();
+
+        let mut app = test_app_at(index.clone(), index_text);
+        app.refresh_render(80);
+        press(&mut app, "jjllllll");
+        press(&mut app, "\n");
+        assert_eq!(app.file_path, setup);
+        assert_eq!(app.cursor_line_col().0, 4);
+
+        app.handle_key(KeyEvent::new(KeyCode::Char('o'), KeyModifiers::CONTROL), 20);
+        assert_eq!(app.file_path, index);
+        assert_eq!(app.cursor_line_col().0, 2);
+        app.handle_key(KeyEvent::new(KeyCode::Tab, KeyModifiers::NONE), 20);
+        assert_eq!(app.file_path, setup);
+        assert_eq!(app.cursor_line_col().0, 4);
     }
-    out
-}
 
-#[cfg(test)]
-mod tests {
-    use super::{find_anchor_link_under_cursor, normalize_anchor_target, slugify_anchor};
+    #[test]
+    fn read_only_rejects_edits_and_writes_copies() {
+        let mut app = test_app("# Title\n\nbody\n");
+        app.read_only = true;
+        press(&mut app, "ixdd");
+        assert_eq!(app.mode, Mode::Normal);
+        assert_eq!(app.rope.to_string(), "# Title\n\nbody\n");
+
+        let dir = TempDir::new("pager");
+        let out = dir.join("copy.md");
+        press(&mut app, &format!(":w {}\n", out.display()));
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "# Title\n\nbody\n");
+    }
 
     #[test]
     fn slugify_anchor_matches_heading_style() {
@@ -4020,9 +5501,165 @@ mod tests {
     fn find_anchor_link_under_cursor_returns_anchor_target() {
         let line = "See [NvimTree](#nvimtree-file-explorer) next.";
         assert_eq!(
-            find_anchor_link_under_cursor(line, 8),
+            find_link_under_cursor(line, 8),
             Some("#nvimtree-file-explorer".to_string())
         );
-        assert_eq!(find_anchor_link_under_cursor(line, 0), None);
+        assert_eq!(find_link_under_cursor(line, 0), None);
+    }
+
+    #[test]
+    fn operators_compose_with_motions_counts_and_repeat() {
+        let mut app = test_app("one two three four\nfive six\n\nseven\n");
+        press(&mut app, "dw");
+        assert_eq!(app.rope.to_string(), "two three four\nfive six\n\nseven\n");
+        press(&mut app, "d2w");
+        assert_eq!(app.rope.to_string(), "four\nfive six\n\nseven\n");
+        press(&mut app, "u");
+        press(&mut app, "cwTWO\x1bw.");
+        assert_eq!(app.rope.to_string(), "TWO TWO four\nfive six\n\nseven\n");
+        press(&mut app, "0dtf");
+        assert_eq!(app.rope.to_string(), "four\nfive six\n\nseven\n");
+        press(&mut app, "d}");
+        assert_eq!(app.rope.to_string(), "\nseven\n");
+        press(&mut app, "ujyiwP");
+        assert_eq!(app.rope.to_string(), "four\nfivefive six\n\nseven\n");
+        press(&mut app, "dG");
+        assert_eq!(app.rope.to_string(), "four\n");
+    }
+
+    #[test]
+    fn motions_move_by_words_finds_brackets_and_paragraphs() {
+        let mut app = test_app("alpha, beta_2 (x [y]) gamma\nend\n\nnext para\n");
+        let at = |app: &App| app.cursor_char;
+        press(&mut app, "w");
+        assert_eq!(at(&app), 5);
+        press(&mut app, "2w");
+        assert_eq!(at(&app), 14);
+        press(&mut app, "%");
+        assert_eq!(at(&app), 20);
+        press(&mut app, "b");
+        assert_eq!(at(&app), 19);
+        press(&mut app, "0eE");
+        assert_eq!((at(&app), app.last_find), (5, None));
+        press(&mut app, "ta;");
+        assert_eq!(at(&app), 22);
+        press(&mut app, ",");
+        assert_eq!(at(&app), 11);
+        press(&mut app, "2Fa");
+        assert_eq!(at(&app), 4);
+        press(&mut app, "}");
+        assert_eq!(app.cursor_line_col(), (2, 0));
+        press(&mut app, "{");
+        assert_eq!(app.cursor_line_col(), (0, 0));
+
+        press(&mut app, "$j");
+        assert_eq!(app.cursor_line_col(), (1, 3));
+        press(&mut app, "kk0wj");
+        assert_eq!(app.cursor_line_col(), (1, 3));
+
+        press(&mut app, "ggv2ey0P");
+        assert!(app.rope.to_string().starts_with("alpha,alpha, beta"));
+        press(&mut app, "uvf(\x1b");
+        assert_eq!(at(&app), 14);
+    }
+
+    #[test]
+    fn text_objects_cover_markdown_spans_and_repeat_with_counts() {
+        let mut app = test_app(
+            "# Shop\n- buy [milk](https://a.example) now\n- and `eggs`\n\n\
+             ## Later\nalpha beta gamma delta\n",
+        );
+        app.cursor_char = 15;
+        press(&mut app, "cilcream\x1bciuhttps://b.example\x1b");
+        assert!(app.rope.to_string().contains("- buy [cream](https://b.example) now\n"));
+        press(&mut app, "j0di`");
+        assert!(app.rope.to_string().contains("- and ``\n"));
+        press(&mut app, "G0daw");
+        assert!(app.rope.to_string().ends_with("## Later\nbeta gamma delta\n"));
+        press(&mut app, "2.");
+        assert!(app.rope.to_string().ends_with("## Later\ndelta\n"));
+        press(&mut app, "dah");
+        assert_eq!(
+            app.rope.to_string(),
+            "# Shop\n- buy [cream](https://b.example) now\n- and ``\n\n"
+        );
+    }
+
+    #[test]
+    fn undo_groups_insert_sessions_restores_cursor_and_walks_branches() {
+        let mut app = test_app("one\n");
+        press(&mut app, "A two\x1b0x");
+        assert_eq!(app.rope.to_string(), "ne two\n");
+        press(&mut app, "u");
+        assert_eq!((app.rope.to_string().as_str(), app.cursor_char), ("one two\n", 0));
+        press(&mut app, "u");
+        assert_eq!((app.rope.to_string().as_str(), app.cursor_char), ("one\n", 3));
+        assert!(!app.dirty);
+
+        press(&mut app, "Ox\x1b");
+        assert_eq!(app.rope.to_string(), "x\none\n");
+        press(&mut app, "g-");
+        assert_eq!(app.rope.to_string(), "ne two\n");
+        press(&mut app, "g-");
+        assert_eq!(app.rope.to_string(), "one two\n");
+        press(&mut app, "g+g+");
+        assert_eq!(app.rope.to_string(), "x\none\n");
+        assert_eq!(app.status.as_deref(), Some("Change 3"));
+        press(&mut app, "uu");
+        assert_eq!(app.status.as_deref(), Some("Already at oldest change"));
+    }
+
+    #[test]
+    fn unnamedplus_routes_default_register_through_plus() {
+        let mut app = test_app("one\ntwo\n");
+        app.config.clipboard = "unnamedplus".to_string();
+        press(&mut app, "yyj\"*yiw");
+        assert_eq!(app.registers[&'+'].text, "one\n");
+        assert_eq!(app.registers[&'*'].text, "two");
+        press(&mut app, "p\"*P");
+        assert_eq!(app.rope.to_string(), "one\ntwo\ntwoone\n");
+
+        press(&mut app, "gg0ve\"*y");
+        assert_eq!(app.registers[&'*'].text, "one");
+        assert_eq!(app.registers[&'+'].text, "one\n");
+    }
+
+    #[test]
+    fn saved_positions_round_trip_through_history() {
+        let path = PathBuf::from("/notes/resume.md");
+        let markdown = "# Title\n\nfirst line\nsecond line\n";
+        let mut app = test_app_at(path.clone(), markdown);
+        press(&mut app, "jjjlll");
+        app.scroll = 2;
+        app.search_query = "second".to_string();
+
+        let mut history = History::default();
+        for (path, position) in app.saved_positions() {
+            history.remember(&path, position, 100);
+        }
+        let mut reopened = test_app_at(path, markdown);
+        reopened.restore_position(&history);
+        assert_eq!(reopened.cursor_line_col(), (3, 3));
+        assert_eq!(reopened.pending_scroll, Some(2));
+        assert_eq!(reopened.search_query, "second");
+    }
+
+    #[test]
+    fn macros_record_replay_and_edit_as_register_text() {
+        let mut app = test_app("one\ntwo\nthree\nfour\n");
+        press(&mut app, "qaI- \x1bjq");
+        assert!(app.recording.is_none());
+        assert_eq!(app.registers[&'a'].text, "I- <Esc>j");
+        press(&mut app, "2@a");
+        assert_eq!(app.rope.to_string(), "- one\n- two\n- three\nfour\n");
+        press(&mut app, "@@");
+        assert_eq!(app.rope.to_string(), "- one\n- two\n- three\n- four\n");
+
+        // A yanked line works as a macro too, so macros can be edited in the buffer.
+        let mut app = test_app("A!<Esc>j\nfoo\nbar\n");
+        press(&mut app, "\"ayydd@a");
+        assert_eq!(app.rope.to_string(), "foo!\nbar\n");
+        press(&mut app, "@@");
+        assert_eq!(app.rope.to_string(), "foo!\nbar!\n");
     }
 }
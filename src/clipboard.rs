@@ -0,0 +1,153 @@
+//! System clipboard behind the `+` and `*` registers.
+//!
+//! Copies always go out as an OSC 52 escape, which the terminal turns into a
+//! clipboard write even over SSH or inside tmux, and also to `wl-copy`,
+//! `xclip` or `pbcopy` when one is around. Terminals rarely allow reading the
+//! clipboard back, so pastes only come from `wl-paste`, `xclip` or `pbpaste`,
+//! and a tool that doesn't answer within `PASTE_TIMEOUT` is given up on.
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use std::env;
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// How long a paste waits for a clipboard tool before falling back.
+const PASTE_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// Puts `text` on the clipboard, or on the primary selection for `*`.
+pub fn copy(text: &str, primary: bool) -> Result<()> {
+    for (program, args) in copy_tools(primary) {
+        let Ok(mut child) = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        else {
+            continue;
+        };
+        let text = text.to_string();
+        std::thread::spawn(move || {
+            if let Some(mut stdin) = child.stdin.take() {
+                let _ = stdin.write_all(text.as_bytes());
+            }
+            child.wait()
+        });
+        break;
+    }
+    let sequence = osc52(text, primary, env::var_os("TMUX").is_some());
+    let mut stdout = io::stdout();
+    stdout
+        .write_all(sequence.as_bytes())
+        .and_then(|()| stdout.flush())
+        .context("Failed to write to the terminal")
+}
+
+/// Reads the clipboard (or primary selection) through the first tool that
+/// answers. `None` when no tool is installed or all of them fail.
+pub fn paste(primary: bool) -> Option<String> {
+    paste_tools(primary).into_iter().find_map(|(program, args)| {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .ok()?;
+        let mut stdout = child.stdout.take()?;
+        // Read on a thread so a tool stuck waiting on the selection owner
+        // can't freeze the editor.
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut bytes = Vec::new();
+            let read = stdout.read_to_end(&mut bytes).map(|_| bytes);
+            let _ = sender.send(read);
+        });
+        let Ok(Ok(bytes)) = receiver.recv_timeout(PASTE_TIMEOUT) else {
+            let _ = child.kill();
+            let _ = child.wait();
+            return None;
+        };
+        if !child.wait().ok()?.success() {
+            return None;
+        }
+        String::from_utf8(bytes).ok()
+    })
+}
+
+/// The OSC 52 write for `text`. Inside tmux the sequence is wrapped in a DCS
+/// passthrough so it reaches the outer terminal.
+fn osc52(text: &str, primary: bool, tmux: bool) -> String {
+    let target = if primary { 'p' } else { 'c' };
+    let sequence = format!("\x1b]52;{target};{}\x07", STANDARD.encode(text));
+    if tmux {
+        format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b"))
+    } else {
+        sequence
+    }
+}
+
+type Tool = (&'static str, &'static [&'static str]);
+
+fn copy_tools(primary: bool) -> Vec<Tool> {
+    let mut tools: Vec<Tool> = Vec::new();
+    if cfg!(target_os = "macos") {
+        tools.push(("pbcopy", &[]));
+    }
+    if env::var_os("WAYLAND_DISPLAY").is_some() {
+        tools.push(("wl-copy", if primary { &["--primary"] } else { &[] }));
+    }
+    if env::var_os("DISPLAY").is_some() {
+        let selection: &[&str] = if primary {
+            &["-selection", "primary"]
+        } else {
+            &["-selection", "clipboard"]
+        };
+        tools.push(("xclip", selection));
+    }
+    tools
+}
+
+fn paste_tools(primary: bool) -> Vec<Tool> {
+    let mut tools: Vec<Tool> = Vec::new();
+    if cfg!(target_os = "macos") {
+        tools.push(("pbpaste", &[]));
+    }
+    if env::var_os("WAYLAND_DISPLAY").is_some() {
+        let args: &[&str] = if primary {
+            &["--no-newline", "--primary"]
+        } else {
+            &["--no-newline"]
+        };
+        tools.push(("wl-paste", args));
+    }
+    if env::var_os("DISPLAY").is_some() {
+        let selection: &[&str] = if primary {
+            &["-o", "-selection", "primary"]
+        } else {
+            &["-o", "-selection", "clipboard"]
+        };
+        tools.push(("xclip", selection));
+    }
+    tools
+}
+
+#[cfg(test)]
+mod tests {
+    use super::osc52;
+
+    #[test]
+    fn osc52_encodes_text_and_wraps_for_tmux() {
+        assert_eq!(osc52("hi\n", false, false), "\x1b]52;c;aGkK\x07");
+        assert_eq!(osc52("hi\n", true, false), "\x1b]52;p;aGkK\x07");
+        assert_eq!(
+            osc52("hi\n", false, true),
+            "\x1bPtmux;\x1b\x1b]52;c;aGkK\x07\x1b\\"
+        );
+    }
+}
@@ -3,7 +3,7 @@ use serde::{Deserialize, Serialize};
 use std::env;
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Stdio};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
@@ -17,6 +17,17 @@ pub struct Config {
     pub tab_width: usize,
     pub forced_discover_dirs: Vec<PathBuf>,
     pub preview_ratio: u16,
+    pub image_protocol: String,
+    pub image_rows: u16,
+    pub open_command: String,
+    pub discover_exclude: Vec<String>,
+    pub max_depth: usize,
+    pub follow_links: bool,
+    pub new_file_template: String,
+    pub undo_levels: usize,
+    pub persist_undo: bool,
+    pub undo_file_max_kb: usize,
+    pub clipboard: String,
 }
 
 impl Default for Config {
@@ -31,6 +42,17 @@ impl Default for Config {
             tab_width: 4,
             forced_discover_dirs: default_forced_discover_dirs(),
             preview_ratio: 55,
+            image_protocol: "auto".to_string(),
+            image_rows: 12,
+            open_command: String::new(),
+            discover_exclude: default_discover_exclude(),
+            max_depth: 0,
+            follow_links: false,
+            new_file_template: String::new(),
+            undo_levels: 1000,
+            persist_undo: false,
+            undo_file_max_kb: 1024,
+            clipboard: String::new(),
         }
     }
 }
@@ -46,6 +68,17 @@ struct PartialConfig {
     tab_width: Option<usize>,
     forced_discover_dirs: Option<Vec<PathBuf>>,
     preview_ratio: Option<u16>,
+    image_protocol: Option<String>,
+    image_rows: Option<u16>,
+    open_command: Option<String>,
+    discover_exclude: Option<Vec<String>>,
+    max_depth: Option<usize>,
+    follow_links: Option<bool>,
+    new_file_template: Option<String>,
+    undo_levels: Option<usize>,
+    persist_undo: Option<bool>,
+    undo_file_max_kb: Option<usize>,
+    clipboard: Option<String>,
 }
 
 impl PartialConfig {
@@ -116,6 +149,83 @@ impl PartialConfig {
                 defaults.preview_ratio
             }
         };
+        let image_protocol = match self.image_protocol {
+            Some(v) => v,
+            None => {
+                changed = true;
+                defaults.image_protocol
+            }
+        };
+        let image_rows = match self.image_rows {
+            Some(v) => v,
+            None => {
+                changed = true;
+                defaults.image_rows
+            }
+        };
+        let open_command = match self.open_command {
+            Some(v) => v,
+            None => {
+                changed = true;
+                defaults.open_command
+            }
+        };
+        let discover_exclude = match self.discover_exclude {
+            Some(v) => v,
+            None => {
+                changed = true;
+                defaults.discover_exclude
+            }
+        };
+        let max_depth = match self.max_depth {
+            Some(v) => v,
+            None => {
+                changed = true;
+                defaults.max_depth
+            }
+        };
+        let follow_links = match self.follow_links {
+            Some(v) => v,
+            None => {
+                changed = true;
+                defaults.follow_links
+            }
+        };
+        let new_file_template = match self.new_file_template {
+            Some(v) => v,
+            None => {
+                changed = true;
+                defaults.new_file_template
+            }
+        };
+        let undo_levels = match self.undo_levels {
+            Some(v) => v,
+            None => {
+                changed = true;
+                defaults.undo_levels
+            }
+        };
+        let persist_undo = match self.persist_undo {
+            Some(v) => v,
+            None => {
+                changed = true;
+                defaults.persist_undo
+            }
+        };
+        let undo_file_max_kb = match self.undo_file_max_kb {
+            Some(v) => v,
+            None => {
+                changed = true;
+                defaults.undo_file_max_kb
+            }
+        };
+        let clipboard = match self.clipboard {
+            Some(v) => v,
+            None => {
+                changed = true;
+                defaults.clipboard
+            }
+        };
 
         (
             Config {
@@ -128,6 +238,17 @@ impl PartialConfig {
             tab_width,
             forced_discover_dirs,
             preview_ratio,
+            image_protocol,
+            image_rows,
+            open_command,
+            discover_exclude,
+            max_depth,
+            follow_links,
+            new_file_template,
+            undo_levels,
+            persist_undo,
+            undo_file_max_kb,
+            clipboard,
         },
         changed,
     )
@@ -143,6 +264,14 @@ fn default_forced_discover_dirs() -> Vec<PathBuf> {
     dirs
 }
 
+fn default_discover_exclude() -> Vec<String> {
+    vec![
+        ".git".to_string(),
+        "node_modules".to_string(),
+        "target".to_string(),
+    ]
+}
+
 pub fn config_path() -> Result<PathBuf> {
     let base = dirs::config_dir().context("Could not determine config directory")?;
     Ok(base.join("mark").join("config.toml"))
@@ -190,11 +319,7 @@ pub fn open_config_in_editor() -> Result<()> {
     }
 
     let editor = env::var("EDITOR").unwrap_or_else(|_| "nvim".to_string());
-    let mut parts = match shell_words::split(&editor) {
-        Ok(p) if !p.is_empty() => p,
-        _ => vec![editor],
-    };
-    let cmd = parts.remove(0);
+    let (cmd, parts) = split_command(&editor);
     let status = Command::new(cmd)
         .args(parts)
         .arg(&path)
@@ -205,3 +330,43 @@ pub fn open_config_in_editor() -> Result<()> {
     }
     Ok(())
 }
+
+/// Launches `url` with `open_command`, or the platform opener when it is empty.
+/// The child is detached from the terminal so it cannot draw over the UI.
+pub fn open_url(open_command: &str, url: &str) -> Result<()> {
+    let command = if open_command.trim().is_empty() {
+        default_open_command()
+    } else {
+        open_command
+    };
+    let (cmd, parts) = split_command(command);
+    let mut child = Command::new(&cmd)
+        .args(parts)
+        .arg(url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Failed to launch {cmd}"))?;
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+fn default_open_command() -> &'static str {
+    if cfg!(target_os = "macos") {
+        "open"
+    } else if cfg!(windows) {
+        "explorer"
+    } else {
+        "xdg-open"
+    }
+}
+
+fn split_command(command: &str) -> (String, Vec<String>) {
+    let mut parts = match shell_words::split(command) {
+        Ok(p) if !p.is_empty() => p,
+        _ => vec![command.to_string()],
+    };
+    let cmd = parts.remove(0);
+    (cmd, parts)
+}
@@ -0,0 +1,2260 @@
+use crate::app::{centered_rect, setup_terminal, TerminalGuard};
+use crate::config::Config;
+use crate::history::{self, History};
+use crate::markdown::{
+    parse_markdown, rewrap_document, MarkdownStyles, ParsedDocument, RenderedDocument,
+};
+use crate::theme::{styles_from_palette, ThemeManager, UiPalette};
+use anyhow::{bail, Context, Result};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use ignore::overrides::OverrideBuilder;
+use ignore::WalkBuilder;
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::{Line, Span, Text};
+use ratatui::widgets::{Block, BorderType, Clear, Paragraph};
+use ratatui::Terminal;
+use regex::{Regex, RegexBuilder};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::io::Stdout;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+const CONTENT_SEARCH_DEBOUNCE: Duration = Duration::from_millis(150);
+const MAX_CONTENT_HITS: usize = 2000;
+const SNIPPET_LEAD: usize = 40;
+const SNIPPET_MAX: usize = 240;
+const SCAN_BATCH_INTERVAL: Duration = Duration::from_millis(40);
+const PREVIEW_DEBOUNCE: Duration = Duration::from_millis(120);
+const PREVIEW_CACHE_LIMIT: usize = 32;
+const PREVIEW_MIN_WIDTH: u16 = 80;
+const OUTLINE_ROWS: usize = 8;
+const RECENT_LIMIT: usize = 10;
+const SPINNER: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+
+const SCORE_MATCH: i64 = 16;
+const SCORE_GAP_START: i64 = 3;
+const SCORE_GAP_EXTENSION: i64 = 1;
+const BONUS_BOUNDARY: i64 = 8;
+const BONUS_PATH_SEPARATOR: i64 = 10;
+const BONUS_CAMEL: i64 = 7;
+const BONUS_CONSECUTIVE: i64 = 4;
+const FRECENCY_WEIGHT: f64 = 10.0;
+
+/// A file picked in discover, optionally at a 0-based source line.
+#[derive(Debug, Clone)]
+pub struct DiscoverPick {
+    pub path: PathBuf,
+    pub line: Option<usize>,
+}
+
+pub fn run_discover(config: &Config) -> Result<Option<DiscoverPick>> {
+    let theme_manager = ThemeManager::load(config)?;
+    let mut terminal = setup_terminal()?;
+    let _guard = TerminalGuard;
+    discover_in(&mut terminal, config, &theme_manager)
+}
+
+pub fn discover_in(
+    terminal: &mut Terminal<CrosstermBackend<Stdout>>,
+    config: &Config,
+    theme_manager: &ThemeManager,
+) -> Result<Option<DiscoverPick>> {
+    let ui = theme_manager.ui_palette(&config.theme);
+    let (base_style, markdown_styles) = styles_from_palette(ui);
+
+    let roots = discover_roots(config);
+    let scan = spawn_scan(roots.clone(), config);
+    let mut state = DiscoverState::new(scan, roots, History::load(), ui, base_style);
+    state.new_file_template = config.new_file_template.clone();
+
+    let tick_rate = Duration::from_millis(50);
+    loop {
+        state.poll_scan();
+        state.poll_content_search();
+        let target = state.current_pick().map(|pick| pick.path);
+        state
+            .preview
+            .update(target, config, theme_manager, &markdown_styles);
+        let size = terminal.size()?;
+        terminal.draw(|f| discover_ui(f, &mut state, size))?;
+
+        if event::poll(tick_rate)?
+            && let Event::Key(key) = event::read()?
+            && let Some(selected) = state.handle_key(key)
+        {
+            return Ok(selected);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct DiscoverItem {
+    path: PathBuf,
+    display: String,
+    frecency: f64,
+    /// Index of the most specific discover root containing the file; recent
+    /// files opened from elsewhere have none.
+    root: Option<usize>,
+    relative: PathBuf,
+    /// Position in the most-recently-used list, if the file is in it.
+    recent: Option<usize>,
+}
+
+enum ScanMessage {
+    Items(Vec<DiscoverItem>),
+    Done,
+}
+
+struct Preview {
+    parsed: ParsedDocument,
+    rendered: RenderedDocument,
+    line_starts: Vec<usize>,
+    width: u16,
+    modified: Option<SystemTime>,
+}
+
+/// Rendered previews keyed by path and kept while the file's mtime is
+/// unchanged. Files are read and parsed on a worker thread, and only once
+/// the selection has rested on them for `PREVIEW_DEBOUNCE`.
+struct PreviewCache {
+    entries: HashMap<PathBuf, Preview>,
+    target: Option<PathBuf>,
+    since: Instant,
+    width: u16,
+    worker: Option<PreviewWorker>,
+    /// The file the worker was last asked for and hasn't answered yet.
+    loading: Option<PathBuf>,
+}
+
+struct PreviewWorker {
+    requests: Sender<PathBuf>,
+    loaded: Receiver<LoadedPreview>,
+}
+
+struct LoadedPreview {
+    path: PathBuf,
+    parsed: ParsedDocument,
+    line_starts: Vec<usize>,
+    modified: Option<SystemTime>,
+}
+
+impl PreviewCache {
+    fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+            target: None,
+            since: Instant::now(),
+            width: 0,
+            worker: None,
+            loading: None,
+        }
+    }
+
+    fn update(
+        &mut self,
+        target: Option<PathBuf>,
+        config: &Config,
+        theme_manager: &ThemeManager,
+        styles: &MarkdownStyles,
+    ) {
+        let worker = self
+            .worker
+            .get_or_insert_with(|| spawn_preview_worker(config, theme_manager, *styles));
+        while let Ok(loaded) = worker.loaded.try_recv() {
+            if self.loading.as_ref() == Some(&loaded.path) {
+                self.loading = None;
+            }
+            if self.entries.len() >= PREVIEW_CACHE_LIMIT {
+                self.entries.clear();
+            }
+            let preview = Preview {
+                parsed: loaded.parsed,
+                rendered: RenderedDocument::default(),
+                line_starts: loaded.line_starts,
+                // Wrapped below once it is the target.
+                width: 0,
+                modified: loaded.modified,
+            };
+            self.entries.insert(loaded.path, preview);
+        }
+        if target != self.target {
+            self.target = target;
+            self.since = Instant::now();
+            return;
+        }
+        let (Some(path), true) = (self.target.clone(), self.width > 0) else {
+            return;
+        };
+        let width = if config.wrap { self.width } else { u16::MAX };
+        let modified = modified_time(&path);
+        if let Some(preview) = self.entries.get_mut(&path) {
+            if preview.width != width {
+                let previous = std::mem::take(&mut preview.rendered);
+                preview.rendered =
+                    rewrap_document(&preview.parsed, Some(previous), width, None, false, None);
+                preview.width = width;
+            }
+            if preview.modified == modified {
+                return;
+            }
+        }
+        // Missing or edited since it was read; a stale preview stays up meanwhile.
+        if self.since.elapsed() < PREVIEW_DEBOUNCE || self.loading.as_ref() == Some(&path) {
+            return;
+        }
+        if let Some(worker) = &self.worker
+            && worker.requests.send(path.clone()).is_ok()
+        {
+            self.loading = Some(path);
+        }
+    }
+
+    fn get(&self, path: &Path) -> Option<&Preview> {
+        self.entries.get(path)
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// Reads and parses preview files off the UI thread, skipping requests that
+/// were superseded while it was busy.
+fn spawn_preview_worker(
+    config: &Config,
+    theme_manager: &ThemeManager,
+    styles: MarkdownStyles,
+) -> PreviewWorker {
+    let (requests, inbox) = mpsc::channel::<PathBuf>();
+    let (outbox, loaded) = mpsc::channel();
+    let syntax_set = theme_manager.syntax_set().clone();
+    let theme = theme_manager.get(&config.theme).clone();
+    let tab_width = config.tab_width;
+    thread::spawn(move || {
+        while let Ok(mut path) = inbox.recv() {
+            while let Ok(newer) = inbox.try_recv() {
+                path = newer;
+            }
+            let modified = modified_time(&path);
+            let text = fs::read_to_string(&path).unwrap_or_else(|err| format!("> {err}"));
+            let parsed = parse_markdown(&text, &syntax_set, &theme, &styles, tab_width)
+                .unwrap_or_default();
+            let loaded = LoadedPreview {
+                path,
+                parsed,
+                line_starts: line_char_offsets(&text),
+                modified,
+            };
+            if outbox.send(loaded).is_err() {
+                break;
+            }
+        }
+    });
+    PreviewWorker { requests, loaded }
+}
+
+/// A row of the discover tree. Directories are keyed by root index and
+/// their path relative to that root; the empty path is the root itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TreeRow {
+    Dir {
+        root: usize,
+        dir: PathBuf,
+        depth: usize,
+        count: usize,
+    },
+    File {
+        rank: usize,
+        depth: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RowKey {
+    Item(usize),
+    Dir(usize, PathBuf),
+}
+
+#[derive(Default)]
+struct DirNode {
+    dirs: BTreeMap<String, DirNode>,
+    files: Vec<usize>,
+    count: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FileAction {
+    New,
+    Rename,
+    Duplicate,
+    Delete,
+}
+
+/// A pending file operation. `input` holds the target path being typed;
+/// deletes only wait for a y/n answer.
+struct FilePrompt {
+    action: FileAction,
+    input: String,
+    item: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DiscoverTab {
+    Files,
+    Content,
+}
+
+#[derive(Debug, Clone)]
+struct ContentHit {
+    item: usize,
+    line: usize,
+    snippet: String,
+    highlight: Range<usize>,
+}
+
+struct SearchBatch {
+    generation: u64,
+    hits: Vec<ContentHit>,
+    scanned: usize,
+    done: bool,
+}
+
+/// Grep state for the content tab. Searches run on a worker thread and are
+/// cancelled by bumping `generation`; stale batches are dropped on receipt.
+struct ContentSearch {
+    query: String,
+    hits: Vec<ContentHit>,
+    selected: usize,
+    scroll: usize,
+    generation: Arc<AtomicU64>,
+    sender: Sender<SearchBatch>,
+    receiver: Receiver<SearchBatch>,
+    pending_since: Option<Instant>,
+    running: bool,
+    scanned: usize,
+}
+
+impl ContentSearch {
+    fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            query: String::new(),
+            hits: Vec::new(),
+            selected: 0,
+            scroll: 0,
+            generation: Arc::new(AtomicU64::new(0)),
+            sender,
+            receiver,
+            pending_since: None,
+            running: false,
+            scanned: 0,
+        }
+    }
+
+    fn start(&mut self, files: Vec<(usize, PathBuf)>) {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        self.pending_since = None;
+        self.hits.clear();
+        self.selected = 0;
+        self.scroll = 0;
+        self.scanned = 0;
+        self.running = false;
+        let Some(pattern) = content_pattern(&self.query) else {
+            return;
+        };
+        self.running = true;
+        let current = Arc::clone(&self.generation);
+        let sender = self.sender.clone();
+        thread::spawn(move || search_contents(files, &pattern, generation, &current, &sender));
+    }
+
+    /// Drops a running search's remaining batches and searches again after
+    /// the usual pause. Hits found so far stay until the new search starts.
+    fn restart(&mut self) {
+        if self.running {
+            self.generation.fetch_add(1, Ordering::SeqCst);
+            self.running = false;
+            self.pending_since = Some(Instant::now());
+        }
+    }
+
+    fn poll(&mut self) {
+        let generation = self.generation.load(Ordering::SeqCst);
+        while let Ok(batch) = self.receiver.try_recv() {
+            if batch.generation != generation {
+                continue;
+            }
+            self.hits.extend(batch.hits);
+            self.scanned = batch.scanned;
+            if batch.done {
+                self.running = false;
+            }
+        }
+    }
+}
+
+impl Drop for ContentSearch {
+    fn drop(&mut self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Smart-case literal matcher: case-insensitive unless the query has an
+/// uppercase character.
+fn content_pattern(query: &str) -> Option<Regex> {
+    if query.trim().is_empty() {
+        return None;
+    }
+    RegexBuilder::new(&regex::escape(query))
+        .case_insensitive(!query.chars().any(char::is_uppercase))
+        .build()
+        .ok()
+}
+
+fn search_contents(
+    files: Vec<(usize, PathBuf)>,
+    pattern: &Regex,
+    generation: u64,
+    current: &AtomicU64,
+    sender: &Sender<SearchBatch>,
+) {
+    let mut total = 0;
+    let mut scanned = 0;
+    for (item, path) in files {
+        if current.load(Ordering::SeqCst) != generation {
+            return;
+        }
+        scanned += 1;
+        let Ok(text) = fs::read_to_string(&path) else {
+            continue;
+        };
+        let mut hits = Vec::new();
+        for (line, content) in text.lines().enumerate() {
+            if total >= MAX_CONTENT_HITS {
+                break;
+            }
+            if let Some(found) = pattern.find(content) {
+                let (snippet, highlight) = snippet(content, found.range());
+                hits.push(ContentHit {
+                    item,
+                    line,
+                    snippet,
+                    highlight,
+                });
+                total += 1;
+            }
+        }
+        let batch = SearchBatch {
+            generation,
+            hits,
+            scanned,
+            done: false,
+        };
+        if sender.send(batch).is_err() || total >= MAX_CONTENT_HITS {
+            break;
+        }
+    }
+    let _ = sender.send(SearchBatch {
+        generation,
+        hits: Vec::new(),
+        scanned,
+        done: true,
+    });
+}
+
+/// Trims a matching line down to a window around the match. Returns the
+/// snippet and the match's byte range within it.
+fn snippet(line: &str, range: Range<usize>) -> (String, Range<usize>) {
+    let indent = line.len() - line.trim_start().len();
+    let mut start = indent.min(range.start);
+    let mut prefix = "";
+    if range.start - start > SNIPPET_LEAD {
+        start = range.start - SNIPPET_LEAD;
+        while !line.is_char_boundary(start) {
+            start += 1;
+        }
+        prefix = "…";
+    }
+    let mut end = line.len().min(start + SNIPPET_MAX).max(range.end);
+    while !line.is_char_boundary(end) {
+        end += 1;
+    }
+    let mut text = String::with_capacity(prefix.len() + end - start + 3);
+    text.push_str(prefix);
+    text.push_str(&line[start..end].replace('\t', " "));
+    let offset = prefix.len() + range.start - start;
+    let highlight = offset..offset + range.len();
+    if end < line.len() {
+        text.push('…');
+    }
+    (text, highlight)
+}
+
+struct DiscoverState {
+    items: Vec<DiscoverItem>,
+    filtered: Vec<usize>,
+    positions: Vec<Vec<usize>>,
+    selected: usize,
+    scroll: usize,
+    ui: UiPalette,
+    base_style: Style,
+    status: Option<String>,
+    query: String,
+    filter_mode: bool,
+    show_help: bool,
+    tab: DiscoverTab,
+    content: ContentSearch,
+    scan: Receiver<ScanMessage>,
+    scanning: bool,
+    roots: Vec<String>,
+    root_paths: Vec<PathBuf>,
+    known: HashSet<PathBuf>,
+    history: History,
+    ticks: usize,
+    preview: PreviewCache,
+    tree_view: bool,
+    tree_rows: Vec<TreeRow>,
+    collapsed: HashSet<(usize, PathBuf)>,
+    prompt: Option<FilePrompt>,
+    new_file_template: String,
+    recent_count: usize,
+}
+
+impl DiscoverState {
+    fn new(
+        scan: Receiver<ScanMessage>,
+        root_paths: Vec<PathBuf>,
+        history: History,
+        ui: UiPalette,
+        base_style: Style,
+    ) -> Self {
+        let roots = root_paths.iter().map(|root| display_root(root)).collect();
+        let mut state = Self {
+            items: Vec::new(),
+            filtered: Vec::new(),
+            positions: Vec::new(),
+            selected: 0,
+            scroll: 0,
+            ui,
+            base_style,
+            status: None,
+            query: String::new(),
+            filter_mode: false,
+            show_help: false,
+            tab: DiscoverTab::Files,
+            content: ContentSearch::new(),
+            scan,
+            scanning: true,
+            roots,
+            root_paths,
+            known: HashSet::new(),
+            history,
+            ticks: 0,
+            preview: PreviewCache::new(),
+            tree_view: false,
+            tree_rows: Vec::new(),
+            collapsed: HashSet::new(),
+            prompt: None,
+            new_file_template: String::new(),
+            recent_count: 0,
+        };
+        state.add_recent_items();
+        state
+    }
+
+    /// Seeds the list with recently opened files so they show before the
+    /// scan reaches them, including files outside the discover roots.
+    fn add_recent_items(&mut self) {
+        for (rank, path) in self.history.recent(RECENT_LIMIT).into_iter().enumerate() {
+            if !self.known.insert(path.clone()) {
+                continue;
+            }
+            let mut item = self.new_item(path);
+            item.recent = Some(rank);
+            self.items.push(item);
+        }
+        self.apply_filter();
+    }
+
+    /// Merges files found by the scan worker, keeping the selected file
+    /// selected while the ranking shifts underneath it.
+    fn poll_scan(&mut self) {
+        self.ticks = self.ticks.wrapping_add(1);
+        let mut changed = false;
+        let now = history::now();
+        while let Ok(message) = self.scan.try_recv() {
+            match message {
+                ScanMessage::Items(items) => {
+                    for mut item in items {
+                        // Files created or renamed from discover may show up again.
+                        if !self.known.insert(item.path.clone()) {
+                            continue;
+                        }
+                        item.frecency = self.history.frecency(&item.path, now);
+                        self.items.push(item);
+                    }
+                    changed = true;
+                }
+                ScanMessage::Done => {
+                    self.scanning = false;
+                    // A content search started mid-scan only saw part of the tree.
+                    if !self.content.query.is_empty() {
+                        self.content.pending_since = Some(Instant::now());
+                    }
+                }
+            }
+        }
+        if !changed {
+            return;
+        }
+        let current = self.selected_row_key();
+        self.apply_filter();
+        if let Some(current) = current {
+            self.reselect(&current);
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Option<Option<DiscoverPick>> {
+        self.status = None;
+        if self.prompt.is_some() {
+            self.handle_prompt_key(key);
+            return None;
+        }
+        if self.show_help {
+            match key.code {
+                KeyCode::Esc | KeyCode::Char('?') => {
+                    self.show_help = false;
+                    return None;
+                }
+                _ => return None,
+            }
+        }
+
+        if matches!(key.code, KeyCode::Tab | KeyCode::BackTab) {
+            self.switch_tab();
+            return None;
+        }
+
+        if self.filter_mode {
+            match key.code {
+                KeyCode::Esc => {
+                    self.filter_mode = false;
+                    return None;
+                }
+                KeyCode::Enter => {
+                    self.filter_mode = false;
+                    return None;
+                }
+                KeyCode::Backspace => {
+                    self.query_mut().pop();
+                    self.query_changed();
+                    return None;
+                }
+                KeyCode::Char('u') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    self.query_mut().clear();
+                    self.query_changed();
+                    return None;
+                }
+                KeyCode::Up => {
+                    self.move_selection(-1);
+                    return None;
+                }
+                KeyCode::Down => {
+                    self.move_selection(1);
+                    return None;
+                }
+                KeyCode::PageDown => {
+                    self.move_selection(10);
+                    return None;
+                }
+                KeyCode::PageUp => {
+                    self.move_selection(-10);
+                    return None;
+                }
+                KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    self.query_mut().push(c);
+                    self.query_changed();
+                    return None;
+                }
+                _ => {}
+            }
+        }
+
+        if self.tab == DiscoverTab::Files && self.tree_view {
+            match key.code {
+                KeyCode::Char('h') | KeyCode::Left => {
+                    self.tree_collapse();
+                    return None;
+                }
+                KeyCode::Char('l') | KeyCode::Right | KeyCode::Enter => {
+                    if self.selected_dir().is_none() {
+                        return self.current_pick().map(Some);
+                    }
+                    if key.code == KeyCode::Enter {
+                        self.tree_toggle();
+                    } else {
+                        self.tree_expand();
+                    }
+                    return None;
+                }
+                _ => {}
+            }
+        }
+
+        match key.code {
+            KeyCode::Esc | KeyCode::Char('q') => return Some(None),
+            KeyCode::Enter => return self.current_pick().map(Some),
+            KeyCode::Char('n') if self.tab == DiscoverTab::Files => {
+                self.begin_file_action(FileAction::New);
+            }
+            KeyCode::Char('r') if self.tab == DiscoverTab::Files => {
+                self.begin_file_action(FileAction::Rename);
+            }
+            KeyCode::Char('c') if self.tab == DiscoverTab::Files => {
+                self.begin_file_action(FileAction::Duplicate);
+            }
+            KeyCode::Char('d') if self.tab == DiscoverTab::Files => {
+                self.begin_file_action(FileAction::Delete);
+            }
+            KeyCode::Char('t') if self.tab == DiscoverTab::Files => {
+                let current = self.selected_row_key();
+                self.tree_view = !self.tree_view;
+                self.rebuild_tree();
+                if let Some(current) = current {
+                    self.reselect(&current);
+                }
+            }
+            KeyCode::Char('j') | KeyCode::Down => self.move_selection(1),
+            KeyCode::Char('k') | KeyCode::Up => self.move_selection(-1),
+            KeyCode::PageDown => self.move_selection(10),
+            KeyCode::PageUp => self.move_selection(-10),
+            KeyCode::Char('g') => *self.selected_mut() = 0,
+            KeyCode::Char('G') => {
+                let last = self.list_len().saturating_sub(1);
+                *self.selected_mut() = last;
+            }
+            KeyCode::Char('?') => {
+                self.show_help = true;
+            }
+            KeyCode::Char('/') => {
+                self.filter_mode = true;
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn begin_file_action(&mut self, action: FileAction) {
+        let selected = self.selected_row_key();
+        let item = match selected {
+            Some(RowKey::Item(idx)) => Some(idx),
+            _ => None,
+        };
+        let input = match (action, item) {
+            (FileAction::New, _) => {
+                let dir = match &selected {
+                    Some(RowKey::Item(idx)) => {
+                        self.items[*idx].path.parent().map(Path::to_path_buf)
+                    }
+                    Some(RowKey::Dir(root, dir)) => {
+                        self.root_paths.get(*root).map(|root| root.join(dir))
+                    }
+                    None => None,
+                };
+                let dir = dir.unwrap_or_else(|| self.cwd());
+                let mut input = display_path(&dir, &self.cwd(), dirs::home_dir().as_deref());
+                if !input.ends_with('/') {
+                    input.push('/');
+                }
+                input
+            }
+            (FileAction::Rename, Some(idx)) => self.items[idx].display.clone(),
+            (FileAction::Duplicate, Some(idx)) => copy_name(&self.items[idx].display),
+            (FileAction::Delete, Some(_)) => String::new(),
+            (_, None) => {
+                self.status = Some("Select a file first".to_string());
+                return;
+            }
+        };
+        self.prompt = Some(FilePrompt {
+            action,
+            input,
+            item,
+        });
+    }
+
+    fn handle_prompt_key(&mut self, key: KeyEvent) {
+        let Some(prompt) = self.prompt.as_mut() else {
+            return;
+        };
+        if prompt.action == FileAction::Delete {
+            let confirmed = matches!(key.code, KeyCode::Char('y') | KeyCode::Char('Y'));
+            if let Some(prompt) = self.prompt.take() {
+                if confirmed {
+                    self.run_file_action(prompt);
+                } else {
+                    self.status = Some("Delete cancelled".to_string());
+                }
+            }
+            return;
+        }
+        match key.code {
+            KeyCode::Esc => self.prompt = None,
+            KeyCode::Enter => {
+                if let Some(prompt) = self.prompt.take() {
+                    self.run_file_action(prompt);
+                }
+            }
+            KeyCode::Backspace => {
+                prompt.input.pop();
+            }
+            KeyCode::Char('u') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                prompt.input.clear();
+            }
+            KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                prompt.input.push(c);
+            }
+            _ => {}
+        }
+    }
+
+    fn run_file_action(&mut self, prompt: FilePrompt) {
+        let result = match (prompt.action, prompt.item) {
+            (FileAction::New, _) => self.create_file(&prompt.input),
+            (FileAction::Rename, Some(idx)) => self.rename_item(idx, &prompt.input),
+            (FileAction::Duplicate, Some(idx)) => self.duplicate_item(idx, &prompt.input),
+            (FileAction::Delete, Some(idx)) => self.delete_item(idx),
+            (_, None) => return,
+        };
+        self.status = Some(match result {
+            Ok(message) => message,
+            Err(err) => format!("{err:#}"),
+        });
+    }
+
+    fn cwd(&self) -> PathBuf {
+        env::current_dir().unwrap_or_else(|_| PathBuf::from("."))
+    }
+
+    /// Turns prompt input into an absolute markdown path that does not exist yet.
+    fn resolve_new_path(&self, input: &str) -> Result<PathBuf> {
+        let input = input.trim();
+        if input.is_empty() || input.ends_with('/') {
+            bail!("Enter a file name");
+        }
+        let input = input.strip_prefix("./").unwrap_or(input);
+        let home = dirs::home_dir();
+        let mut path = expand_discover_dir(Path::new(input), &self.cwd(), home.as_deref());
+        if !is_markdown_file(&path) {
+            path.as_mut_os_string().push(".md");
+        }
+        if path.exists() {
+            bail!("{} already exists", self.display(&path));
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        Ok(path)
+    }
+
+    fn create_file(&mut self, input: &str) -> Result<String> {
+        let path = self.resolve_new_path(input)?;
+        let text = new_file_text(&self.new_file_template, &path)?;
+        fs::write(&path, text).with_context(|| format!("Failed to write {}", path.display()))?;
+        let message = format!("Created {}", self.display(&path));
+        self.add_item(path);
+        Ok(message)
+    }
+
+    fn rename_item(&mut self, idx: usize, input: &str) -> Result<String> {
+        let old = self.items[idx].path.clone();
+        let path = self.resolve_new_path(input)?;
+        fs::rename(&old, &path).with_context(|| {
+            format!("Failed to move {} to {}", old.display(), path.display())
+        })?;
+        self.known.remove(&old);
+        self.known.insert(path.clone());
+        self.history.rename(&old, &path);
+        let _ = history::rename_file(&old, &path);
+        let item = self.new_item(path);
+        let message = format!("Moved {} to {}", self.items[idx].display, item.display);
+        self.items[idx] = item;
+        self.apply_filter();
+        self.reselect(&RowKey::Item(idx));
+        Ok(message)
+    }
+
+    fn duplicate_item(&mut self, idx: usize, input: &str) -> Result<String> {
+        let source = self.items[idx].path.clone();
+        let path = self.resolve_new_path(input)?;
+        fs::copy(&source, &path).with_context(|| {
+            format!("Failed to copy {} to {}", source.display(), path.display())
+        })?;
+        let message = format!("Copied to {}", self.display(&path));
+        self.add_item(path);
+        Ok(message)
+    }
+
+    fn delete_item(&mut self, idx: usize) -> Result<String> {
+        let path = self.items[idx].path.clone();
+        trash::delete(&path).with_context(|| format!("Failed to trash {}", path.display()))?;
+        self.known.remove(&path);
+        self.history.forget(&path);
+        let _ = history::forget_file(&path);
+        let removed = self.items.remove(idx);
+        // Batches still coming in number files from before the removal.
+        self.content.restart();
+        self.content.hits.retain(|hit| hit.item != idx);
+        for hit in &mut self.content.hits {
+            if hit.item > idx {
+                hit.item -= 1;
+            }
+        }
+        self.content.selected = self
+            .content
+            .selected
+            .min(self.content.hits.len().saturating_sub(1));
+        self.apply_filter();
+        Ok(format!("Moved {} to the trash", removed.display))
+    }
+
+    fn new_item(&self, path: PathBuf) -> DiscoverItem {
+        let (root, relative) = owning_root(&self.root_paths, &path);
+        DiscoverItem {
+            display: self.display(&path),
+            frecency: self.history.frecency(&path, history::now()),
+            path,
+            root,
+            relative,
+            recent: None,
+        }
+    }
+
+    fn add_item(&mut self, path: PathBuf) {
+        self.known.insert(path.clone());
+        let item = self.new_item(path);
+        self.items.push(item);
+        self.apply_filter();
+        self.reselect(&RowKey::Item(self.items.len() - 1));
+    }
+
+    fn display(&self, path: &Path) -> String {
+        display_path(path, &self.cwd(), dirs::home_dir().as_deref())
+    }
+
+    fn switch_tab(&mut self) {
+        self.tab = match self.tab {
+            DiscoverTab::Files => DiscoverTab::Content,
+            DiscoverTab::Content => DiscoverTab::Files,
+        };
+        if self.tab == DiscoverTab::Content && self.content.query.is_empty() {
+            self.filter_mode = true;
+        }
+    }
+
+    fn query_mut(&mut self) -> &mut String {
+        match self.tab {
+            DiscoverTab::Files => &mut self.query,
+            DiscoverTab::Content => &mut self.content.query,
+        }
+    }
+
+    fn query_changed(&mut self) {
+        match self.tab {
+            DiscoverTab::Files => self.apply_filter(),
+            DiscoverTab::Content => self.content.pending_since = Some(Instant::now()),
+        }
+    }
+
+    fn list_len(&self) -> usize {
+        match self.tab {
+            DiscoverTab::Files if self.tree_view => self.tree_rows.len(),
+            DiscoverTab::Files => self.filtered.len(),
+            DiscoverTab::Content => self.content.hits.len(),
+        }
+    }
+
+    fn selected_mut(&mut self) -> &mut usize {
+        match self.tab {
+            DiscoverTab::Files => &mut self.selected,
+            DiscoverTab::Content => &mut self.content.selected,
+        }
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        let last = self.list_len().saturating_sub(1);
+        let selected = self.selected_mut();
+        *selected = selected.saturating_add_signed(delta).min(last);
+    }
+
+    fn current_pick(&self) -> Option<DiscoverPick> {
+        match self.tab {
+            DiscoverTab::Files => {
+                let RowKey::Item(idx) = self.selected_row_key()? else {
+                    return None;
+                };
+                let item = self.items.get(idx)?;
+                Some(DiscoverPick {
+                    path: item.path.clone(),
+                    line: None,
+                })
+            }
+            DiscoverTab::Content => {
+                let hit = self.content.hits.get(self.content.selected)?;
+                let item = self.items.get(hit.item)?;
+                Some(DiscoverPick {
+                    path: item.path.clone(),
+                    line: Some(hit.line),
+                })
+            }
+        }
+    }
+
+    /// Starts a debounced content search once typing pauses and collects
+    /// results from the worker.
+    fn poll_content_search(&mut self) {
+        if self
+            .content
+            .pending_since
+            .is_some_and(|since| since.elapsed() >= CONTENT_SEARCH_DEBOUNCE)
+        {
+            let files = self
+                .items
+                .iter()
+                .enumerate()
+                .map(|(idx, item)| (idx, item.path.clone()))
+                .collect();
+            self.content.start(files);
+        }
+        self.content.poll();
+    }
+
+    /// Fuzzy-matches the query against every path and ranks matches by
+    /// score plus a frecency bonus, so frequently opened files float up.
+    fn apply_filter(&mut self) {
+        let query = self.query.trim();
+        let mut ranked: Vec<(i64, usize, Vec<usize>)> = self
+            .items
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, item)| {
+                let (score, positions) = fuzzy_match(query, &item.display)?;
+                let bonus = (item.frecency.ln_1p() * FRECENCY_WEIGHT) as i64;
+                Some((score + bonus, idx, positions))
+            })
+            .collect();
+        // Without a query, recent files lead in most-recently-used order.
+        let recent = |idx: usize| match self.items[idx].recent {
+            Some(rank) if query.is_empty() => rank,
+            _ => usize::MAX,
+        };
+        ranked.sort_by(|a, b| {
+            recent(a.1)
+                .cmp(&recent(b.1))
+                .then(b.0.cmp(&a.0))
+                .then_with(|| self.items[a.1].display.cmp(&self.items[b.1].display))
+        });
+        self.recent_count = ranked
+            .iter()
+            .take_while(|(_, idx, _)| recent(*idx) != usize::MAX)
+            .count();
+        self.filtered = ranked.iter().map(|(_, idx, _)| *idx).collect();
+        self.positions = ranked.into_iter().map(|(_, _, positions)| positions).collect();
+        self.rebuild_tree();
+        let len = if self.tree_view {
+            self.tree_rows.len()
+        } else {
+            self.filtered.len()
+        };
+        if len == 0 {
+            self.selected = 0;
+            self.scroll = 0;
+        } else if self.selected >= len {
+            self.selected = len - 1;
+        }
+    }
+
+    fn selected_row_key(&self) -> Option<RowKey> {
+        if !self.tree_view {
+            return self.filtered.get(self.selected).map(|&idx| RowKey::Item(idx));
+        }
+        match self.tree_rows.get(self.selected)? {
+            TreeRow::Dir { root, dir, .. } => Some(RowKey::Dir(*root, dir.clone())),
+            TreeRow::File { rank, .. } => Some(RowKey::Item(self.filtered[*rank])),
+        }
+    }
+
+    fn reselect(&mut self, key: &RowKey) {
+        let pos = if self.tree_view {
+            self.tree_rows.iter().position(|row| match (row, key) {
+                (TreeRow::File { rank, .. }, RowKey::Item(idx)) => self.filtered[*rank] == *idx,
+                (TreeRow::Dir { root, dir, .. }, RowKey::Dir(key_root, key_dir)) => {
+                    root == key_root && dir == key_dir
+                }
+                _ => false,
+            })
+        } else {
+            match key {
+                RowKey::Item(idx) => self.filtered.iter().position(|i| i == idx),
+                RowKey::Dir(..) => None,
+            }
+        };
+        if let Some(pos) = pos {
+            self.selected = pos;
+        }
+    }
+
+    /// Rebuilds the tree from the filtered items. While a filter query is
+    /// active every directory is shown expanded so no match is hidden.
+    fn rebuild_tree(&mut self) {
+        self.tree_rows.clear();
+        if !self.tree_view {
+            return;
+        }
+        let mut roots: Vec<DirNode> = (0..self.roots.len()).map(|_| DirNode::default()).collect();
+        for (rank, &idx) in self.filtered.iter().enumerate() {
+            let item = &self.items[idx];
+            let Some(mut node) = item.root.and_then(|root| roots.get_mut(root)) else {
+                continue;
+            };
+            node.count += 1;
+            if let Some(parent) = item.relative.parent() {
+                for part in parent.iter() {
+                    node = node.dirs.entry(part.to_string_lossy().to_string()).or_default();
+                    node.count += 1;
+                }
+            }
+            node.files.push(rank);
+        }
+        let expand_all = !self.query.trim().is_empty();
+        for (root, node) in roots.iter().enumerate() {
+            if node.count > 0 {
+                self.push_tree_rows(root, PathBuf::new(), 0, node, expand_all);
+            }
+        }
+    }
+
+    fn push_tree_rows(
+        &mut self,
+        root: usize,
+        dir: PathBuf,
+        depth: usize,
+        node: &DirNode,
+        expand_all: bool,
+    ) {
+        let collapsed = !expand_all && self.collapsed.contains(&(root, dir.clone()));
+        self.tree_rows.push(TreeRow::Dir {
+            root,
+            dir: dir.clone(),
+            depth,
+            count: node.count,
+        });
+        if collapsed {
+            return;
+        }
+        for (name, child) in &node.dirs {
+            self.push_tree_rows(root, dir.join(name), depth + 1, child, expand_all);
+        }
+        for &rank in &node.files {
+            self.tree_rows.push(TreeRow::File {
+                rank,
+                depth: depth + 1,
+            });
+        }
+    }
+
+    fn selected_dir(&self) -> Option<(usize, PathBuf)> {
+        match self.tree_rows.get(self.selected)? {
+            TreeRow::Dir { root, dir, .. } => Some((*root, dir.clone())),
+            TreeRow::File { .. } => None,
+        }
+    }
+
+    fn is_expanded(&self, key: &(usize, PathBuf)) -> bool {
+        !self.collapsed.contains(key) || !self.query.trim().is_empty()
+    }
+
+    fn tree_toggle(&mut self) {
+        let Some(key) = self.selected_dir() else {
+            return;
+        };
+        if self.is_expanded(&key) {
+            self.tree_collapse();
+        } else {
+            self.tree_expand();
+        }
+    }
+
+    /// Collapses the selected directory, or moves to the parent directory
+    /// when the selection is a file or an already collapsed directory.
+    fn tree_collapse(&mut self) {
+        if let Some(key) = self.selected_dir()
+            && self.is_expanded(&key)
+            && self.query.trim().is_empty()
+        {
+            self.collapsed.insert(key);
+            self.rebuild_tree();
+            return;
+        }
+        let depth = match self.tree_rows.get(self.selected) {
+            Some(TreeRow::Dir { depth, .. } | TreeRow::File { depth, .. }) => *depth,
+            None => return,
+        };
+        if let Some(pos) = self.tree_rows[..self.selected]
+            .iter()
+            .rposition(|row| matches!(row, TreeRow::Dir { depth: d, .. } if *d < depth))
+        {
+            self.selected = pos;
+        }
+    }
+
+    /// Expands the selected directory, or steps into it if already open.
+    fn tree_expand(&mut self) {
+        let Some(key) = self.selected_dir() else {
+            return;
+        };
+        if self.is_expanded(&key) {
+            self.move_selection(1);
+            return;
+        }
+        self.collapsed.remove(&key);
+        self.rebuild_tree();
+    }
+}
+
+fn discover_ui(f: &mut ratatui::Frame, state: &mut DiscoverState, size: Rect) {
+    let vertical = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(1), Constraint::Length(1)])
+        .split(size);
+    let main = vertical[0];
+    let status = vertical[1];
+    let (main, preview_area) = if main.width >= PREVIEW_MIN_WIDTH {
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(45), Constraint::Percentage(55)])
+            .split(main);
+        (columns[0], Some(columns[1]))
+    } else {
+        (main, None)
+    };
+    state.preview.width = preview_area.map_or(0, |area| area.width.saturating_sub(2));
+
+    let content_height = main.height.saturating_sub(2).max(1) as usize;
+    let sections = state.tab == DiscoverTab::Files && !state.tree_view && state.recent_count > 0;
+    let (selected, scroll) = match state.tab {
+        DiscoverTab::Files => (state.selected, &mut state.scroll),
+        DiscoverTab::Content => (state.content.selected, &mut state.content.scroll),
+    };
+    // With sections, item rows sit below a "Recent" and a "Files" header.
+    let (selected, top) = if !sections {
+        (selected, selected)
+    } else if selected < state.recent_count {
+        (selected + 1, if selected == 0 { 0 } else { selected + 1 })
+    } else {
+        let line = selected + 2;
+        (line, if selected == state.recent_count { line - 1 } else { line })
+    };
+    if top < *scroll {
+        *scroll = top;
+    } else if selected >= *scroll + content_height {
+        *scroll = selected.saturating_sub(content_height.saturating_sub(1));
+    }
+    let scroll = *scroll;
+
+    let highlight_fg = state.ui.base_bg.unwrap_or(state.ui.base_fg);
+    let highlight = Style::default().bg(state.ui.accent).fg(highlight_fg);
+
+    let mut lines: Vec<Line> = match state.tab {
+        DiscoverTab::Files if !state.scanning && state.items.is_empty() => empty_lines(state),
+        DiscoverTab::Files if state.tree_view => state
+            .tree_rows
+            .iter()
+            .enumerate()
+            .map(|(idx, row)| tree_row_line(state, row, idx == state.selected, highlight))
+            .collect(),
+        DiscoverTab::Files => state
+            .filtered
+            .iter()
+            .enumerate()
+            .map(|(idx, item_idx)| {
+                let item = &state.items[*item_idx];
+                let style = if idx == state.selected {
+                    highlight
+                } else {
+                    state.base_style
+                };
+                let matched = if idx == state.selected {
+                    style.add_modifier(Modifier::BOLD | Modifier::UNDERLINED)
+                } else {
+                    style.fg(state.ui.accent).add_modifier(Modifier::BOLD)
+                };
+                highlight_positions(&item.display, &state.positions[idx], style, matched)
+            })
+            .collect::<Vec<_>>(),
+        DiscoverTab::Content => state
+            .content
+            .hits
+            .iter()
+            .enumerate()
+            .map(|(idx, hit)| content_hit_line(state, hit, idx == selected, highlight))
+            .collect(),
+    };
+    if sections {
+        let header = Style::default().fg(state.ui.muted).add_modifier(Modifier::BOLD);
+        lines.insert(state.recent_count, Line::from(Span::styled("Files", header)));
+        lines.insert(0, Line::from(Span::styled("Recent", header)));
+    }
+
+    let tab_style = |tab: DiscoverTab| {
+        if tab == state.tab {
+            Style::default().fg(state.ui.accent).add_modifier(Modifier::BOLD)
+        } else {
+            Style::default().fg(state.ui.muted)
+        }
+    };
+    let title = Line::from(vec![
+        Span::raw(" "),
+        Span::styled("Files", tab_style(DiscoverTab::Files)),
+        Span::styled(" | ", Style::default().fg(state.ui.border)),
+        Span::styled("Content", tab_style(DiscoverTab::Content)),
+        Span::raw(" "),
+    ]);
+
+    let paragraph = Paragraph::new(Text::from(lines))
+        .block(
+            Block::bordered()
+                .title(title)
+                .border_type(BorderType::Rounded)
+                .border_style(Style::default().fg(state.ui.border))
+                .style(state.base_style),
+        )
+        .style(state.base_style)
+        .scroll((scroll as u16, 0));
+    f.render_widget(paragraph, main);
+    if let Some(area) = preview_area {
+        render_preview(f, state, area);
+    }
+
+    if state.show_help {
+        let popup = centered_rect(70, 60, main);
+        f.render_widget(Clear, popup);
+        let help_lines = vec![
+            Line::from(Span::styled("Discover Help", Style::default().fg(state.ui.accent).add_modifier(Modifier::BOLD))),
+            Line::from(""),
+            Line::from("j/k or arrows: move selection"),
+            Line::from("PageUp/PageDown: scroll"),
+            Line::from("Enter: open file"),
+            Line::from("q or Esc: quit"),
+            Line::from("/ : fuzzy filter (frequent and recent files rank first)"),
+            Line::from("t: toggle tree view (h/l collapse/expand, Enter toggles a directory)"),
+            Line::from("n new file, r rename/move, c duplicate, d delete to trash"),
+            Line::from("Tab: switch between file names and file contents"),
+            Line::from("?: toggle help"),
+            Line::from(""),
+            Line::from("Filter mode: type to filter, Backspace delete, Ctrl+U clear, Enter/Esc exit"),
+            Line::from("Content tab: matches every line, Enter opens the file at that line"),
+        ];
+        let help = Paragraph::new(Text::from(help_lines))
+            .block(
+                Block::bordered()
+                    .title(" Help ")
+                    .border_type(BorderType::Rounded)
+                    .border_style(Style::default().fg(state.ui.border))
+                    .style(state.base_style),
+            )
+            .style(state.base_style);
+        f.render_widget(help, popup);
+    }
+
+    let status_text = state.status.clone().or_else(|| prompt_text(state)).unwrap_or_else(|| {
+        if state.tab == DiscoverTab::Content {
+            return content_status(state);
+        }
+        let filter_hint = if state.filter_mode {
+            "filter: ".to_string() + &state.query
+        } else if state.query.is_empty() {
+            "/ filter".to_string()
+        } else {
+            format!("filter: {}", state.query)
+        };
+        format!(
+            "{} | {} matches | {} | j/k move | Enter open | q quit",
+            scan_progress(state),
+            state.filtered.len(),
+            filter_hint
+        )
+    });
+    f.render_widget(
+        Paragraph::new(Line::from(vec![
+            Span::styled("mark", Style::default().fg(state.ui.accent).add_modifier(Modifier::BOLD)),
+            Span::styled(" | ", Style::default().fg(state.ui.muted)),
+            Span::styled(status_text, state.base_style),
+        ]))
+        .style(state.base_style)
+        .block(Block::default().style(state.base_style)),
+        status,
+    );
+}
+
+/// fzf-style fuzzy match: every query character must appear in order. The
+/// tightest window ending at the first complete match is scored, rewarding
+/// word boundaries and consecutive runs and penalizing gaps. Smart-case.
+/// Returns the score and the matched char indices.
+fn fuzzy_match(query: &str, text: &str) -> Option<(i64, Vec<usize>)> {
+    let case_sensitive = query.chars().any(char::is_uppercase);
+    let fold = |c: char| {
+        if case_sensitive {
+            c
+        } else {
+            c.to_lowercase().next().unwrap_or(c)
+        }
+    };
+    let query: Vec<char> = query.chars().filter(|c| !c.is_whitespace()).map(fold).collect();
+    if query.is_empty() {
+        return Some((0, Vec::new()));
+    }
+    let chars: Vec<char> = text.chars().collect();
+    let folded: Vec<char> = chars.iter().map(|&c| fold(c)).collect();
+
+    let mut next = 0;
+    let mut end = None;
+    for (idx, &c) in folded.iter().enumerate() {
+        if c == query[next] {
+            next += 1;
+            if next == query.len() {
+                end = Some(idx);
+                break;
+            }
+        }
+    }
+    let end = end?;
+    let mut remaining = query.len();
+    let mut start = end;
+    for idx in (0..=end).rev() {
+        if folded[idx] == query[remaining - 1] {
+            remaining -= 1;
+            if remaining == 0 {
+                start = idx;
+                break;
+            }
+        }
+    }
+
+    let mut positions = Vec::with_capacity(query.len());
+    let mut score = 0;
+    let mut prev_matched = false;
+    let mut in_gap = false;
+    let mut chunk_bonus = 0;
+    for (idx, &c) in folded.iter().enumerate().take(end + 1).skip(start) {
+        if positions.len() < query.len() && c == query[positions.len()] {
+            // Characters in a consecutive run share the bonus of its first one.
+            let mut bonus = boundary_bonus(&chars, idx);
+            if prev_matched {
+                bonus = bonus.max(chunk_bonus).max(BONUS_CONSECUTIVE);
+            } else {
+                chunk_bonus = bonus;
+            }
+            if positions.is_empty() {
+                bonus *= 2;
+            }
+            score += SCORE_MATCH + bonus;
+            positions.push(idx);
+            prev_matched = true;
+            in_gap = false;
+        } else {
+            score -= if in_gap {
+                SCORE_GAP_EXTENSION
+            } else {
+                SCORE_GAP_START
+            };
+            prev_matched = false;
+            in_gap = true;
+        }
+    }
+    Some((score, positions))
+}
+
+fn boundary_bonus(chars: &[char], idx: usize) -> i64 {
+    let Some(&prev) = idx.checked_sub(1).and_then(|i| chars.get(i)) else {
+        return BONUS_BOUNDARY;
+    };
+    let c = chars[idx];
+    if prev == '/' || prev == '\\' {
+        BONUS_PATH_SEPARATOR
+    } else if !prev.is_alphanumeric() {
+        BONUS_BOUNDARY
+    } else if prev.is_lowercase() && c.is_uppercase() {
+        BONUS_CAMEL
+    } else {
+        0
+    }
+}
+
+fn highlight_positions(
+    text: &str,
+    positions: &[usize],
+    style: Style,
+    matched: Style,
+) -> Line<'static> {
+    let mut spans = Vec::new();
+    let mut run = String::new();
+    let mut run_matched = false;
+    let mut positions = positions.iter().peekable();
+    for (idx, c) in text.chars().enumerate() {
+        let is_match = positions.next_if_eq(&&idx).is_some();
+        if is_match != run_matched && !run.is_empty() {
+            let run_style = if run_matched { matched } else { style };
+            spans.push(Span::styled(std::mem::take(&mut run), run_style));
+        }
+        run_matched = is_match;
+        run.push(c);
+    }
+    if !run.is_empty() {
+        spans.push(Span::styled(run, if run_matched { matched } else { style }));
+    }
+    Line::from(spans)
+}
+
+fn tree_row_line(
+    state: &DiscoverState,
+    row: &TreeRow,
+    selected: bool,
+    highlight: Style,
+) -> Line<'static> {
+    let style = if selected { highlight } else { state.base_style };
+    match row {
+        TreeRow::Dir {
+            root,
+            dir,
+            depth,
+            count,
+        } => {
+            let marker = if state.is_expanded(&(*root, dir.clone())) {
+                "▾"
+            } else {
+                "▸"
+            };
+            let name = match dir.file_name() {
+                Some(name) => format!("{}/", name.to_string_lossy()),
+                None => state.roots.get(*root).cloned().unwrap_or_default(),
+            };
+            let name_style = if *depth == 0 && !selected {
+                style.fg(state.ui.accent).add_modifier(Modifier::BOLD)
+            } else {
+                style
+            };
+            let count_style = if selected {
+                style
+            } else {
+                Style::default().fg(state.ui.muted)
+            };
+            Line::from(vec![
+                Span::styled(format!("{}{marker} ", "  ".repeat(*depth)), style),
+                Span::styled(name, name_style),
+                Span::styled(format!(" ({count})"), count_style),
+            ])
+        }
+        TreeRow::File { rank, depth } => {
+            let item = &state.items[state.filtered[*rank]];
+            let name = item
+                .relative
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| item.display.clone());
+            // Fuzzy positions index the full display path; keep the file name's.
+            let offset = item.display.chars().count().saturating_sub(name.chars().count());
+            let positions: Vec<usize> = state.positions[*rank]
+                .iter()
+                .filter_map(|pos| pos.checked_sub(offset))
+                .collect();
+            let matched = if selected {
+                style.add_modifier(Modifier::BOLD | Modifier::UNDERLINED)
+            } else {
+                style.fg(state.ui.accent).add_modifier(Modifier::BOLD)
+            };
+            let mut line = highlight_positions(&name, &positions, style, matched);
+            line.spans
+                .insert(0, Span::styled("  ".repeat(depth + 1), style));
+            line
+        }
+    }
+}
+
+fn content_hit_line<'a>(
+    state: &DiscoverState,
+    hit: &'a ContentHit,
+    selected: bool,
+    highlight: Style,
+) -> Line<'a> {
+    let Some(item) = state.items.get(hit.item) else {
+        return Line::default();
+    };
+    let location = format!("{}:{}: ", item.display, hit.line + 1);
+    if selected {
+        return Line::from(Span::styled(location + &hit.snippet, highlight));
+    }
+    let range = hit.highlight.clone();
+    Line::from(vec![
+        Span::styled(location, Style::default().fg(state.ui.muted)),
+        Span::styled(&hit.snippet[..range.start], state.base_style),
+        Span::styled(
+            &hit.snippet[range.clone()],
+            Style::default().fg(state.ui.accent).add_modifier(Modifier::BOLD),
+        ),
+        Span::styled(&hit.snippet[range.end..], state.base_style),
+    ])
+}
+
+fn content_status(state: &DiscoverState) -> String {
+    let content = &state.content;
+    let query = if content.query.is_empty() && !state.filter_mode {
+        "/ search contents".to_string()
+    } else {
+        format!("grep: {}", content.query)
+    };
+    let progress = if content.running || content.pending_since.is_some() {
+        format!("searching {}/{}", content.scanned, state.items.len())
+    } else if content.hits.len() >= MAX_CONTENT_HITS {
+        format!("first {MAX_CONTENT_HITS} matches")
+    } else {
+        format!("{} matches", content.hits.len())
+    };
+    format!("{progress} | {query} | Tab files | Enter open | q quit")
+}
+
+fn render_preview(f: &mut ratatui::Frame, state: &DiscoverState, area: Rect) {
+    let muted = Style::default().fg(state.ui.muted);
+    let pick = state.current_pick();
+    let preview = pick.as_ref().and_then(|pick| state.preview.get(&pick.path));
+    let mut title = vec![Span::raw(" Preview ")];
+    if let Some(modified) = preview.and_then(|preview| preview.modified) {
+        title.push(Span::styled(format!("modified {} ", format_age(modified)), muted));
+    }
+    let block = Block::bordered()
+        .title(Line::from(title))
+        .border_type(BorderType::Rounded)
+        .border_style(Style::default().fg(state.ui.border))
+        .style(state.base_style);
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+
+    let Some(preview) = preview else {
+        let text = if pick.is_some() { "Loading…" } else { "" };
+        f.render_widget(Paragraph::new(Span::styled(text, muted)), inner);
+        return;
+    };
+    let rendered = &preview.rendered;
+    let mut lines: Vec<Line> = Vec::new();
+    let headings = &rendered.headings;
+    if !headings.is_empty() {
+        for heading in headings.iter().take(OUTLINE_ROWS) {
+            let indent = "  ".repeat(heading.level.saturating_sub(1) as usize);
+            let style = if heading.level <= 2 {
+                Style::default().fg(state.ui.accent)
+            } else {
+                muted
+            };
+            lines.push(Line::from(Span::styled(format!("{indent}{}", heading.title), style)));
+        }
+        if headings.len() > OUTLINE_ROWS {
+            let more = format!("… {} more headings", headings.len() - OUTLINE_ROWS);
+            lines.push(Line::from(Span::styled(more, muted)));
+        }
+        let rule = "─".repeat(inner.width as usize);
+        lines.push(Line::from(Span::styled(rule, Style::default().fg(state.ui.border))));
+    }
+
+    // Content hits scroll the document so the matching line is in view.
+    let start = pick
+        .and_then(|pick| pick.line)
+        .and_then(|line| preview.line_starts.get(line))
+        .and_then(|&offset| rendered.locate_source(offset))
+        .map_or(0, |(line, _)| line.saturating_sub(2));
+    let room = (inner.height as usize).saturating_sub(lines.len());
+    lines.extend(rendered.lines.iter().skip(start).take(room).cloned());
+    f.render_widget(Paragraph::new(Text::from(lines)).style(state.base_style), inner);
+}
+
+/// Char offset of the start of every source line.
+fn line_char_offsets(text: &str) -> Vec<usize> {
+    let mut offset = 0;
+    let mut starts = vec![0];
+    for line in text.split_inclusive('\n') {
+        offset += line.chars().count();
+        starts.push(offset);
+    }
+    starts
+}
+
+fn format_age(modified: SystemTime) -> String {
+    let secs = SystemTime::now()
+        .duration_since(modified)
+        .map(|age| age.as_secs())
+        .unwrap_or(0);
+    match secs {
+        0..60 => "just now".to_string(),
+        60..3600 => format!("{} min ago", secs / 60),
+        3600..86400 => format!("{} h ago", secs / 3600),
+        86400..2592000 => format!("{} days ago", secs / 86400),
+        2592000..31536000 => format!("{} months ago", secs / 2592000),
+        _ => format!("{} years ago", secs / 31536000),
+    }
+}
+
+fn prompt_text(state: &DiscoverState) -> Option<String> {
+    let prompt = state.prompt.as_ref()?;
+    let label = match prompt.action {
+        FileAction::New => "New file",
+        FileAction::Rename => "Move to",
+        FileAction::Duplicate => "Copy to",
+        FileAction::Delete => {
+            let item = state.items.get(prompt.item?)?;
+            return Some(format!("Move {} to the trash? (y/n)", item.display));
+        }
+    };
+    Some(format!("{label}: {}_ (Enter confirm, Esc cancel)", prompt.input))
+}
+
+fn scan_progress(state: &DiscoverState) -> String {
+    if state.scanning {
+        let spinner = SPINNER[state.ticks % SPINNER.len()];
+        format!("{spinner} scanning {} files", state.items.len())
+    } else {
+        format!("{} files", state.items.len())
+    }
+}
+
+fn empty_lines(state: &DiscoverState) -> Vec<Line<'static>> {
+    let muted = Style::default().fg(state.ui.muted);
+    let mut lines = vec![
+        Line::from(""),
+        Line::from(Span::styled(
+            "No markdown files under",
+            Style::default().fg(state.ui.accent).add_modifier(Modifier::BOLD),
+        )),
+    ];
+    lines.extend(
+        state
+            .roots
+            .iter()
+            .map(|root| Line::from(Span::styled(format!("  {root}"), state.base_style))),
+    );
+    lines.push(Line::from(""));
+    lines.push(Line::from(Span::styled(
+        "Add directories with forced_discover_dirs (mark config), or press q to quit",
+        muted,
+    )));
+    lines
+}
+
+/// Picks the deepest root containing `path`, so files under a forced
+/// `./.claude` group there rather than under the working directory.
+fn owning_root(roots: &[PathBuf], path: &Path) -> (Option<usize>, PathBuf) {
+    roots
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, root)| Some((idx, root, path.strip_prefix(root).ok()?)))
+        .max_by_key(|(_, root, _)| root.components().count())
+        .map(|(idx, _, rel)| (Some(idx), rel.to_path_buf()))
+        .unwrap_or_else(|| (None, path.to_path_buf()))
+}
+
+/// Suggests a sibling name for a duplicate: `notes.md` becomes `notes-copy.md`.
+fn copy_name(display: &str) -> String {
+    let path = Path::new(display);
+    let Some(stem) = path.file_stem() else {
+        return display.to_string();
+    };
+    let ext = path
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    let name = format!("{}-copy{ext}", stem.to_string_lossy());
+    let parent = &display[..display.len() - path.file_name().map_or(0, |n| n.len())];
+    format!("{parent}{name}")
+}
+
+/// Contents for a new file: the configured template with `{title}` and
+/// `{date}` filled in, or a bare title heading.
+fn new_file_text(template: &str, path: &Path) -> Result<String> {
+    let title = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    if template.trim().is_empty() {
+        return Ok(format!("# {title}\n"));
+    }
+    let cwd = env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    let template_path = expand_discover_dir(Path::new(template), &cwd, dirs::home_dir().as_deref());
+    let text = fs::read_to_string(&template_path)
+        .with_context(|| format!("Failed to read template {}", template_path.display()))?;
+    Ok(text
+        .replace("{title}", &title)
+        .replace("{date}", &format_date(history::now())))
+}
+
+/// Formats a unix timestamp as a UTC `YYYY-MM-DD` date.
+fn format_date(secs: u64) -> String {
+    // Civil-from-days conversion (Howard Hinnant's algorithm).
+    let z = (secs / 86400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+fn discover_roots(config: &Config) -> Vec<PathBuf> {
+    let cwd = env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    let home = dirs::home_dir();
+    let mut roots = Vec::new();
+    roots.push(cwd.clone());
+    for path in &config.forced_discover_dirs {
+        roots.push(expand_discover_dir(path, &cwd, home.as_deref()));
+    }
+    roots
+}
+
+fn spawn_scan(roots: Vec<PathBuf>, config: &Config) -> Receiver<ScanMessage> {
+    let (sender, receiver) = mpsc::channel();
+    let config = config.clone();
+    thread::spawn(move || scan_markdown_files(&roots, &config, &sender));
+    receiver
+}
+
+/// Walks every root and streams markdown files in small batches. Stops early
+/// once discover is closed and the receiver is gone.
+fn scan_markdown_files(roots: &[PathBuf], config: &Config, sender: &Sender<ScanMessage>) {
+    let cwd = env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    let home = dirs::home_dir();
+    let mut seen = HashSet::new();
+    let mut batch = Vec::new();
+    let mut last_sent = Instant::now();
+    for root in roots {
+        if !root.exists() {
+            continue;
+        }
+        for entry in markdown_walker(root, config) {
+            let entry = match entry {
+                Ok(e) => e,
+                Err(_) => continue,
+            };
+            if !entry.file_type().is_some_and(|kind| kind.is_file()) {
+                continue;
+            }
+            let path = entry.path();
+            if !is_markdown_file(path) {
+                continue;
+            }
+            let path = path.to_path_buf();
+            if !seen.insert(path.clone()) {
+                continue;
+            }
+            let display = display_path(&path, &cwd, home.as_deref());
+            let (root, relative) = owning_root(roots, &path);
+            batch.push(DiscoverItem {
+                path,
+                display,
+                frecency: 0.0,
+                root,
+                relative,
+                recent: None,
+            });
+            if last_sent.elapsed() >= SCAN_BATCH_INTERVAL {
+                if sender.send(ScanMessage::Items(std::mem::take(&mut batch))).is_err() {
+                    return;
+                }
+                last_sent = Instant::now();
+            }
+        }
+    }
+    if !batch.is_empty() {
+        let _ = sender.send(ScanMessage::Items(batch));
+    }
+    let _ = sender.send(ScanMessage::Done);
+}
+
+/// Walks `root` honoring `.gitignore`/`.ignore` files, the `discover_exclude`
+/// globs and the depth and symlink settings. Hidden files are still visited
+/// so dot-directories such as `.claude` show up unless excluded.
+fn markdown_walker(root: &Path, config: &Config) -> ignore::Walk {
+    let mut overrides = OverrideBuilder::new(root);
+    for glob in &config.discover_exclude {
+        let _ = overrides.add(&format!("!{glob}"));
+    }
+    let mut builder = WalkBuilder::new(root);
+    builder
+        .hidden(false)
+        .require_git(false)
+        .follow_links(config.follow_links)
+        .max_depth((config.max_depth > 0).then_some(config.max_depth));
+    if let Ok(overrides) = overrides.build() {
+        builder.overrides(overrides);
+    }
+    builder.build()
+}
+
+pub fn is_markdown_file(path: &Path) -> bool {
+    let Some(ext) = path.extension().and_then(|s| s.to_str()) else {
+        return false;
+    };
+    matches!(
+        ext.to_ascii_lowercase().as_str(),
+        "md" | "markdown" | "mdx"
+    )
+}
+
+fn expand_discover_dir(path: &Path, cwd: &Path, home: Option<&Path>) -> PathBuf {
+    let raw = path.to_string_lossy();
+    if raw == "~"
+        && let Some(home) = home
+    {
+        return home.to_path_buf();
+    }
+    if let Some(rest) = raw.strip_prefix("~/")
+        && let Some(home) = home
+    {
+        return home.join(rest);
+    }
+    if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        cwd.join(path)
+    }
+}
+
+fn display_root(root: &Path) -> String {
+    let home = dirs::home_dir();
+    if let Some(home) = home.as_deref()
+        && let Ok(rel) = root.strip_prefix(home)
+    {
+        let rel_str = rel.display().to_string();
+        return if rel_str.is_empty() {
+            "~".to_string()
+        } else {
+            format!("~/{rel_str}")
+        };
+    }
+    root.display().to_string()
+}
+
+fn display_path(path: &Path, cwd: &Path, home: Option<&Path>) -> String {
+    if let Ok(rel) = path.strip_prefix(cwd) {
+        let rel_str = rel.display().to_string();
+        return if rel_str.is_empty() {
+            "./".to_string()
+        } else {
+            format!("./{rel_str}")
+        };
+    }
+    if let Some(home) = home
+        && let Ok(rel) = path.strip_prefix(home)
+    {
+        let rel_str = rel.display().to_string();
+        return if rel_str.is_empty() {
+            "~".to_string()
+        } else {
+            format!("~/{rel_str}")
+        };
+    }
+    path.display().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        content_pattern, copy_name, format_age, format_date, fuzzy_match, line_char_offsets,
+        markdown_walker, scan_markdown_files, search_contents, snippet, ContentHit,
+        ContentSearch, DiscoverItem, DiscoverState, PreviewCache, ScanMessage, SearchBatch,
+        TreeRow, PREVIEW_DEBOUNCE,
+    };
+    use crate::config::Config;
+    use crate::history::History;
+    use crate::test_support::{palette, TempDir};
+    use crate::theme::{styles_from_palette, ThemeManager};
+    use std::fs;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::{Duration, Instant, SystemTime};
+
+    #[test]
+    fn content_search_reports_lines_and_snippets() {
+        let dir = TempDir::new("grep");
+        let first = dir.join("a.md");
+        let second = dir.join("b.md");
+        fs::write(&first, "# Title\n\n\tSee the Needle here\n").unwrap();
+        fs::write(&second, "nothing\nneedle again\n").unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let pattern = content_pattern("needle").unwrap();
+        let files = vec![(0, first), (1, second)];
+        search_contents(files, &pattern, 1, &AtomicU64::new(1), &tx);
+        drop(tx);
+        let hits: Vec<_> = rx.iter().flat_map(|batch| batch.hits).collect();
+
+        let found: Vec<_> = hits.iter().map(|hit| (hit.item, hit.line)).collect();
+        assert_eq!(found, vec![(0, 2), (1, 1)]);
+        assert_eq!(hits[0].snippet, "See the Needle here");
+        assert_eq!(&hits[0].snippet[hits[0].highlight.clone()], "Needle");
+        assert!(content_pattern("Needle").unwrap().find("needle").is_none());
+
+        let long = format!("{}ünïcode match", "é".repeat(60));
+        let (text, range) = snippet(&long, long.find("match").unwrap()..long.len());
+        assert!(text.starts_with('…'));
+        assert_eq!(&text[range], "match");
+    }
+
+    #[test]
+    fn restarted_content_search_drops_stale_batches() {
+        let mut search = ContentSearch::new();
+        search.running = true;
+        let generation = search.generation.load(Ordering::SeqCst);
+        search.restart();
+        let hit = ContentHit {
+            item: 5,
+            line: 0,
+            snippet: String::new(),
+            highlight: 0..0,
+        };
+        let batch = SearchBatch {
+            generation,
+            hits: vec![hit],
+            scanned: 1,
+            done: true,
+        };
+        search.sender.send(batch).unwrap();
+        search.poll();
+        assert!(search.hits.is_empty());
+        assert!(search.pending_since.is_some());
+    }
+
+    #[test]
+    fn fuzzy_match_prefers_boundaries_and_tight_runs() {
+        let (_, positions) = fuzzy_match("rdme", "./docs/README.md").unwrap();
+        assert_eq!(positions, vec![7, 10, 11, 12]);
+        assert!(fuzzy_match("xyz", "./docs/README.md").is_none());
+        assert!(fuzzy_match("Readme", "./readme.md").is_none());
+
+        let score = |query, text| fuzzy_match(query, text).unwrap().0;
+        assert!(score("notes", "./notes.md") > score("notes", "./not-essential.md"));
+        assert!(score("cfg", "./src/cfg.md") > score("cfg", "./scaffolding.md"));
+        assert_eq!(fuzzy_match("", "./a.md"), Some((0, Vec::new())));
+    }
+
+    #[test]
+    fn walker_honors_ignore_files_excludes_and_depth() {
+        let root = TempDir::new("walk");
+        for dir in ["docs/deep/er", "node_modules/pkg", "build", ".claude"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        fs::write(root.join(".gitignore"), "build/\n").unwrap();
+        fs::write(root.join(".ignore"), "*.draft.md\n").unwrap();
+        for file in [
+            "README.md",
+            "notes.draft.md",
+            "docs/guide.md",
+            "docs/deep/er/nested.md",
+            "node_modules/pkg/README.md",
+            "build/out.md",
+            ".claude/CLAUDE.md",
+        ] {
+            fs::write(root.join(file), "# doc\n").unwrap();
+        }
+
+        let collect = |config: &Config| {
+            let mut found: Vec<String> = markdown_walker(&root, config)
+                .filter_map(Result::ok)
+                .filter(|entry| entry.file_type().is_some_and(|kind| kind.is_file()))
+                .filter_map(|entry| {
+                    let rel = entry.path().strip_prefix(&root).ok()?;
+                    let rel = rel.to_string_lossy().replace('\\', "/");
+                    rel.ends_with(".md").then_some(rel)
+                })
+                .collect();
+            found.sort();
+            found
+        };
+        let mut config = Config::default();
+        let all = collect(&config);
+        config.max_depth = 2;
+        let shallow = collect(&config);
+
+        assert_eq!(
+            all,
+            vec![".claude/CLAUDE.md", "README.md", "docs/deep/er/nested.md", "docs/guide.md"]
+        );
+        assert_eq!(shallow, vec![".claude/CLAUDE.md", "README.md", "docs/guide.md"]);
+    }
+
+    #[test]
+    fn scan_streams_each_file_once_then_finishes() {
+        let root = TempDir::new("scan");
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("README.md"), "# a\n").unwrap();
+        fs::write(root.join("docs/guide.md"), "# b\n").unwrap();
+        fs::write(root.join("docs/notes.txt"), "c\n").unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let roots = vec![root.to_path_buf(), root.join("docs"), root.join("missing")];
+        scan_markdown_files(&roots, &Config::default(), &tx);
+        drop(tx);
+        let messages: Vec<ScanMessage> = rx.iter().collect();
+
+        assert!(matches!(messages.last(), Some(ScanMessage::Done)));
+        let mut names: Vec<String> = messages
+            .iter()
+            .filter_map(|message| match message {
+                ScanMessage::Items(items) => Some(items),
+                ScanMessage::Done => None,
+            })
+            .flatten()
+            .map(|item| item.path.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["README.md", "guide.md"]);
+    }
+
+    #[test]
+    fn preview_helpers_map_lines_and_ages() {
+        assert_eq!(line_char_offsets("# é\n\nbody"), vec![0, 4, 5, 9]);
+        let now = SystemTime::now();
+        assert_eq!(format_age(now), "just now");
+        assert_eq!(format_age(now - Duration::from_secs(3 * 3600)), "3 h ago");
+        assert_eq!(format_age(now - Duration::from_secs(5 * 86400)), "5 days ago");
+    }
+
+    #[test]
+    fn previews_load_after_a_pause_and_reload_edited_files() {
+        let dir = TempDir::new("preview");
+        let file = dir.join("a.md");
+        fs::write(&file, "# One\n").unwrap();
+        let config = Config::default();
+        let themes = ThemeManager::load(&config).unwrap();
+        let (_, styles) = styles_from_palette(palette());
+        let mut cache = PreviewCache::new();
+        cache.width = 40;
+        let target = Some(file.clone());
+        let update = |cache: &mut PreviewCache| {
+            cache.update(target.clone(), &config, &themes, &styles);
+        };
+        let wait_for_worker = |cache: &mut PreviewCache| {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while cache.loading.is_some() && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(5));
+                update(cache);
+            }
+        };
+        let text = |cache: &PreviewCache| cache.get(&file).unwrap().rendered.plain_lines.join("\n");
+
+        update(&mut cache);
+        update(&mut cache);
+        assert!(cache.loading.is_none(), "waits for the selection to rest");
+        thread::sleep(PREVIEW_DEBOUNCE);
+        update(&mut cache);
+        assert_eq!(cache.loading.as_ref(), Some(&file));
+        wait_for_worker(&mut cache);
+        assert!(text(&cache).contains("One"));
+
+        fs::write(&file, "# Two\n").unwrap();
+        let later = SystemTime::now() + Duration::from_secs(5);
+        fs::File::options().write(true).open(&file).unwrap().set_modified(later).unwrap();
+        update(&mut cache);
+        assert_eq!(cache.loading.as_ref(), Some(&file));
+        wait_for_worker(&mut cache);
+        assert!(text(&cache).contains("Two"));
+    }
+
+    #[test]
+    fn tree_view_groups_by_root_and_collapses_directories() {
+        let (_tx, rx) = mpsc::channel();
+        let roots = vec!["/r0".into(), "/r1".into()];
+        let style = ratatui::style::Style::default();
+        let mut state = DiscoverState::new(rx, roots, History::default(), palette(), style);
+        for (root, relative) in [(0, "a.md"), (0, "docs/x.md"), (0, "docs/y.md"), (1, "n/z.md")] {
+            state.items.push(DiscoverItem {
+                path: format!("/r{root}/{relative}").into(),
+                display: format!("./{relative}"),
+                frecency: 0.0,
+                root: Some(root),
+                relative: relative.into(),
+                recent: None,
+            });
+        }
+        state.tree_view = true;
+        state.apply_filter();
+        let shape = |state: &DiscoverState| -> Vec<(usize, usize)> {
+            state
+                .tree_rows
+                .iter()
+                .map(|row| match row {
+                    TreeRow::Dir { depth, count, .. } => (*depth, *count),
+                    TreeRow::File { depth, .. } => (*depth, 0),
+                })
+                .collect()
+        };
+        assert_eq!(
+            shape(&state),
+            vec![(0, 3), (1, 2), (2, 0), (2, 0), (1, 0), (0, 1), (1, 1), (2, 0)]
+        );
+
+        state.selected = 2;
+        state.tree_collapse();
+        assert_eq!(state.selected, 1);
+        state.tree_collapse();
+        assert_eq!(shape(&state), vec![(0, 3), (1, 2), (1, 0), (0, 1), (1, 1), (2, 0)]);
+        state.tree_expand();
+        assert_eq!(state.tree_rows.len(), 8);
+        state.selected = 4;
+        assert_eq!(state.current_pick().unwrap().path, std::path::Path::new("/r0/a.md"));
+    }
+
+    #[test]
+    fn file_actions_update_items_in_place() {
+        let root = TempDir::new("fileops");
+        let template = root.join("template.txt");
+        fs::write(&template, "# {title}\n\ncreated {date}\n").unwrap();
+        let (_tx, rx) = mpsc::channel();
+        let style = ratatui::style::Style::default();
+        let roots = vec![root.to_path_buf()];
+        let mut state = DiscoverState::new(rx, roots, History::default(), palette(), style);
+        state.new_file_template = template.display().to_string();
+
+        let new_input = root.join("notes/today").display().to_string();
+        state.create_file(&new_input).unwrap();
+        let created = root.join("notes/today.md");
+        let text = fs::read_to_string(&created).unwrap();
+        assert!(text.starts_with("# today\n\ncreated 2"));
+        assert_eq!(state.items.len(), 1);
+        assert_eq!(state.items[0].relative, std::path::Path::new("notes/today.md"));
+        assert!(state.create_file(&new_input).is_err());
+
+        let moved = root.join("archive/old.md");
+        state.rename_item(0, &moved.display().to_string()).unwrap();
+        assert!(!created.exists() && moved.exists());
+        assert_eq!(state.items[0].path, moved);
+
+        state.duplicate_item(0, &root.join("copy.md").display().to_string()).unwrap();
+        assert_eq!(state.items.len(), 2);
+        assert_eq!(state.current_pick().unwrap().path, root.join("copy.md"));
+
+        assert_eq!(copy_name("./docs/notes.md"), "./docs/notes-copy.md");
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(1_700_000_000), "2023-11-14");
+    }
+}
@@ -0,0 +1,280 @@
+use crate::config::Config;
+use crate::graphics;
+use crate::markdown::{resolve_code_syntax, slugify_anchor};
+use crate::theme::{ThemeManager, UiPalette};
+use anyhow::{Context, Result};
+use base64::Engine;
+use pulldown_cmark::{html, CodeBlockKind, CowStr, Event, Options, Parser, Tag, TagEnd};
+use ratatui::style::Color;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use syntect::highlighting::Theme;
+use syntect::html::highlighted_html_for_string;
+use syntect::parsing::SyntaxSet;
+
+pub fn export_html(path: &Path, output: Option<&Path>, config: &Config) -> Result<()> {
+    let input = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let theme_manager = ThemeManager::load(config)?;
+    let theme_name = if theme_manager.theme_names().iter().any(|t| t == &config.theme) {
+        config.theme.as_str()
+    } else {
+        theme_manager.fallback_name()
+    };
+    let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let title = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "mark".to_string());
+
+    let page = render_html(
+        &input,
+        &title,
+        base_dir,
+        theme_manager.syntax_set(),
+        theme_manager.get(theme_name),
+        theme_manager.ui_palette(theme_name),
+    )?;
+
+    match output {
+        Some(out) => fs::write(out, page)
+            .with_context(|| format!("Failed to write {}", out.display()))?,
+        None => print!("{page}"),
+    }
+    Ok(())
+}
+
+fn render_html(
+    input: &str,
+    title: &str,
+    base_dir: &Path,
+    syntax_set: &SyntaxSet,
+    theme: &Theme,
+    ui: UiPalette,
+) -> Result<String> {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options.insert(Options::ENABLE_TASKLISTS);
+    options.insert(Options::ENABLE_FOOTNOTES);
+
+    let mut events: Vec<Event> = Vec::new();
+    let mut slug_counts: HashMap<String, usize> = HashMap::new();
+    let mut heading: Option<(Tag, Vec<Event>, String)> = None;
+    let mut code: Option<(Option<String>, String)> = None;
+
+    for event in Parser::new_ext(input, options) {
+        if let Some((_, text)) = code.as_mut() {
+            match event {
+                Event::Text(chunk) => text.push_str(&chunk),
+                Event::End(TagEnd::CodeBlock) => {
+                    let (lang, text) = code.take().unwrap_or_default();
+                    let syntax = resolve_code_syntax(syntax_set, lang.as_deref());
+                    let block = highlighted_html_for_string(&text, syntax_set, syntax, theme)
+                        .context("Failed to highlight code block")?;
+                    events.push(Event::Html(block.into()));
+                }
+                _ => {}
+            }
+            continue;
+        }
+
+        if let Some((_, inner, text)) = heading.as_mut() {
+            match event {
+                Event::End(TagEnd::Heading(_)) => {
+                    let Some((start, inner, text)) = heading.take() else {
+                        continue;
+                    };
+                    events.push(with_heading_id(start, &text, &mut slug_counts));
+                    events.extend(inner);
+                    events.push(event);
+                }
+                event => {
+                    if let Event::Text(chunk) | Event::Code(chunk) = &event {
+                        text.push_str(chunk);
+                    }
+                    inner.push(event);
+                }
+            }
+            continue;
+        }
+
+        match event {
+            Event::Start(Tag::Heading { .. }) => {
+                if let Event::Start(tag) = event {
+                    heading = Some((tag, Vec::new(), String::new()));
+                }
+            }
+            Event::Start(Tag::CodeBlock(kind)) => {
+                let lang = match kind {
+                    CodeBlockKind::Fenced(info) if !info.trim().is_empty() => {
+                        Some(info.trim().to_string())
+                    }
+                    _ => None,
+                };
+                code = Some((lang, String::new()));
+            }
+            Event::Start(Tag::Image {
+                link_type,
+                dest_url,
+                title,
+                id,
+            }) => {
+                let dest_url = embed_local_image(base_dir, &dest_url).unwrap_or(dest_url);
+                events.push(Event::Start(Tag::Image {
+                    link_type,
+                    dest_url,
+                    title,
+                    id,
+                }));
+            }
+            event => events.push(event),
+        }
+    }
+
+    let mut body = String::new();
+    html::push_html(&mut body, events.into_iter());
+
+    let mut page = String::new();
+    page.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    page.push_str(
+        "<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n",
+    );
+    page.push_str(&format!("<title>{}</title>\n", escape_html(title)));
+    page.push_str(&format!("<style>\n{}</style>\n", page_css(ui)));
+    page.push_str("</head>\n<body>\n<main>\n");
+    page.push_str(&body);
+    page.push_str("</main>\n</body>\n</html>\n");
+    Ok(page)
+}
+
+fn with_heading_id<'a>(
+    tag: Tag<'a>,
+    text: &str,
+    counts: &mut HashMap<String, usize>,
+) -> Event<'a> {
+    let Tag::Heading {
+        level,
+        id,
+        classes,
+        attrs,
+    } = tag
+    else {
+        return Event::Start(tag);
+    };
+    let id = id.or_else(|| {
+        let base = slugify_anchor(text);
+        if base.is_empty() {
+            return None;
+        }
+        let count = counts.entry(base.clone()).or_insert(0);
+        let slug = if *count == 0 {
+            base
+        } else {
+            format!("{base}-{count}")
+        };
+        *count += 1;
+        Some(slug.into())
+    });
+    Event::Start(Tag::Heading {
+        level,
+        id,
+        classes,
+        attrs,
+    })
+}
+
+fn embed_local_image<'a>(base_dir: &Path, dest: &str) -> Option<CowStr<'a>> {
+    let path = graphics::resolve_local_image(base_dir, dest)?;
+    let bytes = fs::read(&path).ok()?;
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    let mime = match ext.as_str() {
+        "png" => "image/png",
+        "gif" => "image/gif",
+        _ => "image/jpeg",
+    };
+    let data = base64::engine::general_purpose::STANDARD.encode(bytes);
+    Some(format!("data:{mime};base64,{data}").into())
+}
+
+fn page_css(ui: UiPalette) -> String {
+    let fg = css_color(ui.base_fg).unwrap_or_else(|| "inherit".to_string());
+    let bg = ui
+        .base_bg
+        .and_then(css_color)
+        .unwrap_or_else(|| "inherit".to_string());
+    let accent = css_color(ui.accent).unwrap_or_else(|| fg.clone());
+    let muted = css_color(ui.muted).unwrap_or_else(|| fg.clone());
+    let border = css_color(ui.border).unwrap_or_else(|| muted.clone());
+    let code_bg = ui
+        .code_bg
+        .and_then(css_color)
+        .unwrap_or_else(|| "transparent".to_string());
+
+    format!(
+        "body {{ background: {bg}; color: {fg}; margin: 0; line-height: 1.6;
+  font-family: -apple-system, \"Segoe UI\", Helvetica, Arial, sans-serif; }}
+main {{ max-width: 48rem; margin: 0 auto; padding: 2rem 1rem; }}
+a, h1, h2, h3 {{ color: {accent}; }}
+h4, h5, h6 {{ color: {muted}; }}
+code {{ background: {code_bg}; padding: 0.1em 0.3em; border-radius: 3px;
+  font-family: ui-monospace, Menlo, Consolas, monospace; }}
+pre {{ padding: 0.75em 1em; overflow-x: auto; border-radius: 4px;
+  border: 1px solid {border}; font-family: ui-monospace, Menlo, Consolas, monospace; }}
+blockquote {{ color: {muted}; border-left: 3px solid {border}; margin-left: 0;
+  padding-left: 1em; }}
+table {{ border-collapse: collapse; }}
+th, td {{ border: 1px solid {border}; padding: 0.25em 0.6em; }}
+hr {{ border: none; border-top: 1px solid {border}; }}
+img {{ max-width: 100%; }}
+"
+    )
+}
+
+fn css_color(color: Color) -> Option<String> {
+    match color {
+        Color::Rgb(r, g, b) => Some(format!("#{r:02x}{g:02x}{b:02x}")),
+        _ => None,
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            _ => out.push(ch),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render_html;
+    use std::path::Path;
+    use syntect::highlighting::ThemeSet;
+    use syntect::parsing::SyntaxSet;
+
+    #[test]
+    fn headings_get_slug_ids_and_code_is_highlighted() {
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let themes = ThemeSet::load_defaults();
+        let theme = &themes.themes["base16-ocean.dark"];
+        let ui = crate::test_support::palette();
+        let input = "# NvimTree - File Explorer\n\n## Usage\n\n## Usage\n\n\
+                     ```rust\nfn main() {}\n```\n";
+        let page = render_html(input, "doc", Path::new("."), &syntax_set, theme, ui).unwrap();
+
+        assert!(page.contains("<h1 id=\"nvimtree-file-explorer\">"));
+        assert!(page.contains("<h2 id=\"usage\">"));
+        assert!(page.contains("<h2 id=\"usage-1\">"));
+        assert!(page.contains("<pre style=\"background-color:"));
+        assert!(page.contains("<span style=\"color:"));
+        assert!(!page.contains("```"));
+    }
+}
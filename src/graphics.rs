@@ -0,0 +1,438 @@
+use anyhow::Result;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use crossterm::cursor::MoveTo;
+use crossterm::queue;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat, RgbaImage};
+use ratatui::buffer::Buffer;
+use ratatui::layout::Rect;
+use ratatui::style::Color;
+use std::collections::{BTreeSet, HashMap};
+use std::env;
+use std::fmt::Write as _;
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Kitty,
+    Iterm,
+    Sixel,
+    HalfBlock,
+    Off,
+}
+
+impl Protocol {
+    /// Resolves the `image_protocol` config value, sniffing the terminal for `auto`.
+    pub fn from_config(name: &str) -> Self {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "kitty" => Self::Kitty,
+            "iterm" | "iterm2" => Self::Iterm,
+            "sixel" => Self::Sixel,
+            "halfblock" | "blocks" => Self::HalfBlock,
+            "none" | "off" => Self::Off,
+            _ => Self::detect(),
+        }
+    }
+
+    fn detect() -> Self {
+        let term = env::var("TERM").unwrap_or_default();
+        let program = env::var("TERM_PROGRAM").unwrap_or_default();
+        let kitty = term.contains("kitty") || env::var_os("KITTY_WINDOW_ID").is_some();
+        if kitty || program == "ghostty" {
+            Self::Kitty
+        } else if program == "iTerm.app" || program == "WezTerm" {
+            Self::Iterm
+        } else if term.starts_with("foot") || term.contains("mlterm") || term.contains("sixel") {
+            Self::Sixel
+        } else {
+            Self::HalfBlock
+        }
+    }
+}
+
+/// Resolves an image destination to a local PNG/JPEG/GIF file next to the document.
+pub fn resolve_local_image(base: &Path, dest: &str) -> Option<PathBuf> {
+    let dest = dest.strip_prefix("file://").unwrap_or(dest);
+    if dest.contains("://") || dest.starts_with("data:") {
+        return None;
+    }
+    let path = Path::new(dest);
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    if !matches!(ext.as_str(), "png" | "jpg" | "jpeg" | "gif") {
+        return None;
+    }
+    if path.is_absolute() {
+        Some(path.to_path_buf())
+    } else {
+        Some(base.join(path))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Placement {
+    path: PathBuf,
+    x: u16,
+    y: u16,
+    cols: u16,
+    rows: u16,
+}
+
+/// An image scaled to fit a cell box.
+struct Fitted {
+    cols: u16,
+    rows: u16,
+    pixels: RgbaImage,
+    id: u32,
+    transmitted: bool,
+    payload: Option<String>,
+}
+
+/// Loads local images and draws them into the preview, either as half-block cells or
+/// through a terminal graphics protocol after the frame has been flushed.
+pub struct Graphics {
+    protocol: Protocol,
+    cell_size: (u32, u32),
+    images: HashMap<PathBuf, Option<DynamicImage>>,
+    // Keyed by path, box size and whether it is the half-block variant.
+    fitted: HashMap<(PathBuf, u16, u16, bool), Fitted>,
+    next_id: u32,
+    queued: Vec<Placement>,
+    placed: Vec<Placement>,
+}
+
+impl Graphics {
+    pub fn new(protocol: Protocol) -> Self {
+        Self {
+            protocol,
+            cell_size: (8, 16),
+            images: HashMap::new(),
+            fitted: HashMap::new(),
+            next_id: 1,
+            queued: Vec::new(),
+            placed: Vec::new(),
+        }
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    /// Starts a frame; images drawn through a protocol are queued until `flush`.
+    pub fn begin_frame(&mut self) {
+        self.queued.clear();
+        if let Ok(size) = crossterm::terminal::window_size()
+            && size.columns > 0
+            && size.rows > 0
+            && size.width > 0
+            && size.height > 0
+        {
+            let cell = (
+                (size.width / size.columns).max(1) as u32,
+                (size.height / size.rows).max(1) as u32,
+            );
+            if cell != self.cell_size {
+                self.cell_size = cell;
+                self.fitted.retain(|key, _| key.3);
+            }
+        }
+    }
+
+    /// Forgets decoded images so edited files are picked up again.
+    pub fn invalidate(&mut self) {
+        self.images.clear();
+        self.fitted.clear();
+    }
+
+    /// Draws the image at `path` into `area`, which shows its rows from `skip` onward
+    /// out of a `rows`-high box. Returns false when the image cannot be loaded.
+    pub fn draw(
+        &mut self,
+        buf: &mut Buffer,
+        path: &Path,
+        area: Rect,
+        rows: u16,
+        skip: u16,
+    ) -> bool {
+        if self.protocol == Protocol::Off || area.width == 0 || area.height == 0 {
+            return false;
+        }
+        let whole = skip == 0 && area.height >= rows;
+        let half = self.protocol == Protocol::HalfBlock || !whole;
+        let Some(fitted) = self.fit(path, area.width, rows, half) else {
+            return false;
+        };
+        clear_area(buf, area);
+        if half {
+            draw_half_blocks(buf, &fitted.pixels, area, skip);
+            return true;
+        }
+        self.queued.push(Placement {
+            path: path.to_path_buf(),
+            x: area.x,
+            y: area.y,
+            cols: area.width,
+            rows,
+        });
+        true
+    }
+
+    /// Whether the screen must be repainted before protocol images move, because
+    /// iTerm2 and sixel images are plain cell contents the diff renderer cannot see.
+    pub fn needs_clear(&self) -> bool {
+        matches!(self.protocol, Protocol::Iterm | Protocol::Sixel)
+            && !self.placed.is_empty()
+            && self.queued != self.placed
+    }
+
+    pub fn flush<W: Write>(&mut self, out: &mut W) -> Result<()> {
+        if self.queued == self.placed {
+            return Ok(());
+        }
+        if self.protocol == Protocol::Kitty {
+            // Drop the old placements but keep the transmitted image data.
+            out.write_all(b"\x1b_Ga=d,d=a,q=2\x1b\\")?;
+        }
+        for placement in &self.queued {
+            let key = (placement.path.clone(), placement.cols, placement.rows, false);
+            let Some(fitted) = self.fitted.get_mut(&key) else {
+                continue;
+            };
+            let payload = match self.protocol {
+                Protocol::Kitty => {
+                    let mut payload = String::new();
+                    if !fitted.transmitted {
+                        payload.push_str(&kitty_transmit(fitted)?);
+                        fitted.transmitted = true;
+                    }
+                    let _ = write!(
+                        payload,
+                        "\x1b_Ga=p,i={},c={},r={},C=1,q=2\x1b\\",
+                        fitted.id, fitted.cols, fitted.rows
+                    );
+                    payload
+                }
+                Protocol::Iterm => {
+                    if fitted.payload.is_none() {
+                        fitted.payload = Some(iterm_payload(fitted)?);
+                    }
+                    fitted.payload.clone().unwrap_or_default()
+                }
+                Protocol::Sixel => {
+                    if fitted.payload.is_none() {
+                        fitted.payload = Some(sixel_payload(&fitted.pixels));
+                    }
+                    fitted.payload.clone().unwrap_or_default()
+                }
+                Protocol::HalfBlock | Protocol::Off => continue,
+            };
+            queue!(out, MoveTo(placement.x, placement.y))?;
+            out.write_all(payload.as_bytes())?;
+        }
+        out.flush()?;
+        self.placed = std::mem::take(&mut self.queued);
+        Ok(())
+    }
+
+    fn fit(&mut self, path: &Path, cols: u16, rows: u16, half: bool) -> Option<&Fitted> {
+        let key = (path.to_path_buf(), cols, rows, half);
+        if !self.fitted.contains_key(&key) {
+            let image = self
+                .images
+                .entry(path.to_path_buf())
+                .or_insert_with(|| image::open(path).ok())
+                .as_ref()?;
+            // Half blocks pack two pixels per cell vertically.
+            let (cell_w, cell_h) = if half { (1, 2) } else { self.cell_size };
+            let box_w = cols as f64 * cell_w as f64;
+            let box_h = rows as f64 * cell_h as f64;
+            let (img_w, img_h) = (image.width().max(1) as f64, image.height().max(1) as f64);
+            let mut scale = (box_w / img_w).min(box_h / img_h);
+            if !half {
+                scale = scale.min(1.0);
+            }
+            let width = ((img_w * scale).round() as u32).max(1);
+            let height = ((img_h * scale).round() as u32).max(1);
+            let pixels = image
+                .resize_exact(width, height, FilterType::Triangle)
+                .to_rgba8();
+            let fitted = Fitted {
+                cols: width.div_ceil(cell_w).min(cols as u32) as u16,
+                rows: height.div_ceil(cell_h).min(rows as u32) as u16,
+                pixels,
+                id: self.next_id,
+                transmitted: false,
+                payload: None,
+            };
+            self.next_id += 1;
+            self.fitted.insert(key.clone(), fitted);
+        }
+        self.fitted.get(&key)
+    }
+}
+
+fn clear_area(buf: &mut Buffer, area: Rect) {
+    for y in area.top()..area.bottom() {
+        for x in area.left()..area.right() {
+            buf.get_mut(x, y).set_symbol(" ");
+        }
+    }
+}
+
+fn draw_half_blocks(buf: &mut Buffer, pixels: &RgbaImage, area: Rect, skip: u16) {
+    let pixel = |x: u32, y: u32| {
+        if x >= pixels.width() || y >= pixels.height() {
+            return None;
+        }
+        let [r, g, b, a] = pixels.get_pixel(x, y).0;
+        (a >= 128).then_some(Color::Rgb(r, g, b))
+    };
+    for row in 0..area.height {
+        let py = (row + skip) as u32 * 2;
+        for col in 0..area.width {
+            let cell = buf.get_mut(area.x + col, area.y + row);
+            match (pixel(col as u32, py), pixel(col as u32, py + 1)) {
+                (Some(top), Some(bottom)) => {
+                    cell.set_symbol("▀").set_fg(top).set_bg(bottom);
+                }
+                (Some(top), None) => {
+                    cell.set_symbol("▀").set_fg(top);
+                }
+                (None, Some(bottom)) => {
+                    cell.set_symbol("▄").set_fg(bottom);
+                }
+                (None, None) => {}
+            }
+        }
+    }
+}
+
+fn encode_png(pixels: &RgbaImage) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    pixels.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)?;
+    Ok(bytes)
+}
+
+fn kitty_transmit(fitted: &Fitted) -> Result<String> {
+    let data = STANDARD.encode(encode_png(&fitted.pixels)?);
+    let chunks: Vec<&[u8]> = data.as_bytes().chunks(4096).collect();
+    let mut out = String::new();
+    for (idx, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(idx + 1 < chunks.len());
+        if idx == 0 {
+            let _ = write!(out, "\x1b_Ga=t,f=100,i={},q=2,m={more};", fitted.id);
+        } else {
+            let _ = write!(out, "\x1b_Gm={more};");
+        }
+        out.push_str(std::str::from_utf8(chunk).unwrap_or_default());
+        out.push_str("\x1b\\");
+    }
+    Ok(out)
+}
+
+fn iterm_payload(fitted: &Fitted) -> Result<String> {
+    let png = encode_png(&fitted.pixels)?;
+    Ok(format!(
+        "\x1b]1337;File=inline=1;size={};width={};height={};preserveAspectRatio=1:{}\x07",
+        png.len(),
+        fitted.cols,
+        fitted.rows,
+        STANDARD.encode(&png)
+    ))
+}
+
+/// Encodes pixels as sixel data using a 6x6x6 color cube.
+fn sixel_payload(pixels: &RgbaImage) -> String {
+    let (width, height) = pixels.dimensions();
+    let color_at = |x: u32, y: u32| {
+        let [r, g, b, a] = pixels.get_pixel(x, y).0;
+        if a < 128 {
+            return None;
+        }
+        let level = |v: u8| (v as usize * 5 + 127) / 255;
+        Some(level(r) * 36 + level(g) * 6 + level(b))
+    };
+
+    let mut out = String::from("\x1bP0;1q");
+    let _ = write!(out, "\"1;1;{width};{height}");
+    let used: BTreeSet<usize> = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .filter_map(|(x, y)| color_at(x, y))
+        .collect();
+    for &color in &used {
+        let (r, g, b) = (color / 36, color / 6 % 6, color % 6);
+        let _ = write!(out, "#{color};2;{};{};{}", r * 20, g * 20, b * 20);
+    }
+
+    for band in (0..height).step_by(6) {
+        let rows = band..(band + 6).min(height);
+        let colors: BTreeSet<usize> = rows
+            .clone()
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .filter_map(|(x, y)| color_at(x, y))
+            .collect();
+        for color in colors {
+            let _ = write!(out, "#{color}");
+            let mut run: Option<(char, usize)> = None;
+            for x in 0..width {
+                let bits = rows
+                    .clone()
+                    .filter(|&y| color_at(x, y) == Some(color))
+                    .fold(0u8, |acc, y| acc | 1 << (y - band));
+                let ch = char::from(63 + bits);
+                run = match run {
+                    Some((prev, count)) if prev == ch => Some((prev, count + 1)),
+                    Some((prev, count)) => {
+                        push_sixel_run(&mut out, prev, count);
+                        Some((ch, 1))
+                    }
+                    None => Some((ch, 1)),
+                };
+            }
+            if let Some((prev, count)) = run {
+                push_sixel_run(&mut out, prev, count);
+            }
+            out.push('$');
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\");
+    out
+}
+
+fn push_sixel_run(out: &mut String, ch: char, count: usize) {
+    if count > 3 {
+        let _ = write!(out, "!{count}{ch}");
+    } else {
+        out.extend(std::iter::repeat_n(ch, count));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{resolve_local_image, sixel_payload};
+    use image::{Rgba, RgbaImage};
+    use std::path::Path;
+
+    #[test]
+    fn resolve_local_image_skips_remote_and_unknown_formats() {
+        let base = Path::new("/docs");
+        assert_eq!(
+            resolve_local_image(base, "img/a.PNG"),
+            Some(Path::new("/docs/img/a.PNG").to_path_buf())
+        );
+        assert_eq!(resolve_local_image(base, "https://example.com/a.png"), None);
+        assert_eq!(resolve_local_image(base, "diagram.svg"), None);
+    }
+
+    #[test]
+    fn sixel_payload_run_length_encodes_rows() {
+        let image = RgbaImage::from_pixel(8, 6, Rgba([255, 0, 0, 255]));
+        let payload = sixel_payload(&image);
+        assert!(payload.starts_with("\x1bP0;1q\"1;1;8;6"));
+        assert!(payload.contains("#180;2;100;0;0"));
+        assert!(payload.contains("#180!8~$-"));
+        assert!(payload.ends_with("\x1b\\"));
+    }
+}
@@ -0,0 +1,288 @@
+use crate::config::{config_path, ensure_config_dir};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{self, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const HISTORY_LIMIT: usize = 500;
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+const WEEK: u64 = 7 * DAY;
+
+/// Open counts and timestamps used to rank discover results by frecency,
+/// plus where each file was left so reopening it resumes there.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct History {
+    #[serde(default)]
+    files: Vec<HistoryEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct HistoryEntry {
+    path: PathBuf,
+    visits: u32,
+    last_visit: u64,
+    #[serde(default)]
+    line: usize,
+    #[serde(default)]
+    column: usize,
+    #[serde(default)]
+    scroll: usize,
+    #[serde(default)]
+    query: String,
+}
+
+/// Cursor, preview scroll and search query saved when a file is closed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+    pub scroll: usize,
+    pub query: String,
+}
+
+pub fn history_path() -> Result<PathBuf> {
+    Ok(config_path()?.with_file_name("history.toml"))
+}
+
+/// Records an open of `path` in the history file.
+pub fn record_visit(path: &Path) -> Result<()> {
+    let mut history = History::load();
+    history.record(path, now());
+    history.save()
+}
+
+/// Saves the positions of files being closed in one write.
+pub fn remember_positions(positions: Vec<(PathBuf, Position)>) -> Result<()> {
+    let mut history = History::load();
+    let now = now();
+    for (path, position) in positions {
+        history.remember(&path, position, now);
+    }
+    history.save()
+}
+
+/// Moves the entry of a renamed file to its new path.
+pub fn rename_file(from: &Path, to: &Path) -> Result<()> {
+    let mut history = History::load();
+    if history.rename(from, to) {
+        history.save()?;
+    }
+    Ok(())
+}
+
+/// Drops the entry of a deleted file.
+pub fn forget_file(path: &Path) -> Result<()> {
+    let mut history = History::load();
+    if history.forget(path) {
+        history.save()?;
+    }
+    Ok(())
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl History {
+    /// Loads the history file. A missing or unreadable file yields an empty
+    /// history so ranking never blocks discover.
+    pub fn load() -> Self {
+        history_path()
+            .ok()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|raw| toml::from_str(&raw).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = history_path()?;
+        ensure_config_dir(&path)?;
+        let text = toml::to_string_pretty(self).context("Failed to serialize history")?;
+        fs::write(&path, text).with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(())
+    }
+
+    pub fn record(&mut self, path: &Path, now: u64) {
+        let entry = self.entry_mut(path, now);
+        entry.visits = entry.visits.saturating_add(1);
+        entry.last_visit = now;
+        self.prune(now);
+    }
+
+    /// Saves where `path` was left. Counts as a use for recency but not as
+    /// another visit.
+    pub fn remember(&mut self, path: &Path, position: Position, now: u64) {
+        let entry = self.entry_mut(path, now);
+        entry.last_visit = now;
+        entry.line = position.line;
+        entry.column = position.column;
+        entry.scroll = position.scroll;
+        entry.query = position.query;
+        self.prune(now);
+    }
+
+    /// Moves the entry for `from` to `to`. Returns whether there was one.
+    pub fn rename(&mut self, from: &Path, to: &Path) -> bool {
+        let (from, to) = (history_key(from), history_key(to));
+        if !self.files.iter().any(|entry| entry.path == from) {
+            return false;
+        }
+        self.files.retain(|entry| entry.path != to);
+        for entry in &mut self.files {
+            if entry.path == from {
+                entry.path = to.clone();
+            }
+        }
+        true
+    }
+
+    /// Drops the entry for `path`. Returns whether there was one.
+    pub fn forget(&mut self, path: &Path) -> bool {
+        let path = history_key(path);
+        let before = self.files.len();
+        self.files.retain(|entry| entry.path != path);
+        self.files.len() < before
+    }
+
+    pub fn position(&self, path: &Path) -> Option<Position> {
+        let path = history_key(path);
+        let entry = self.files.iter().find(|entry| entry.path == path)?;
+        Some(Position {
+            line: entry.line,
+            column: entry.column,
+            scroll: entry.scroll,
+            query: entry.query.clone(),
+        })
+    }
+
+    /// Most recently used files that still exist, newest first.
+    pub fn recent(&self, limit: usize) -> Vec<PathBuf> {
+        let mut entries: Vec<&HistoryEntry> = self.files.iter().collect();
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.last_visit));
+        entries
+            .into_iter()
+            .filter(|entry| entry.path.is_file())
+            .take(limit)
+            .map(|entry| entry.path.clone())
+            .collect()
+    }
+
+    fn entry_mut(&mut self, path: &Path, now: u64) -> &mut HistoryEntry {
+        let path = history_key(path);
+        let idx = match self.files.iter().position(|entry| entry.path == path) {
+            Some(idx) => idx,
+            None => {
+                self.files.push(HistoryEntry {
+                    path,
+                    visits: 0,
+                    last_visit: now,
+                    line: 0,
+                    column: 0,
+                    scroll: 0,
+                    query: String::new(),
+                });
+                self.files.len() - 1
+            }
+        };
+        &mut self.files[idx]
+    }
+
+    fn prune(&mut self, now: u64) {
+        if self.files.len() > HISTORY_LIMIT {
+            self.files
+                .sort_by(|a, b| entry_score(b, now).total_cmp(&entry_score(a, now)));
+            self.files.truncate(HISTORY_LIMIT);
+        }
+    }
+
+    /// Visit count weighted by how recently the file was last opened.
+    pub fn frecency(&self, path: &Path, now: u64) -> f64 {
+        let path = history_key(path);
+        self.files
+            .iter()
+            .find(|entry| entry.path == path)
+            .map_or(0.0, |entry| entry_score(entry, now))
+    }
+}
+
+fn entry_score(entry: &HistoryEntry, now: u64) -> f64 {
+    let age = now.saturating_sub(entry.last_visit);
+    let weight = if age < HOUR {
+        4.0
+    } else if age < DAY {
+        2.0
+    } else if age < WEEK {
+        1.0
+    } else if age < 4 * WEEK {
+        0.5
+    } else {
+        0.25
+    };
+    f64::from(entry.visits) * weight
+}
+
+fn history_key(path: &Path) -> PathBuf {
+    path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{History, Position, DAY, HOUR};
+    use crate::test_support::TempDir;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn frecency_favors_frequent_and_recent_files() {
+        let mut history = History::default();
+        let now = 10 * DAY;
+        let daily = Path::new("/notes/daily.md");
+        let old = Path::new("/notes/old.md");
+        for day in (0..5).rev() {
+            history.record(daily, now - day * DAY);
+        }
+        history.record(old, now - 9 * DAY);
+        history.record(old, now - 9 * DAY);
+
+        assert!(history.frecency(daily, now + HOUR) > history.frecency(old, now + HOUR));
+        assert_eq!(history.frecency(Path::new("/notes/new.md"), now), 0.0);
+        assert_eq!(history.frecency(daily, now), 5.0 * 4.0);
+
+        let renamed = Path::new("/notes/journal.md");
+        history.rename(daily, renamed);
+        assert_eq!(history.frecency(daily, now), 0.0);
+        assert_eq!(history.frecency(renamed, now), 5.0 * 4.0);
+        assert!(history.forget(renamed));
+        assert_eq!(history.frecency(renamed, now), 0.0);
+    }
+
+    #[test]
+    fn positions_and_recent_files_follow_last_use() {
+        let dir = TempDir::new("recent");
+        let (a, b) = (dir.join("a.md"), dir.join("b.md"));
+        fs::write(&a, "a").unwrap();
+        fs::write(&b, "b").unwrap();
+
+        let mut history = History::default();
+        history.record(&a, 10);
+        history.record(&b, 20);
+        history.record(&dir.join("gone.md"), 30);
+        let position = Position {
+            line: 4,
+            column: 2,
+            scroll: 7,
+            query: "todo".to_string(),
+        };
+        history.remember(&a, position.clone(), 40);
+        let recent = history.recent(10);
+
+        assert_eq!(recent, vec![a.clone(), b]);
+        assert_eq!(history.position(&a), Some(position));
+        assert_eq!(history.frecency(&a, 40), 4.0);
+    }
+}
@@ -0,0 +1,149 @@
+//! Text form of recorded keystrokes, so macros can live in registers.
+//!
+//! Printable keys are written as themselves and the rest in Vim's angle
+//! bracket notation (`<Esc>`, `<CR>`, `<C-r>`, `<lt>` for a literal `<`).
+//! Pasting a macro register, editing the text and yanking it back gives a
+//! macro that replays the edited keys.
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+const NAMED: &[(&str, KeyCode)] = &[
+    ("Esc", KeyCode::Esc),
+    ("CR", KeyCode::Enter),
+    ("Tab", KeyCode::Tab),
+    ("S-Tab", KeyCode::BackTab),
+    ("BS", KeyCode::Backspace),
+    ("Del", KeyCode::Delete),
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+    ("Home", KeyCode::Home),
+    ("End", KeyCode::End),
+    ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown),
+    ("lt", KeyCode::Char('<')),
+];
+
+/// Writes `keys` out in the notation `decode` reads. Keys with no notation,
+/// like function keys, are dropped.
+pub fn encode(keys: &[KeyEvent]) -> String {
+    let mut out = String::new();
+    for key in keys {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let alt = key.modifiers.contains(KeyModifiers::ALT);
+        match key.code {
+            KeyCode::Char(c) if ctrl || alt => {
+                out.push('<');
+                if ctrl {
+                    out.push_str("C-");
+                }
+                if alt {
+                    out.push_str("M-");
+                }
+                if c == '<' {
+                    out.push_str("lt");
+                } else {
+                    out.push(c);
+                }
+                out.push('>');
+            }
+            KeyCode::Char('<') => out.push_str("<lt>"),
+            KeyCode::Char(c) => out.push(c),
+            code => {
+                if let Some((name, _)) = NAMED.iter().find(|(_, named)| *named == code) {
+                    out.push('<');
+                    out.push_str(name);
+                    out.push('>');
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Reads keys back from register text. A `<` that doesn't start a known
+/// name is taken literally, and raw newlines, tabs and escapes typed into
+/// the text count as `<CR>`, `<Tab>` and `<Esc>`.
+pub fn decode(text: &str) -> Vec<KeyEvent> {
+    let mut keys = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c == '<'
+            && let Some(end) = rest.find('>')
+            && let Some(key) = parse_name(&rest[1..end])
+        {
+            keys.push(key);
+            rest = &rest[end + 1..];
+            continue;
+        }
+        let code = match c {
+            '\n' => KeyCode::Enter,
+            '\t' => KeyCode::Tab,
+            '\x1b' => KeyCode::Esc,
+            c => KeyCode::Char(c),
+        };
+        keys.push(KeyEvent::new(code, KeyModifiers::NONE));
+        rest = &rest[c.len_utf8()..];
+    }
+    keys
+}
+
+fn parse_name(name: &str) -> Option<KeyEvent> {
+    if let Some((_, code)) = NAMED.iter().find(|(named, _)| named.eq_ignore_ascii_case(name)) {
+        return Some(KeyEvent::new(*code, KeyModifiers::NONE));
+    }
+    let mut modifiers = KeyModifiers::NONE;
+    let mut rest = name;
+    loop {
+        let lower = rest.get(..2).map(str::to_ascii_lowercase);
+        match lower.as_deref() {
+            Some("c-") => modifiers |= KeyModifiers::CONTROL,
+            Some("m-" | "a-") => modifiers |= KeyModifiers::ALT,
+            _ => break,
+        }
+        rest = &rest[2..];
+    }
+    if modifiers.is_empty() {
+        return None;
+    }
+    let mut chars = rest.chars();
+    let c = match (chars.next(), chars.next()) {
+        (Some(c), None) => c,
+        _ if rest.eq_ignore_ascii_case("lt") => '<',
+        _ => return None,
+    };
+    Some(KeyEvent::new(KeyCode::Char(c), modifiers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode};
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+    #[test]
+    fn keys_round_trip_through_text() {
+        let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+        let keys = vec![
+            key(KeyCode::Char('I')),
+            key(KeyCode::Char('<')),
+            key(KeyCode::Char('-')),
+            key(KeyCode::Esc),
+            KeyEvent::new(KeyCode::Char('r'), KeyModifiers::CONTROL),
+            key(KeyCode::Enter),
+        ];
+        let text = encode(&keys);
+        assert_eq!(text, "I<lt>-<Esc><C-r><CR>");
+        assert_eq!(decode(&text), keys);
+        assert_eq!(
+            decode("a<b>\n"),
+            vec![
+                key(KeyCode::Char('a')),
+                key(KeyCode::Char('<')),
+                key(KeyCode::Char('b')),
+                key(KeyCode::Char('>')),
+                key(KeyCode::Enter),
+            ]
+        );
+    }
+}
@@ -1,11 +1,23 @@
 mod app;
+mod clipboard;
 mod config;
+mod discover;
+mod export;
+mod graphics;
+mod history;
+mod keys;
 mod markdown;
+mod motion;
+mod render;
+#[cfg(test)]
+mod test_support;
 mod theme;
 mod themes;
+mod undo;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
+use std::io::{self, IsTerminal, Read};
 use std::path::PathBuf;
 
 #[derive(Parser)]
@@ -14,7 +26,7 @@ struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
 
-    /// Markdown file to open
+    /// Markdown file to open ("-" reads stdin in a read-only pager)
     file: Option<PathBuf>,
 }
 
@@ -22,6 +34,34 @@ struct Cli {
 enum Commands {
     /// Open the config file in $EDITOR (default: nvim)
     Config,
+    /// Export a markdown file to another format
+    Export {
+        /// Markdown file to export
+        file: PathBuf,
+        /// Output format
+        #[arg(long, default_value = "html", value_parser = ["html"])]
+        format: String,
+        /// Write to this path instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Print a rendered markdown file to stdout ("-" reads stdin)
+    Render {
+        /// Markdown file to render, or "-" for stdin
+        file: PathBuf,
+        /// Wrap width (default: terminal width, or 80 when piped)
+        #[arg(short, long)]
+        width: Option<u16>,
+        /// Theme to render with (default: configured theme)
+        #[arg(long)]
+        theme: Option<String>,
+        /// Keep bold/italic/underline but drop colors
+        #[arg(long)]
+        no_color: bool,
+        /// Print plain text without escape sequences
+        #[arg(long, conflicts_with = "no_color")]
+        plain: bool,
+    },
     /// Manage themes
     Themes {
         #[command(subcommand)]
@@ -46,6 +86,33 @@ fn main() -> Result<()> {
     if let Some(command) = cli.command {
         match command {
             Commands::Config => return config::open_config_in_editor(),
+            Commands::Export {
+                file,
+                format: _,
+                output,
+            } => {
+                let cfg = config::load_config()?;
+                return export::export_html(&file, output.as_deref(), &cfg);
+            }
+            Commands::Render {
+                file,
+                width,
+                theme,
+                no_color,
+                plain,
+            } => {
+                let cfg = config::load_config()?;
+                let no_color =
+                    no_color || std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+                let mode = if plain {
+                    render::RenderMode::Plain
+                } else if no_color {
+                    render::RenderMode::NoColor
+                } else {
+                    render::RenderMode::Color
+                };
+                return render::render_to_stdout(&file, width, theme.as_deref(), mode, &cfg);
+            }
             Commands::Themes { command } => match command {
                 ThemeCommands::Install { source } => {
                     let source = source.unwrap_or_else(|| "bat".to_string());
@@ -72,25 +139,24 @@ fn main() -> Result<()> {
     }
 
     let cfg = config::load_config()?;
-    let mut file = match cli.file {
-        Some(path) => path,
-        None => match app::run_discover(&cfg)? {
-            Some(path) => path,
+    let from_stdin = match &cli.file {
+        Some(path) => path.as_os_str() == "-",
+        None => !io::stdin().is_terminal(),
+    };
+    if from_stdin {
+        let mut markdown = String::new();
+        io::stdin()
+            .read_to_string(&mut markdown)
+            .context("Failed to read markdown from stdin")?;
+        return app::run_pager(markdown, cfg);
+    }
+
+    let (file, line) = match cli.file {
+        Some(path) => (path, None),
+        None => match discover::run_discover(&cfg)? {
+            Some(pick) => (pick.path, pick.line),
             None => return Ok(()),
         },
     };
-
-    loop {
-        match app::run_app(file, cfg.clone())? {
-            app::AppExit::Quit => break,
-            app::AppExit::Discover => {
-                if let Some(path) = app::run_discover(&cfg)? {
-                    file = path;
-                } else {
-                    break;
-                }
-            }
-        }
-    }
-    Ok(())
+    app::run_app(file, line, cfg)
 }
@@ -1,8 +1,17 @@
+use crate::graphics;
 use anyhow::Result;
-use pulldown_cmark::{Alignment, CodeBlockKind, Event, Options, Parser, Tag, TagEnd};
+use pulldown_cmark::{
+    Alignment, BrokenLink, CodeBlockKind, Event, Options, Parser, Tag, TagEnd,
+};
 use ratatui::style::{Color, Modifier, Style};
 use ratatui::text::{Line, Span};
+use regex::{Regex, RegexBuilder};
+use ropey::Rope;
 use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
 use syntect::easy::HighlightLines;
 use syntect::highlighting::{FontStyle, Theme};
 use syntect::parsing::SyntaxSet;
@@ -29,6 +38,8 @@ pub struct Heading {
     pub level: u8,
     pub title: String,
     pub line: usize,
+    /// Lines the wrapped title takes, starting at `line`.
+    pub rows: usize,
 }
 
 #[derive(Debug, Clone)]
@@ -38,6 +49,21 @@ pub struct Match {
     pub end: usize,
 }
 
+/// An image that takes `rows` preview lines starting at `line`.
+#[derive(Debug, Clone)]
+pub struct RenderedImage {
+    pub line: usize,
+    pub rows: usize,
+    pub dest: String,
+}
+
+#[derive(Debug, Clone)]
+struct ImageRaw {
+    raw_line: usize,
+    dest: String,
+    alt: String,
+}
+
 #[derive(Debug, Clone)]
 struct HeadingRaw {
     level: u8,
@@ -45,16 +71,276 @@ struct HeadingRaw {
     raw_line: usize,
 }
 
+/// Where a rendered line came from, in char offsets of the parsed input.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LineSource {
+    /// Char range of the innermost block that produced the line.
+    pub range: Range<usize>,
+    /// Rendered columns paired with the source offset of the text drawn there.
+    pub anchors: Vec<SourceAnchor>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceAnchor {
+    pub col: usize,
+    pub offset: usize,
+}
+
+impl LineSource {
+    fn new(range: Range<usize>, anchors: Vec<SourceAnchor>) -> Self {
+        Self { range, anchors }
+    }
+
+    fn shift(&mut self, delta: isize) {
+        self.range.start = self.range.start.saturating_add_signed(delta);
+        self.range.end = self.range.end.saturating_add_signed(delta);
+        for anchor in &mut self.anchors {
+            anchor.offset = anchor.offset.saturating_add_signed(delta);
+        }
+    }
+
+    /// Restricts the anchors to the columns `start..end`, rebased to `start`.
+    fn slice(&self, start: usize, end: usize) -> LineSource {
+        let mut anchors = Vec::new();
+        if let Some(lead) = self.anchors.iter().rev().find(|a| a.col <= start) {
+            anchors.push(SourceAnchor {
+                col: 0,
+                offset: lead.offset + (start - lead.col),
+            });
+        }
+        anchors.extend(
+            self.anchors
+                .iter()
+                .filter(|a| a.col > start && a.col < end)
+                .map(|a| SourceAnchor {
+                    col: a.col - start,
+                    offset: a.offset,
+                }),
+        );
+        LineSource::new(self.range.clone(), anchors)
+    }
+}
+
+#[derive(Default)]
 pub struct ParsedDocument {
+    text: Rope,
+    blocks: Vec<ParsedBlock>,
+    definitions: Vec<Definition>,
+    // Link reference definitions by lowercased label, as (destination, title).
+    references: HashMap<String, (String, String)>,
+}
+
+/// A link reference definition, at the char offset it starts at.
+#[derive(Clone)]
+struct Definition {
+    offset: usize,
+    label: String,
+    dest: String,
+    title: String,
+}
+
+/// The definitions by label; the first one of a label wins, as in CommonMark.
+fn reference_map(definitions: &[Definition]) -> HashMap<String, (String, String)> {
+    let mut references = HashMap::new();
+    for def in definitions {
+        references
+            .entry(def.label.clone())
+            .or_insert_with(|| (def.dest.clone(), def.title.clone()));
+    }
+    references
+}
+
+/// An edit in char offsets: `start..old_end` of the previous text became
+/// `start..new_end` of the new one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextEdit {
+    pub start: usize,
+    pub old_end: usize,
+    pub new_end: usize,
+}
+
+impl TextEdit {
+    pub fn new(start: usize, removed: usize, inserted: usize) -> Self {
+        Self {
+            start,
+            old_end: start + removed,
+            new_end: start + inserted,
+        }
+    }
+
+    /// One edit covering `self` followed by `next`, whose offsets are in the text
+    /// `self` produced.
+    pub fn then(self, next: TextEdit) -> TextEdit {
+        let end = self.new_end.max(next.old_end);
+        TextEdit {
+            start: self.start.min(next.start),
+            old_end: end - self.new_end + self.old_end,
+            new_end: end - next.old_end + next.new_end,
+        }
+    }
+
+    /// The edit between two texts, from their common prefix and suffix.
+    fn between(old: &Rope, new: &Rope) -> Option<TextEdit> {
+        if old == new {
+            return None;
+        }
+        let prefix = old.chars().zip(new.chars()).take_while(|(a, b)| a == b).count();
+        let (old_len, new_len) = (old.len_chars(), new.len_chars());
+        let suffix = old
+            .chars_at(old_len)
+            .reversed()
+            .zip(new.chars_at(new_len).reversed())
+            .take(old_len.min(new_len) - prefix)
+            .take_while(|(a, b)| a == b)
+            .count();
+        Some(TextEdit {
+            start: prefix,
+            old_end: old_len - suffix,
+            new_end: new_len - suffix,
+        })
+    }
+}
+
+/// A top-level block, the unit of incremental re-parsing.
+struct ParsedBlock {
+    id: u64,
+    range: Range<usize>,
     raw_lines: Vec<Line<'static>>,
+    // Offsets are relative to `range.start` so the block survives edits before it.
+    raw_sources: Vec<Option<LineSource>>,
+    // `raw_line` is relative to the block.
     headings: Vec<HeadingRaw>,
+    images: Vec<ImageRaw>,
+    // Raw lines holding code block text, relative to the block.
+    code: Vec<Range<usize>>,
 }
 
+#[derive(Default)]
 pub struct RenderedDocument {
     pub lines: Vec<Line<'static>>,
     pub plain_lines: Vec<String>,
     pub headings: Vec<Heading>,
     pub matches: Vec<Match>,
+    pub sources: Vec<Option<LineSource>>,
+    pub images: Vec<RenderedImage>,
+    /// Lines showing code block text, without the frame around it.
+    pub code_blocks: Vec<Range<usize>>,
+    layout: WrapLayout,
+}
+
+/// What a rendered document was wrapped with, so unchanged blocks can be reused.
+#[derive(Default)]
+struct WrapLayout {
+    width: u16,
+    query: Option<String>,
+    case_sensitive: bool,
+    images: Option<(u16, PathBuf)>,
+    blocks: Vec<WrappedBlock>,
+}
+
+/// Boxes of blank rows reserved under images the terminal can draw: local
+/// png, jpg and gif files that exist. Other images keep a one-line placeholder.
+#[derive(Clone, Copy)]
+pub struct ImageBoxes<'a> {
+    pub rows: u16,
+    /// Directory relative image paths are resolved against.
+    pub base: &'a Path,
+}
+
+impl ImageBoxes<'_> {
+    fn fits(&self, dest: &str) -> bool {
+        graphics::resolve_local_image(self.base, dest).is_some_and(|path| path.is_file())
+    }
+}
+
+struct WrappedBlock {
+    id: u64,
+    start: usize,
+    lines: usize,
+    // Wrapped lines of each heading and code block, relative to the block.
+    headings: Vec<Range<usize>>,
+    // Boxed images as (index in the block's images, first wrapped line).
+    images: Vec<(usize, usize)>,
+    code: Vec<Range<usize>>,
+}
+
+static NEXT_BLOCK_ID: AtomicU64 = AtomicU64::new(1);
+
+impl RenderedDocument {
+    /// Maps a rendered position back to a char offset in the source.
+    pub fn source_offset(&self, line: usize, col: usize) -> Option<usize> {
+        let source = self.sources.get(line)?.as_ref()?;
+        let Some(first) = source.anchors.first() else {
+            return Some(source.range.start);
+        };
+        if col < first.col {
+            return Some(first.offset);
+        }
+        let idx = source.anchors.partition_point(|a| a.col <= col) - 1;
+        let anchor = source.anchors[idx];
+        Some(anchor.offset + (col - anchor.col))
+    }
+
+    /// Finds the rendered line and column that display a source char offset.
+    pub fn locate_source(&self, offset: usize) -> Option<(usize, usize)> {
+        if self.lines.is_empty() {
+            return None;
+        }
+        let mut best: Option<(usize, SourceAnchor, Option<usize>)> = None;
+        let mut first_containing: Option<usize> = None;
+        for (idx, source) in self.sources.iter().enumerate() {
+            let Some(source) = source else {
+                continue;
+            };
+            if offset < source.range.start || offset >= source.range.end {
+                continue;
+            }
+            first_containing.get_or_insert(idx);
+            for (pos, anchor) in source.anchors.iter().enumerate() {
+                if anchor.offset > offset {
+                    continue;
+                }
+                if best.is_none_or(|(_, b, _)| anchor.offset > b.offset) {
+                    let next_col = source.anchors.get(pos + 1).map(|a| a.col);
+                    best = Some((idx, *anchor, next_col));
+                }
+            }
+        }
+
+        if let Some((line, anchor, next_col)) = best {
+            let mut col = anchor.col + (offset - anchor.offset);
+            if let Some(next_col) = next_col {
+                col = col.min(next_col);
+            }
+            return Some((line, col.min(self.line_len(line))));
+        }
+        if let Some(line) = first_containing {
+            let col = self.sources[line]
+                .as_ref()
+                .and_then(|s| s.anchors.first())
+                .map(|a| a.col)
+                .unwrap_or(0);
+            return Some((line, col));
+        }
+
+        // Between blocks: prefer the blank separator in front of the next block.
+        let next = self
+            .sources
+            .iter()
+            .position(|s| s.as_ref().is_some_and(|s| s.range.start > offset));
+        match next {
+            Some(idx) if idx > 0 && self.sources[idx - 1].is_none() => Some((idx - 1, 0)),
+            Some(idx) => Some((idx, 0)),
+            None => Some((self.lines.len() - 1, 0)),
+        }
+    }
+
+    fn line_len(&self, line: usize) -> usize {
+        self.plain_lines
+            .get(line)
+            .map(|l| l.chars().count())
+            .unwrap_or(0)
+    }
 }
 
 pub fn parse_markdown(
@@ -64,6 +350,197 @@ pub fn parse_markdown(
     styles: &MarkdownStyles,
     tab_width: usize,
 ) -> Result<ParsedDocument> {
+    let (blocks, definitions) = parse_blocks(
+        input,
+        false,
+        &HashMap::new(),
+        syntax_set,
+        theme,
+        styles,
+        tab_width,
+    )?;
+    Ok(ParsedDocument {
+        text: Rope::from_str(input),
+        blocks,
+        references: reference_map(&definitions),
+        definitions,
+    })
+}
+
+impl ParsedDocument {
+    /// Re-parses after an edit, keeping the top-level blocks the edit did not touch.
+    /// Without `edit` the changed range is found by comparing against the old text.
+    #[allow(clippy::too_many_arguments)]
+    pub fn update(
+        &mut self,
+        text: &Rope,
+        edit: Option<TextEdit>,
+        syntax_set: &SyntaxSet,
+        theme: &Theme,
+        styles: &MarkdownStyles,
+        tab_width: usize,
+    ) -> Result<()> {
+        // An edit that doesn't fit the two lengths was tracked wrong; diff instead.
+        let fits = |edit: &TextEdit| {
+            edit.start <= edit.old_end.min(edit.new_end)
+                && edit.old_end <= self.text.len_chars()
+                && edit.new_end <= text.len_chars()
+                && self.text.len_chars() - edit.old_end == text.len_chars() - edit.new_end
+        };
+        let edit = match edit.filter(fits) {
+            Some(edit) => edit,
+            None => match TextEdit::between(&self.text, text) {
+                Some(edit) => edit,
+                None => return Ok(()),
+            },
+        };
+        if !self.update_blocks(text, edit, syntax_set, theme, styles, tab_width)? {
+            *self = parse_markdown(&text.to_string(), syntax_set, theme, styles, tab_width)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    fn raw_lines(&self) -> impl Iterator<Item = &Line<'static>> {
+        self.blocks.iter().flat_map(|block| block.raw_lines.iter())
+    }
+
+    /// Splices freshly parsed blocks over the edited region. Returns false when the
+    /// edit could change how the rest of the document parses.
+    fn update_blocks(
+        &mut self,
+        input: &Rope,
+        edit: TextEdit,
+        syntax_set: &SyntaxSet,
+        theme: &Theme,
+        styles: &MarkdownStyles,
+        tab_width: usize,
+    ) -> Result<bool> {
+        if self.blocks.is_empty() {
+            return Ok(false);
+        }
+        let old = &self.text;
+        let TextEdit {
+            start: edit_start,
+            old_end,
+            new_end,
+        } = edit;
+        let delta = new_end as isize - old_end as isize;
+
+        let count = self.blocks.len();
+        let touched_first = self
+            .blocks
+            .iter()
+            .position(|b| b.range.end >= edit_start)
+            .unwrap_or(count - 1);
+        let touched_last = self
+            .blocks
+            .iter()
+            .rposition(|b| b.range.start <= old_end)
+            .unwrap_or(0)
+            .max(touched_first);
+
+        // One block of context on each side catches merges and splits; indented
+        // blocks may belong to a list further up, so keep walking back past them.
+        let mut first = touched_first.saturating_sub(1);
+        let mut region_start = line_start_char(old, self.blocks[first].range.start);
+        while first > 0 && starts_indented(old, region_start) {
+            first -= 1;
+            region_start = line_start_char(old, self.blocks[first].range.start);
+        }
+        if first == 0 {
+            region_start = 0;
+        }
+        let lookahead = touched_last + 1;
+        let old_region_end = match self.blocks.get(lookahead) {
+            Some(block) => block.range.end,
+            None => old.len_chars(),
+        };
+        let new_region_end = old_region_end.saturating_add_signed(delta);
+
+        let preceded = self.blocks[..first]
+            .iter()
+            .any(|b| !b.raw_lines.is_empty());
+        let (mut fresh, defined) = parse_blocks(
+            &input.slice(region_start..new_region_end).to_string(),
+            preceded,
+            &self.references,
+            syntax_set,
+            theme,
+            styles,
+            tab_width,
+        )?;
+        for block in &mut fresh {
+            block.range.start += region_start;
+            block.range.end += region_start;
+        }
+
+        let mut replace_end = count;
+        if let Some(next) = self.blocks.get(lookahead) {
+            // The block after the edit must come out exactly as before, otherwise the
+            // edit leaked further (an unclosed fence, for example).
+            let expected = next.range.start.saturating_add_signed(delta)..new_region_end;
+            if fresh.last().map(|b| &b.range) != Some(&expected) {
+                return Ok(false);
+            }
+            fresh.pop();
+            replace_end = lookahead;
+        }
+        // Reference definitions are visible document-wide, so a region that gains or
+        // loses one, even on a line the edit didn't touch, changes other blocks too.
+        let split = self.definitions.partition_point(|d| d.offset < region_start);
+        let after = self.definitions.partition_point(|d| d.offset < old_region_end);
+        let mut definitions = self.definitions[..split].to_vec();
+        definitions.extend(defined.into_iter().map(|d| Definition {
+            offset: d.offset + region_start,
+            ..d
+        }));
+        definitions.extend(self.definitions[after..].iter().map(|d| Definition {
+            offset: d.offset.saturating_add_signed(delta),
+            ..d.clone()
+        }));
+        if reference_map(&definitions) != self.references {
+            return Ok(false);
+        }
+        self.definitions = definitions;
+        // Keep the untouched leading context block so its highlighting is reused.
+        let mut replace_start = first;
+        if first < touched_first
+            && fresh.first().map(|b| &b.range) == Some(&self.blocks[first].range)
+        {
+            fresh.remove(0);
+            replace_start += 1;
+        }
+
+        for block in &mut self.blocks[replace_end..] {
+            block.range.start = block.range.start.saturating_add_signed(delta);
+            block.range.end = block.range.end.saturating_add_signed(delta);
+        }
+        self.blocks.splice(replace_start..replace_end, fresh);
+        self.text = input.clone();
+        Ok(true)
+    }
+}
+
+fn line_start_char(text: &Rope, char_idx: usize) -> usize {
+    text.line_to_char(text.char_to_line(char_idx))
+}
+
+fn starts_indented(text: &Rope, char_idx: usize) -> bool {
+    matches!(text.get_char(char_idx), Some(' ' | '\t'))
+}
+
+/// Parses `input` into top-level blocks with ranges relative to its start.
+/// Returns the blocks and the reference definitions found, in order.
+fn parse_blocks(
+    input: &str,
+    preceded: bool,
+    references: &HashMap<String, (String, String)>,
+    syntax_set: &SyntaxSet,
+    theme: &Theme,
+    styles: &MarkdownStyles,
+    tab_width: usize,
+) -> Result<(Vec<ParsedBlock>, Vec<Definition>)> {
     let mut options = Options::empty();
     options.insert(Options::ENABLE_TABLES);
     options.insert(Options::ENABLE_STRIKETHROUGH);
@@ -71,13 +548,30 @@ pub fn parse_markdown(
     options.insert(Options::ENABLE_FOOTNOTES);
 
     let normalized = normalize_line_endings(input);
-    let parser = Parser::new_ext(normalized.as_ref(), options);
+    let text = normalized.as_ref();
+    let offsets = OffsetMapper::new(text, input);
+    // Slices parsed on their own still resolve references defined elsewhere.
+    let resolve = |link: BrokenLink<'_>| {
+        references
+            .get(&link.reference.to_lowercase())
+            .map(|(dest, title)| (dest.clone().into(), title.clone().into()))
+    };
+    let mut parser =
+        Parser::new_with_broken_link_callback(text, options, Some(resolve)).into_offset_iter();
 
-    let mut raw_lines: Vec<Line<'static>> = Vec::new();
+    let mut raw = RawLines {
+        preceded,
+        ..RawLines::default()
+    };
+    // Top-level blocks as (char range, first raw line, first heading).
+    let mut chunks: Vec<(Range<usize>, usize, usize)> = Vec::new();
     let mut headings: Vec<HeadingRaw> = Vec::new();
+    // Char ranges of the block elements enclosing the current event.
+    let mut blocks: Vec<Range<usize>> = Vec::new();
 
     let mut line = LineBuilder::new();
     let mut heading: Option<HeadingBuilder> = None;
+    let mut image: Option<ImageRaw> = None;
     let mut code_block: Option<CodeBlock> = None;
     let mut table: Option<TableBuilder> = None;
     let mut list_stack: Vec<ListKind> = Vec::new();
@@ -86,179 +580,249 @@ pub fn parse_markdown(
 
     let mut style_state = StyleState::new(styles.base, styles.link_color);
 
-    for event in parser {
+    for (event, byte_range) in parser.by_ref() {
+        let span = offsets.char_range(&byte_range);
+        let top_level = blocks.is_empty()
+            && match &event {
+                Event::Start(tag) => !is_inline_tag(tag),
+                Event::Rule => true,
+                _ => false,
+            };
+        if top_level {
+            flush_line(&mut line, &mut raw, &blocks);
+            chunks.push((span.clone(), raw.len(), headings.len()));
+        }
         match event {
-            Event::Start(tag) => match tag {
-                Tag::Paragraph => {
-                    if table.is_none() {
+            Event::Start(tag) => {
+                let is_block = !is_inline_tag(&tag);
+                match tag {
+                    Tag::Paragraph if table.is_none() => {
                         line.ensure_prefix(
                             &current_prefix(blockquote_level, pending_list_prefix.as_deref()),
                             styles.prefix,
                         );
                     }
-                }
-                Tag::Heading { level, .. } => {
-                    flush_line(&mut line, &mut raw_lines);
-                    heading = Some(HeadingBuilder::new(level as u8));
-                }
-                Tag::CodeBlock(kind) => {
-                    flush_line(&mut line, &mut raw_lines);
-                    code_block = Some(CodeBlock::new(kind));
-                }
-                Tag::Table(alignments) => {
-                    flush_line(&mut line, &mut raw_lines);
-                    table = Some(TableBuilder::new(alignments));
-                }
-                Tag::TableHead => {
-                    if let Some(table) = table.as_mut() {
-                        table.in_head = true;
-                        table.saw_head = true;
+                    Tag::Heading { level, .. } => {
+                        flush_line(&mut line, &mut raw, &blocks);
+                        heading = Some(HeadingBuilder::new(level as u8));
                     }
-                }
-                Tag::TableRow => {
-                    if let Some(table) = table.as_mut() {
-                        table.start_row();
+                    Tag::CodeBlock(kind) => {
+                        flush_line(&mut line, &mut raw, &blocks);
+                        code_block = Some(CodeBlock::new(kind, span.clone()));
                     }
-                }
-                Tag::TableCell => {
-                    if let Some(table) = table.as_mut() {
-                        table.start_cell();
+                    Tag::Table(alignments) => {
+                        flush_line(&mut line, &mut raw, &blocks);
+                        table = Some(TableBuilder::new(alignments));
                     }
-                }
-                Tag::List(start) => list_stack.push(ListKind::from(start)),
-                Tag::Item => {
-                    flush_line(&mut line, &mut raw_lines);
-                    pending_list_prefix = Some(list_prefix(&mut list_stack));
-                    line.ensure_prefix(
-                        &current_prefix(blockquote_level, pending_list_prefix.as_deref()),
-                        styles.prefix,
-                    );
-                }
-                Tag::Emphasis => style_state.italic += 1,
-                Tag::Strong => style_state.bold += 1,
-                Tag::Strikethrough => style_state.strike += 1,
-                Tag::BlockQuote => {
-                    blockquote_level += 1;
-                    line.ensure_prefix(
-                        &current_prefix(blockquote_level, pending_list_prefix.as_deref()),
-                        styles.prefix,
-                    );
-                }
-                Tag::Link { .. } => style_state.underline += 1,
-                _ => {}
-            },
-            Event::End(tag) => match tag {
-                TagEnd::Paragraph => {
-                    if table.is_none() {
-                        flush_line(&mut line, &mut raw_lines);
-                        push_blank_line(&mut raw_lines);
+                    Tag::TableHead => {
+                        if let Some(table) = table.as_mut() {
+                            table.in_head = true;
+                            table.saw_head = true;
+                            table.row_source = Some(span.clone());
+                        }
                     }
-                }
-                TagEnd::Heading(_) => {
-                    if let Some(h) = heading.take() {
-                        let text = h.text.trim().to_string();
-                        let raw_line = raw_lines.len();
-                        if h.level <= 2 && !raw_lines.is_empty() {
-                            push_blank_line(&mut raw_lines);
+                    Tag::TableRow => {
+                        if let Some(table) = table.as_mut() {
+                            table.start_row();
+                            table.row_source = Some(span.clone());
                         }
-                        raw_lines.push(Line::from(Span::styled(
-                            text.clone(),
-                            heading_style(styles, h.level),
-                        )));
-                        if h.level <= 2 {
-                            let ch = if h.level == 1 { '═' } else { '─' };
-                            let underline =
-                                ch.to_string().repeat(text.chars().count().clamp(4, 48));
-                            raw_lines.push(Line::from(Span::styled(underline, styles.rule)));
+                    }
+                    Tag::TableCell => {
+                        if let Some(table) = table.as_mut() {
+                            table.start_cell();
+                        }
+                    }
+                    Tag::List(start) => list_stack.push(ListKind::from(start)),
+                    Tag::Item => {
+                        flush_line(&mut line, &mut raw, &blocks);
+                        pending_list_prefix = Some(list_prefix(&mut list_stack));
+                        line.ensure_prefix(
+                            &current_prefix(blockquote_level, pending_list_prefix.as_deref()),
+                            styles.prefix,
+                        );
+                    }
+                    Tag::Emphasis => style_state.italic += 1,
+                    Tag::Strong => style_state.bold += 1,
+                    Tag::Strikethrough => style_state.strike += 1,
+                    Tag::BlockQuote => {
+                        blockquote_level += 1;
+                        line.ensure_prefix(
+                            &current_prefix(blockquote_level, pending_list_prefix.as_deref()),
+                            styles.prefix,
+                        );
+                    }
+                    Tag::Link { .. } => style_state.underline += 1,
+                    Tag::Image { dest_url, .. } if table.is_none() && heading.is_none() => {
+                        // Images get their own rows, so break the paragraph around them.
+                        // A line holding only the list or quote prefix goes to the image.
+                        let prefix =
+                            current_prefix(blockquote_level, pending_list_prefix.as_deref());
+                        if line.plain != prefix {
+                            flush_line(&mut line, &mut raw, &blocks);
                         }
-                        // plain lines are reconstructed later from spans
-                        headings.push(HeadingRaw {
-                            level: h.level,
-                            title: text,
-                            raw_line,
+                        image = Some(ImageRaw {
+                            raw_line: 0,
+                            dest: dest_url.to_string(),
+                            alt: String::new(),
                         });
-                        push_blank_line(&mut raw_lines);
                     }
+                    _ => {}
                 }
-                TagEnd::CodeBlock => {
-                    if let Some(block) = code_block.take() {
-                        render_code_block(&block, syntax_set, theme, styles, &mut raw_lines);
-                        push_blank_line(&mut raw_lines);
-                    }
+                if is_block {
+                    blocks.push(span);
                 }
-                TagEnd::Table => {
-                    if let Some(mut table_state) = table.take() {
-                        table_state.end_row();
-                        render_table(&table_state, styles, &mut raw_lines);
-                        push_blank_line(&mut raw_lines);
+            }
+            Event::End(tag) => {
+                let is_block = !is_inline_tag_end(&tag);
+                match tag {
+                    TagEnd::Paragraph if table.is_none() => {
+                        flush_line(&mut line, &mut raw, &blocks);
+                        raw.push_blank();
                     }
-                }
-                TagEnd::TableHead => {
-                    if let Some(table) = table.as_mut() {
-                        // Be tolerant of parser event ordering and ensure header row is committed
-                        // before we leave the head section.
-                        table.end_row();
-                        table.in_head = false;
+                    TagEnd::Heading(_) => {
+                        if let Some(h) = heading.take() {
+                            let (text, anchors) = h.finish();
+                            let raw_line = raw.len();
+                            if h.level <= 2 && !raw.is_empty() {
+                                raw.push_blank();
+                            }
+                            raw.push(
+                                Line::from(Span::styled(
+                                    text.clone(),
+                                    heading_style(styles, h.level),
+                                )),
+                                Some(LineSource::new(span.clone(), anchors)),
+                            );
+                            if h.level <= 2 {
+                                let ch = if h.level == 1 { '═' } else { '─' };
+                                let underline =
+                                    ch.to_string().repeat(text.chars().count().clamp(4, 48));
+                                raw.push(
+                                    Line::from(Span::styled(underline, styles.rule)),
+                                    Some(LineSource::new(span.clone(), Vec::new())),
+                                );
+                            }
+                            // plain lines are reconstructed later from spans
+                            headings.push(HeadingRaw {
+                                level: h.level,
+                                title: text,
+                                raw_line,
+                            });
+                            raw.push_blank();
+                        }
                     }
-                }
-                TagEnd::TableRow => {
-                    if let Some(table) = table.as_mut() {
-                        table.end_row();
+                    TagEnd::CodeBlock => {
+                        if let Some(mut block) = code_block.take() {
+                            block.closing =
+                                offsets.char_offset(closing_line_start(text, &byte_range));
+                            render_code_block(&block, syntax_set, theme, styles, &mut raw);
+                            raw.push_blank();
+                        }
                     }
-                }
-                TagEnd::TableCell => {
-                    if let Some(table) = table.as_mut() {
-                        table.end_cell();
+                    TagEnd::Table => {
+                        if let Some(mut table_state) = table.take() {
+                            table_state.end_row();
+                            table_state.range = span.clone();
+                            render_table(&table_state, styles, &mut raw);
+                            raw.push_blank();
+                        }
                     }
+                    TagEnd::TableHead => {
+                        if let Some(table) = table.as_mut() {
+                            // Be tolerant of parser event ordering and ensure header row is committed
+                            // before we leave the head section.
+                            table.end_row();
+                            table.in_head = false;
+                        }
+                    }
+                    TagEnd::TableRow => {
+                        if let Some(table) = table.as_mut() {
+                            table.end_row();
+                        }
+                    }
+                    TagEnd::TableCell => {
+                        if let Some(table) = table.as_mut() {
+                            table.end_cell();
+                        }
+                    }
+                    TagEnd::List(_) => {
+                        list_stack.pop();
+                        flush_line(&mut line, &mut raw, &blocks);
+                    }
+                    TagEnd::Item => {
+                        pending_list_prefix = None;
+                        flush_line(&mut line, &mut raw, &blocks);
+                    }
+                    TagEnd::Emphasis => style_state.italic = style_state.italic.saturating_sub(1),
+                    TagEnd::Strong => style_state.bold = style_state.bold.saturating_sub(1),
+                    TagEnd::Strikethrough => {
+                        style_state.strike = style_state.strike.saturating_sub(1)
+                    }
+                    TagEnd::BlockQuote => {
+                        blockquote_level = blockquote_level.saturating_sub(1);
+                        flush_line(&mut line, &mut raw, &blocks);
+                        raw.push_blank();
+                    }
+                    TagEnd::Link => {
+                        style_state.underline = style_state.underline.saturating_sub(1)
+                    }
+                    TagEnd::Image => {
+                        if let Some(mut img) = image.take() {
+                            let label = if img.alt.is_empty() { &img.dest } else { &img.alt };
+                            let placeholder = format!("[image: {label}]");
+                            line.ensure_prefix(
+                                &current_prefix(blockquote_level, pending_list_prefix.as_deref()),
+                                styles.prefix,
+                            );
+                            let source = Some(span.start);
+                            line.push_text(&placeholder, styles.prefix, tab_width, source);
+                            if let Some((placeholder, anchors)) = line.take_line() {
+                                img.raw_line = raw.len();
+                                raw.push(placeholder, Some(LineSource::new(span.clone(), anchors)));
+                                raw.images.push(img);
+                            }
+                        }
+                    }
+                    _ => {}
                 }
-                TagEnd::List(_) => {
-                    list_stack.pop();
-                    flush_line(&mut line, &mut raw_lines);
-                }
-                TagEnd::Item => {
-                    pending_list_prefix = None;
-                    flush_line(&mut line, &mut raw_lines);
-                }
-                TagEnd::Emphasis => style_state.italic = style_state.italic.saturating_sub(1),
-                TagEnd::Strong => style_state.bold = style_state.bold.saturating_sub(1),
-                TagEnd::Strikethrough => style_state.strike = style_state.strike.saturating_sub(1),
-                TagEnd::BlockQuote => {
-                    blockquote_level = blockquote_level.saturating_sub(1);
-                    flush_line(&mut line, &mut raw_lines);
-                    push_blank_line(&mut raw_lines);
+                if is_block {
+                    blocks.pop();
                 }
-                TagEnd::Link => style_state.underline = style_state.underline.saturating_sub(1),
-                _ => {}
-            },
+            }
             Event::Text(text) => {
-                if let Some(table) = table.as_mut() {
-                    table.push_text(&text, style_state.inline_style(), tab_width);
+                if let Some(img) = image.as_mut() {
+                    img.alt.push_str(&text);
+                } else if let Some(table) = table.as_mut() {
+                    table.push_text(&text, style_state.inline_style(), tab_width, Some(span.start));
                 } else if let Some(h) = heading.as_mut() {
-                    h.text.push_str(&text);
+                    h.push_text(&text, span.start);
                 } else if let Some(block) = code_block.as_mut() {
-                    block.text.push_str(&text);
+                    block.push_text(&text, span.start);
                 } else {
                     line.ensure_prefix(
                         &current_prefix(blockquote_level, pending_list_prefix.as_deref()),
                         styles.prefix,
                     );
-                    line.push_text(&text, style_state.current_style(), tab_width);
+                    line.push_text(&text, style_state.current_style(), tab_width, Some(span.start));
                 }
             }
-            Event::Code(text) => {
-                if let Some(table) = table.as_mut() {
+            Event::Code(code) => {
+                let content_start = span.start + leading_backticks(text, &byte_range);
+                if let Some(img) = image.as_mut() {
+                    img.alt.push_str(&code);
+                } else if let Some(table) = table.as_mut() {
                     let inline = styles.inline_code.patch(style_state.inline_style());
-                    table.push_text(&text, inline, tab_width);
+                    table.push_text(&code, inline, tab_width, Some(content_start));
                 } else if let Some(h) = heading.as_mut() {
-                    h.text.push_str(&text);
+                    h.push_text(&code, content_start);
                 } else if let Some(block) = code_block.as_mut() {
-                    block.text.push_str(&text);
+                    block.push_text(&code, content_start);
                 } else {
                     line.ensure_prefix(
                         &current_prefix(blockquote_level, pending_list_prefix.as_deref()),
                         styles.prefix,
                     );
-                    line.push_text(&text, styles.inline_code, tab_width);
+                    line.push_text(&code, styles.inline_code, tab_width, Some(content_start));
                 }
             }
             Event::SoftBreak => {
@@ -271,20 +835,23 @@ pub fn parse_markdown(
                         &current_prefix(blockquote_level, pending_list_prefix.as_deref()),
                         styles.prefix,
                     );
-                    line.push_text(" ", style_state.current_style(), tab_width);
+                    line.push_text(" ", style_state.current_style(), tab_width, None);
                 }
             }
             Event::HardBreak => {
                 if let Some(table) = table.as_mut() {
                     table.push_break(style_state.inline_style(), tab_width);
                 } else {
-                    flush_line(&mut line, &mut raw_lines);
+                    flush_line(&mut line, &mut raw, &blocks);
                 }
             }
             Event::Rule => {
-                flush_line(&mut line, &mut raw_lines);
-                raw_lines.push(Line::from(Span::styled("─".repeat(48), styles.rule)));
-                push_blank_line(&mut raw_lines);
+                flush_line(&mut line, &mut raw, &blocks);
+                raw.push(
+                    Line::from(Span::styled("─".repeat(48), styles.rule)),
+                    Some(LineSource::new(span, Vec::new())),
+                );
+                raw.push_blank();
             }
             Event::TaskListMarker(checked) => {
                 let marker = if checked { "[x] " } else { "[ ] " };
@@ -292,18 +859,172 @@ pub fn parse_markdown(
                     &current_prefix(blockquote_level, pending_list_prefix.as_deref()),
                     styles.prefix,
                 );
-                line.push_text(marker, styles.prefix, tab_width);
+                line.push_text(marker, styles.prefix, tab_width, None);
             }
             _ => {}
         }
     }
 
-    flush_line(&mut line, &mut raw_lines);
+    flush_line(&mut line, &mut raw, &blocks);
 
-    Ok(ParsedDocument {
-        raw_lines,
-        headings,
-    })
+    let mut definitions: Vec<Definition> = parser
+        .reference_definitions()
+        .iter()
+        .map(|(label, def)| Definition {
+            offset: offsets.char_offset(def.span.start),
+            label: label.to_lowercase(),
+            dest: def.dest.to_string(),
+            title: def.title.as_deref().unwrap_or("").to_string(),
+        })
+        .collect();
+    definitions.sort_by_key(|d| d.offset);
+
+    Ok((split_blocks(raw, headings, chunks), definitions))
+}
+
+/// Cuts the parsed lines into top-level blocks with block-relative offsets.
+fn split_blocks(
+    raw: RawLines,
+    headings: Vec<HeadingRaw>,
+    chunks: Vec<(Range<usize>, usize, usize)>,
+) -> Vec<ParsedBlock> {
+    let mut lines = raw.lines.into_iter();
+    let mut sources = raw.sources.into_iter();
+    let total_headings = headings.len();
+    let mut headings = headings.into_iter();
+    let mut images = raw.images.into_iter().peekable();
+    let mut code = raw.code.into_iter().peekable();
+    let total = lines.len();
+    let mut out = Vec::with_capacity(chunks.len());
+    for (idx, (range, line_start, heading_start)) in chunks.iter().enumerate() {
+        let (line_end, heading_end) = chunks
+            .get(idx + 1)
+            .map(|(_, lines, headings)| (*lines, *headings))
+            .unwrap_or((total, total_headings));
+        let count = line_end - line_start;
+        let base = range.start as isize;
+        let raw_sources = sources
+            .by_ref()
+            .take(count)
+            .map(|source| {
+                source.map(|mut source| {
+                    source.shift(-base);
+                    source
+                })
+            })
+            .collect();
+        let block_headings = headings
+            .by_ref()
+            .take(heading_end - heading_start)
+            .map(|mut heading| {
+                heading.raw_line -= line_start;
+                heading
+            })
+            .collect();
+        let mut block_images = Vec::new();
+        while let Some(mut image) = images.next_if(|image| image.raw_line < line_end) {
+            image.raw_line -= line_start;
+            block_images.push(image);
+        }
+        let mut block_code = Vec::new();
+        while let Some(lines) = code.next_if(|lines| lines.start < line_end) {
+            block_code.push(lines.start - line_start..lines.end - line_start);
+        }
+        out.push(ParsedBlock {
+            id: NEXT_BLOCK_ID.fetch_add(1, AtomicOrdering::Relaxed),
+            range: range.clone(),
+            raw_lines: lines.by_ref().take(count).collect(),
+            raw_sources,
+            headings: block_headings,
+            images: block_images,
+            code: block_code,
+        });
+    }
+    out
+}
+
+fn is_inline_tag(tag: &Tag) -> bool {
+    matches!(
+        tag,
+        Tag::Emphasis | Tag::Strong | Tag::Strikethrough | Tag::Link { .. } | Tag::Image { .. }
+    )
+}
+
+fn is_inline_tag_end(tag: &TagEnd) -> bool {
+    matches!(
+        tag,
+        TagEnd::Emphasis | TagEnd::Strong | TagEnd::Strikethrough | TagEnd::Link | TagEnd::Image
+    )
+}
+
+/// Byte offset of the last line of a block, i.e. the closing fence of a code block.
+fn closing_line_start(text: &str, range: &Range<usize>) -> usize {
+    let body = text
+        .get(range.clone())
+        .unwrap_or("")
+        .trim_end_matches('\n');
+    match body.rfind('\n') {
+        Some(idx) => range.start + idx + 1,
+        None => range.start,
+    }
+}
+
+fn leading_backticks(text: &str, range: &Range<usize>) -> usize {
+    text.get(range.clone())
+        .map(|s| s.bytes().take_while(|b| *b == b'`').count())
+        .unwrap_or(0)
+}
+
+/// Converts parser byte offsets into char offsets of the original, un-normalized input.
+struct OffsetMapper<'a> {
+    text: &'a str,
+    // (byte, char) offsets of every line start in the normalized text.
+    line_starts: Vec<(usize, usize)>,
+    // Normalized char offsets of line feeds whose preceding `\r` was dropped.
+    dropped_cr: Vec<usize>,
+}
+
+impl<'a> OffsetMapper<'a> {
+    fn new(text: &'a str, original: &str) -> Self {
+        let mut line_starts = vec![(0, 0)];
+        for (chars, (byte, ch)) in text.char_indices().enumerate() {
+            if ch == '\n' {
+                line_starts.push((byte + 1, chars + 1));
+            }
+        }
+        let mut dropped_cr = Vec::new();
+        if original.len() != text.len() {
+            let mut idx = 0usize;
+            let mut chars = original.chars().peekable();
+            while let Some(ch) = chars.next() {
+                if ch == '\r' && chars.peek() == Some(&'\n') {
+                    dropped_cr.push(idx);
+                    continue;
+                }
+                idx += 1;
+            }
+        }
+        Self {
+            text,
+            line_starts,
+            dropped_cr,
+        }
+    }
+
+    fn char_offset(&self, byte: usize) -> usize {
+        let mut byte = byte.min(self.text.len());
+        while !self.text.is_char_boundary(byte) {
+            byte -= 1;
+        }
+        let idx = self.line_starts.partition_point(|&(start, _)| start <= byte) - 1;
+        let (line_byte, line_chars) = self.line_starts[idx];
+        let normalized = line_chars + self.text[line_byte..byte].chars().count();
+        normalized + self.dropped_cr.partition_point(|&d| d <= normalized)
+    }
+
+    fn char_range(&self, range: &Range<usize>) -> Range<usize> {
+        self.char_offset(range.start)..self.char_offset(range.end)
+    }
 }
 
 fn normalize_line_endings(input: &str) -> Cow<'_, str> {
@@ -314,114 +1035,291 @@ fn normalize_line_endings(input: &str) -> Cow<'_, str> {
     }
 }
 
+/// Wraps the parsed document to `width`. Images that fit `images` take exactly
+/// `images.rows` lines, the rest a single placeholder line.
 pub fn wrap_document(
     parsed: &ParsedDocument,
     width: u16,
     query: Option<&str>,
     case_sensitive: bool,
+    images: Option<ImageBoxes>,
+) -> RenderedDocument {
+    rewrap_document(parsed, None, width, query, case_sensitive, images)
+}
+
+/// Wraps `parsed`, reusing the lines of blocks that are unchanged since `previous`
+/// was wrapped with the same settings.
+pub fn rewrap_document(
+    parsed: &ParsedDocument,
+    previous: Option<RenderedDocument>,
+    width: u16,
+    query: Option<&str>,
+    case_sensitive: bool,
+    images: Option<ImageBoxes>,
 ) -> RenderedDocument {
     let width = width.max(1);
-    let mut wrapped_lines: Vec<Line<'static>> = Vec::new();
-    let mut raw_to_wrapped: Vec<usize> = Vec::with_capacity(parsed.raw_lines.len());
+    let images = images.filter(|boxes| boxes.rows > 0);
+    let query = query.filter(|q| !q.is_empty());
+    let mut doc = previous
+        .filter(|doc| {
+            doc.layout.width == width
+                && doc.layout.query.as_deref() == query
+                && doc.layout.case_sensitive == case_sensitive
+                && doc.layout.images.as_ref().map(|(rows, base)| (*rows, base.as_path()))
+                    == images.map(|boxes| (boxes.rows, boxes.base))
+        })
+        .unwrap_or_else(|| RenderedDocument {
+            layout: WrapLayout {
+                width,
+                query: query.map(str::to_string),
+                case_sensitive,
+                images: images.map(|boxes| (boxes.rows, boxes.base.to_path_buf())),
+                blocks: Vec::new(),
+            },
+            ..RenderedDocument::default()
+        });
 
-    for line in &parsed.raw_lines {
-        raw_to_wrapped.push(wrapped_lines.len());
-        let mut wrapped = wrap_line(line, width as usize);
-        if wrapped.is_empty() {
-            wrapped.push(Line::from(""));
+    let old = &doc.layout.blocks;
+    let new = &parsed.blocks;
+    let head = old
+        .iter()
+        .zip(new)
+        .take_while(|(a, b)| a.id == b.id)
+        .count();
+    let tail = old[head..]
+        .iter()
+        .rev()
+        .zip(new[head..].iter().rev())
+        .take_while(|(a, b)| a.id == b.id)
+        .count();
+    let line_start: usize = old[..head].iter().map(|b| b.lines).sum();
+    let line_end = line_start
+        + old[head..old.len() - tail]
+            .iter()
+            .map(|b| b.lines)
+            .sum::<usize>();
+    let tail_delta = match (old.len() - tail, new.len() - tail) {
+        (o, n) if tail > 0 => new[n].range.start as isize - old[o].start as isize,
+        _ => 0,
+    };
+
+    let mut lines = Vec::new();
+    let mut sources = Vec::new();
+    let mut layout = Vec::new();
+    for block in &new[head..new.len() - tail] {
+        layout.push(wrap_block(
+            block,
+            width as usize,
+            images,
+            &mut lines,
+            &mut sources,
+        ));
+    }
+    let mut plain: Vec<String> = lines.iter().map(line_to_plain).collect();
+    let mut matches = Vec::new();
+    if let Some(q) = query {
+        matches = find_matches(&plain, q, case_sensitive);
+        let match_map = build_match_map(&matches);
+        for (idx, ranges) in match_map {
+            lines[idx] = apply_highlight(&lines[idx], &ranges);
+        }
+        plain = lines.iter().map(line_to_plain).collect();
+    }
+
+    let inserted = lines.len();
+    // Matches outside the rewrapped lines keep their columns; only their line moves.
+    doc.matches.retain(|m| m.line < line_start || m.line >= line_end);
+    let at = doc.matches.partition_point(|m| m.line < line_start);
+    for m in &mut doc.matches[at..] {
+        m.line = m.line - line_end + line_start + inserted;
+    }
+    doc.matches.splice(
+        at..at,
+        matches.into_iter().map(|m| Match {
+            line: m.line + line_start,
+            ..m
+        }),
+    );
+    doc.lines.splice(line_start..line_end, lines);
+    doc.plain_lines.splice(line_start..line_end, plain);
+    doc.sources.splice(line_start..line_end, sources);
+    let old_len = doc.layout.blocks.len();
+    doc.layout.blocks.splice(head..old_len - tail, layout);
+    if tail_delta != 0 {
+        for source in doc.sources[line_start + inserted..].iter_mut().flatten() {
+            source.shift(tail_delta);
+        }
+        let count = doc.layout.blocks.len();
+        for block in &mut doc.layout.blocks[count - tail..] {
+            block.start = block.start.saturating_add_signed(tail_delta);
         }
-        wrapped_lines.extend(wrapped);
     }
 
-    let mut headings = Vec::new();
-    for h in &parsed.headings {
-        let line = raw_to_wrapped
-            .get(h.raw_line)
-            .copied()
-            .unwrap_or(0);
-        headings.push(Heading {
-            level: h.level,
-            title: h.title.clone(),
-            line,
-        });
+    doc.headings.clear();
+    doc.images.clear();
+    doc.code_blocks.clear();
+    let mut offset = 0usize;
+    for (wrapped, block) in doc.layout.blocks.iter().zip(&parsed.blocks) {
+        for (heading, lines) in block.headings.iter().zip(&wrapped.headings) {
+            doc.headings.push(Heading {
+                level: heading.level,
+                title: heading.title.clone(),
+                line: offset + lines.start,
+                rows: lines.len(),
+            });
+        }
+        for lines in &wrapped.code {
+            doc.code_blocks.push(offset + lines.start..offset + lines.end);
+        }
+        if let Some(boxes) = images {
+            for &(idx, line) in &wrapped.images {
+                doc.images.push(RenderedImage {
+                    line: offset + line,
+                    rows: boxes.rows as usize,
+                    dest: block.images[idx].dest.clone(),
+                });
+            }
+        }
+        offset += wrapped.lines;
     }
+    doc
+}
 
-    let mut plain_lines: Vec<String> = wrapped_lines.iter().map(line_to_plain).collect();
-
-    let matches = if let Some(q) = query {
-        if q.is_empty() {
-            Vec::new()
-        } else {
-            find_matches(&plain_lines, q, case_sensitive)
+fn wrap_block(
+    block: &ParsedBlock,
+    width: usize,
+    images: Option<ImageBoxes>,
+    lines: &mut Vec<Line<'static>>,
+    sources: &mut Vec<Option<LineSource>>,
+) -> WrappedBlock {
+    let first = lines.len();
+    let boxed: Vec<usize> = match images {
+        Some(boxes) => (0..block.images.len())
+            .filter(|&idx| boxes.fits(&block.images[idx].dest))
+            .collect(),
+        None => Vec::new(),
+    };
+    let image_rows = images.map_or(0, |boxes| boxes.rows as usize);
+    let mut raw_to_wrapped = Vec::with_capacity(block.raw_lines.len());
+    for (idx, (line, source)) in block.raw_lines.iter().zip(&block.raw_sources).enumerate() {
+        raw_to_wrapped.push(lines.len() - first);
+        let (mut wrapped, mut starts) = wrap_line_with_starts(line, width);
+        if wrapped.is_empty() {
+            wrapped.push(Line::from(""));
+            starts = vec![0];
         }
-    } else {
-        Vec::new()
+        if boxed.iter().any(|&image| block.images[image].raw_line == idx) {
+            // A fixed-height box: the placeholder on top, blank rows below it.
+            wrapped.truncate(1);
+            wrapped.resize(image_rows, Line::from(""));
+            starts = vec![0; image_rows];
+        }
+        for (pos, &start) in starts.iter().enumerate() {
+            let end = starts.get(pos + 1).copied().unwrap_or(usize::MAX);
+            sources.push(source.as_ref().map(|s| {
+                let mut slice = s.slice(start, end);
+                slice.shift(block.range.start as isize);
+                slice
+            }));
+        }
+        lines.extend(wrapped);
+    }
+    let total = lines.len() - first;
+    let wrapped_range = |raw: Range<usize>| {
+        let start = raw_to_wrapped.get(raw.start).copied().unwrap_or(total);
+        let end = raw_to_wrapped.get(raw.end).copied().unwrap_or(total);
+        start..end
     };
-
-    if !matches.is_empty() {
-        let match_map = build_match_map(&matches);
-        let highlighted: Vec<Line<'static>> = wrapped_lines
+    WrappedBlock {
+        id: block.id,
+        start: block.range.start,
+        lines: total,
+        headings: block
+            .headings
             .iter()
-            .enumerate()
-            .map(|(idx, line)| {
-                if let Some(ranges) = match_map.get(&idx) {
-                    apply_highlight(line, ranges)
-                } else {
-                    line.clone()
-                }
+            .map(|h| wrapped_range(h.raw_line..h.raw_line + 1))
+            .collect(),
+        images: boxed
+            .into_iter()
+            .map(|idx| {
+                let line = block.images[idx].raw_line;
+                (idx, raw_to_wrapped.get(line).copied().unwrap_or(0))
             })
-            .collect();
-        wrapped_lines = highlighted;
-    }
-
-    if !matches.is_empty() {
-        plain_lines = wrapped_lines.iter().map(line_to_plain).collect();
+            .collect(),
+        code: block.code.iter().cloned().map(wrapped_range).collect(),
     }
+}
 
-    RenderedDocument {
-        lines: wrapped_lines,
-        plain_lines,
-        headings,
-        matches,
+/// Compiles a `/` query as a regular expression. `\c` anywhere in the query
+/// forces a case-insensitive search and `\C` a case-sensitive one; otherwise
+/// the search is case-sensitive when `case_sensitive` is set or the query
+/// contains an uppercase letter (smart-case). `\<` and `\>` match word starts
+/// and ends.
+pub fn search_regex(query: &str, case_sensitive: bool) -> Result<Regex, regex::Error> {
+    let mut pattern = String::with_capacity(query.len());
+    let mut forced = None;
+    let mut has_upper = false;
+    let mut chars = query.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            has_upper |= ch.is_uppercase();
+            pattern.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('c') => forced = Some(false),
+            Some('C') => forced = Some(true),
+            Some(next) => {
+                pattern.push('\\');
+                pattern.push(next);
+            }
+            None => pattern.push('\\'),
+        }
     }
+    let sensitive = forced.unwrap_or(case_sensitive || has_upper);
+    RegexBuilder::new(&pattern)
+        .case_insensitive(!sensitive)
+        .build()
 }
 
+/// Finds non-empty matches of `query` in `lines`. `start` and `end` are char
+/// columns. An invalid pattern matches nothing.
 pub fn find_matches(lines: &[String], query: &str, case_sensitive: bool) -> Vec<Match> {
-    let needle = if case_sensitive {
-        query.to_string()
-    } else {
-        query.to_ascii_lowercase()
+    if query.is_empty() {
+        return Vec::new();
+    }
+    let Ok(regex) = search_regex(query, case_sensitive) else {
+        return Vec::new();
     };
 
     let mut out = Vec::new();
     for (line_idx, line) in lines.iter().enumerate() {
-        if needle.is_empty() {
-            break;
-        }
-        let hay = if case_sensitive {
-            line.clone()
-        } else {
-            line.to_ascii_lowercase()
-        };
-        let mut cursor = 0;
-        while cursor < hay.len() {
-            if let Some(found) = hay[cursor..].find(&needle) {
-                let start = cursor + found;
-                let end = start + needle.len();
-                out.push(Match {
-                    line: line_idx,
-                    start,
-                    end,
-                });
-                cursor = end;
-            } else {
-                break;
-            }
+        for range in match_char_ranges(&regex, line) {
+            out.push(Match {
+                line: line_idx,
+                start: range.start,
+                end: range.end,
+            });
         }
     }
     out
 }
 
+/// Char ranges of the non-empty matches of `regex` in `text`.
+pub fn match_char_ranges(regex: &Regex, text: &str) -> Vec<Range<usize>> {
+    let mut out = Vec::new();
+    let mut chars = 0usize;
+    let mut byte = 0usize;
+    for found in regex.find_iter(text).filter(|m| !m.is_empty()) {
+        chars += text[byte..found.start()].chars().count();
+        let start = chars;
+        chars += found.as_str().chars().count();
+        byte = found.end();
+        out.push(start..chars);
+    }
+    out
+}
+
 fn heading_style(styles: &MarkdownStyles, level: u8) -> Style {
     let idx = level.saturating_sub(1).min(5) as usize;
     styles.heading[idx]
@@ -439,11 +1337,11 @@ fn build_match_map(matches: &[Match]) -> std::collections::HashMap<usize, Vec<st
         ranges.sort_by_key(|r| r.start);
         let mut merged: Vec<std::ops::Range<usize>> = Vec::new();
         for r in ranges.drain(..) {
-            if let Some(last) = merged.last_mut() {
-                if r.start <= last.end {
-                    last.end = last.end.max(r.end);
-                    continue;
-                }
+            if let Some(last) = merged.last_mut()
+                && r.start <= last.end
+            {
+                last.end = last.end.max(r.end);
+                continue;
             }
             merged.push(r);
         }
@@ -462,15 +1360,17 @@ fn apply_highlight(line: &Line<'static>, ranges: &[std::ops::Range<usize>]) -> L
 
     for span in &line.spans {
         let text = span.content.as_ref();
+        let char_count = text.chars().count();
+        let byte_at = |col: usize| text.char_indices().nth(col).map_or(text.len(), |(b, _)| b);
         let span_start = cursor;
-        let span_end = cursor + text.len();
+        let span_end = cursor + char_count;
 
         let mut local_idx = 0usize;
         for range in ranges.iter().filter(|r| r.end > span_start && r.start < span_end) {
             let start = range.start.max(span_start);
             let end = range.end.min(span_end);
-            let local_start = start - span_start;
-            let local_end = end - span_start;
+            let local_start = byte_at(start - span_start);
+            let local_end = byte_at(end - span_start);
 
             if local_start > local_idx {
                 out_spans.push(Span::styled(
@@ -494,15 +1394,21 @@ fn apply_highlight(line: &Line<'static>, ranges: &[std::ops::Range<usize>]) -> L
             ));
         }
 
-        cursor += text.len();
+        cursor += char_count;
     }
 
     Line::from(out_spans)
 }
 
+#[cfg(test)]
 fn wrap_line(line: &Line<'static>, width: usize) -> Vec<Line<'static>> {
+    wrap_line_with_starts(line, width).0
+}
+
+/// Wraps a line and reports the column of the original line each wrapped line starts at.
+fn wrap_line_with_starts(line: &Line<'static>, width: usize) -> (Vec<Line<'static>>, Vec<usize>) {
     if width == 0 {
-        return vec![line.clone()];
+        return (vec![line.clone()], vec![0]);
     }
 
     let fill_bg = line_uniform_bg(line);
@@ -512,16 +1418,25 @@ fn wrap_line(line: &Line<'static>, width: usize) -> Vec<Line<'static>> {
     let tokens = tokenize_line(line);
     if tokens.is_empty() {
         if let (Some(style), Some(fill_width)) = (fill_style, fill_width) {
-            return vec![Line::from(Span::styled(" ".repeat(fill_width), style))];
+            return (
+                vec![Line::from(Span::styled(" ".repeat(fill_width), style))],
+                vec![0],
+            );
         }
-        return vec![Line::from("")];
+        return (vec![Line::from("")], vec![0]);
     }
 
     let mut out: Vec<Line<'static>> = Vec::new();
+    let mut starts: Vec<usize> = Vec::new();
     let mut current: Vec<Span<'static>> = Vec::new();
     let mut current_width = 0usize;
+    let mut current_start = 0usize;
 
-    let push_current = |current: &mut Vec<Span<'static>>, out: &mut Vec<Line<'static>>| {
+    let push_current = |current: &mut Vec<Span<'static>>,
+                        out: &mut Vec<Line<'static>>,
+                        starts: &mut Vec<usize>,
+                        start: usize| {
+        starts.push(start);
         if current.is_empty() {
             if let (Some(style), Some(fill_width)) = (fill_style, fill_width) {
                 out.push(Line::from(Span::styled(" ".repeat(fill_width), style)));
@@ -537,7 +1452,7 @@ fn wrap_line(line: &Line<'static>, width: usize) -> Vec<Line<'static>> {
                 current.push(Span::styled(" ".repeat(fill_width - width_now), style));
             }
         }
-        out.push(Line::from(current.drain(..).collect::<Vec<_>>()));
+        out.push(Line::from(std::mem::take(current)));
     };
 
     for token in tokens {
@@ -547,7 +1462,7 @@ fn wrap_line(line: &Line<'static>, width: usize) -> Vec<Line<'static>> {
             }
             let w = UnicodeWidthStr::width(token.text.as_str());
             if current_width + w > width {
-                push_current(&mut current, &mut out);
+                push_current(&mut current, &mut out, &mut starts, current_start);
                 current_width = 0;
                 continue;
             }
@@ -559,24 +1474,30 @@ fn wrap_line(line: &Line<'static>, width: usize) -> Vec<Line<'static>> {
         let token_width = UnicodeWidthStr::width(token.text.as_str());
         if token_width <= width {
             if current_width + token_width > width && !current.is_empty() {
-                push_current(&mut current, &mut out);
+                push_current(&mut current, &mut out, &mut starts, current_start);
                 current_width = 0;
             }
+            if current.is_empty() {
+                current_start = token.col;
+            }
             current.push(Span::styled(token.text, token.style));
             current_width += token_width;
         } else {
             if !current.is_empty() {
-                push_current(&mut current, &mut out);
+                push_current(&mut current, &mut out, &mut starts, current_start);
                 current_width = 0;
             }
             let mut buf = String::new();
             let mut buf_width = 0usize;
-            for ch in token.text.chars() {
+            let mut buf_start = token.col;
+            for (offset, ch) in token.text.chars().enumerate() {
                 let ch_width = UnicodeWidthChar::width(ch).unwrap_or(0);
                 if buf_width + ch_width > width && !buf.is_empty() {
                     out.push(Line::from(Span::styled(buf.clone(), token.style)));
+                    starts.push(buf_start);
                     buf.clear();
                     buf_width = 0;
+                    buf_start = token.col + offset;
                 }
                 buf.push(ch);
                 buf_width += ch_width;
@@ -584,12 +1505,13 @@ fn wrap_line(line: &Line<'static>, width: usize) -> Vec<Line<'static>> {
             if !buf.is_empty() {
                 current.push(Span::styled(buf, token.style));
                 current_width = buf_width;
+                current_start = buf_start;
             }
         }
     }
 
-    push_current(&mut current, &mut out);
-    out
+    push_current(&mut current, &mut out, &mut starts, current_start);
+    (out, starts)
 }
 
 fn trim_trailing_ws(spans: &mut Vec<Span<'static>>) {
@@ -620,9 +1542,7 @@ fn line_uniform_bg(line: &Line<'static>) -> Option<Color> {
         if span.content.is_empty() {
             continue;
         }
-        let Some(color) = span.style.bg else {
-            return None;
-        };
+        let color = span.style.bg?;
         if color == Color::Reset {
             return None;
         }
@@ -637,6 +1557,7 @@ fn line_uniform_bg(line: &Line<'static>) -> Option<Color> {
 
 fn tokenize_line(line: &Line<'static>) -> Vec<Token> {
     let mut tokens = Vec::new();
+    let mut col = 0usize;
     for span in &line.spans {
         let text = span.content.as_ref();
         if text.is_empty() {
@@ -650,22 +1571,28 @@ fn tokenize_line(line: &Line<'static>) -> Vec<Token> {
                 current_ws = Some(is_ws);
             }
             if Some(is_ws) != current_ws {
+                let len = buf.chars().count();
                 tokens.push(Token {
                     text: buf.clone(),
                     style: span.style,
                     is_whitespace: current_ws.unwrap_or(false),
+                    col,
                 });
+                col += len;
                 buf.clear();
                 current_ws = Some(is_ws);
             }
             buf.push(ch);
         }
         if !buf.is_empty() {
+            let len = buf.chars().count();
             tokens.push(Token {
                 text: buf,
                 style: span.style,
                 is_whitespace: current_ws.unwrap_or(false),
+                col,
             });
+            col += len;
         }
     }
     tokens
@@ -679,7 +1606,7 @@ fn line_to_plain(line: &Line<'static>) -> String {
     out
 }
 
-fn render_table(table: &TableBuilder, styles: &MarkdownStyles, raw_lines: &mut Vec<Line<'static>>) {
+fn render_table(table: &TableBuilder, styles: &MarkdownStyles, raw: &mut RawLines) {
     if table.rows.is_empty() {
         return;
     }
@@ -702,11 +1629,11 @@ fn render_table(table: &TableBuilder, styles: &MarkdownStyles, raw_lines: &mut V
     }
 
     let border = styles.table_border;
-    raw_lines.push(table_border_line(
-        &widths,
-        ('┌', '┬', '┐'),
-        border,
-    ));
+    let border_source = || Some(LineSource::new(table.range.clone(), Vec::new()));
+    raw.push(
+        table_border_line(&widths, ('┌', '┬', '┐'), border),
+        border_source(),
+    );
 
     let mut header_len = 0usize;
     for row in &table.rows {
@@ -722,37 +1649,41 @@ fn render_table(table: &TableBuilder, styles: &MarkdownStyles, raw_lines: &mut V
         header_len = 1;
     }
 
+    let row_source = |row: &TableRow, anchors| {
+        let range = row.source.clone().unwrap_or_else(|| table.range.clone());
+        Some(LineSource::new(range, anchors))
+    };
     for row in table.rows.iter().take(header_len) {
-        raw_lines.push(table_row_line(
+        let (line, anchors) = table_row_line(
             row,
             &widths,
             &table.alignments,
             styles.table_header,
             border,
-        ));
+        );
+        raw.push(line, row_source(row, anchors));
     }
     if header_len > 0 && header_len < table.rows.len() {
-        raw_lines.push(table_border_line(
-            &widths,
-            ('├', '┼', '┤'),
-            border,
-        ));
+        raw.push(
+            table_border_line(&widths, ('├', '┼', '┤'), border),
+            border_source(),
+        );
     }
     for row in table.rows.iter().skip(header_len) {
-        raw_lines.push(table_row_line(
+        let (line, anchors) = table_row_line(
             row,
             &widths,
             &table.alignments,
             styles.base,
             border,
-        ));
+        );
+        raw.push(line, row_source(row, anchors));
     }
 
-    raw_lines.push(table_border_line(
-        &widths,
-        ('└', '┴', '┘'),
-        border,
-    ));
+    raw.push(
+        table_border_line(&widths, ('└', '┴', '┘'), border),
+        border_source(),
+    );
 }
 
 fn table_border_line(
@@ -778,10 +1709,12 @@ fn table_row_line(
     alignments: &[Alignment],
     cell_style: Style,
     border_style: Style,
-) -> Line<'static> {
+) -> (Line<'static>, Vec<SourceAnchor>) {
     let mut spans = Vec::new();
+    let mut anchors = Vec::new();
     spans.push(Span::styled("│", border_style));
-    for idx in 0..widths.len() {
+    let mut col = 1usize;
+    for (idx, &width) in widths.iter().enumerate() {
         let cell = row.cells.get(idx);
         let text = cell.map(|c| c.text.as_str()).unwrap_or("");
         let align = alignments
@@ -789,10 +1722,15 @@ fn table_row_line(
             .copied()
             .unwrap_or(Alignment::Left);
         let text_width = UnicodeWidthStr::width(text);
-        let (left_pad, right_pad) = cell_padding(text_width, widths[idx], align);
+        let (left_pad, right_pad) = cell_padding(text_width, width, align);
 
         spans.push(Span::styled(" ".repeat(1 + left_pad), cell_style));
+        col += 1 + left_pad;
         if let Some(cell) = cell {
+            if let Some(offset) = cell.source {
+                anchors.push(SourceAnchor { col, offset });
+            }
+            col += cell.text.chars().count();
             for fragment in &cell.spans {
                 spans.push(Span::styled(
                     fragment.text.clone(),
@@ -802,8 +1740,9 @@ fn table_row_line(
         }
         spans.push(Span::styled(" ".repeat(1 + right_pad), cell_style));
         spans.push(Span::styled("│", border_style));
+        col += 2 + right_pad;
     }
-    Line::from(spans)
+    (Line::from(spans), anchors)
 }
 
 fn cell_padding(text_width: usize, width: usize, align: Alignment) -> (usize, usize) {
@@ -827,7 +1766,7 @@ fn render_code_block(
     syntax_set: &SyntaxSet,
     theme: &Theme,
     styles: &MarkdownStyles,
-    raw_lines: &mut Vec<Line<'static>>,
+    raw: &mut RawLines,
 ) {
     let syntax = resolve_code_syntax(syntax_set, block.language.as_deref());
     let mut highlighter = HighlightLines::new(syntax, theme);
@@ -845,6 +1784,12 @@ fn render_code_block(
         }
     }
     let inner_width = max_width.saturating_add(2);
+    let source = |anchor: Option<usize>| {
+        let anchors = anchor
+            .map(|offset| vec![SourceAnchor { col: 0, offset }])
+            .unwrap_or_default();
+        Some(LineSource::new(block.range.clone(), anchors))
+    };
 
     let label = block
         .language
@@ -857,26 +1802,36 @@ fn render_code_block(
         let dashes = inner_width - header_width;
         let left = dashes / 2;
         let right = dashes - left;
-        raw_lines.push(Line::from(vec![
-            Span::styled("┌", border_style),
-            Span::styled("─".repeat(left), border_style),
-            Span::styled(header, header_style),
-            Span::styled("─".repeat(right), border_style),
-            Span::styled("┐", border_style),
-        ]));
+        raw.push(
+            Line::from(vec![
+                Span::styled("┌", border_style),
+                Span::styled("─".repeat(left), border_style),
+                Span::styled(header, header_style),
+                Span::styled("─".repeat(right), border_style),
+                Span::styled("┐", border_style),
+            ]),
+            source(Some(block.range.start)),
+        );
     } else {
-        raw_lines.push(Line::from(Span::styled(
-            format!("┌{}┐", "─".repeat(inner_width)),
-            border_style,
-        )));
-    }
-    raw_lines.push(Line::from(vec![
-        Span::styled("│", border_style),
-        Span::styled(" ".repeat(inner_width), pad_style),
-        Span::styled("│", border_style),
-    ]));
+        raw.push(
+            Line::from(Span::styled(
+                format!("┌{}┐", "─".repeat(inner_width)),
+                border_style,
+            )),
+            source(Some(block.range.start)),
+        );
+    }
+    raw.push(
+        Line::from(vec![
+            Span::styled("│", border_style),
+            Span::styled(" ".repeat(inner_width), pad_style),
+            Span::styled("│", border_style),
+        ]),
+        source(None),
+    );
 
-    for line in LinesWithEndings::from(&block.text) {
+    let body_start = raw.len();
+    for (idx, line) in LinesWithEndings::from(&block.text).enumerate() {
         let ranges = match highlighter.highlight_line(line, syntax_set) {
             Ok(r) => r,
             Err(_) => vec![(syntect::highlighting::Style::default(), line)],
@@ -902,19 +1857,53 @@ fn render_code_block(
         }
         spans.push(Span::styled(" ", pad_style));
         spans.push(Span::styled("│", border_style));
-        raw_lines.push(Line::from(spans));
+        let anchors = block
+            .line_starts
+            .get(idx)
+            .map(|&offset| vec![SourceAnchor { col: 2, offset }])
+            .unwrap_or_default();
+        raw.push(
+            Line::from(spans),
+            Some(LineSource::new(block.range.clone(), anchors)),
+        );
     }
+    raw.code.push(body_start..raw.len());
 
-    raw_lines.push(Line::from(vec![
-        Span::styled("│", border_style),
-        Span::styled(" ".repeat(inner_width), pad_style),
-        Span::styled("│", border_style),
-    ]));
+    raw.push(
+        Line::from(vec![
+            Span::styled("│", border_style),
+            Span::styled(" ".repeat(inner_width), pad_style),
+            Span::styled("│", border_style),
+        ]),
+        source(None),
+    );
     let bottom = format!("└{}┘", "─".repeat(inner_width));
-    raw_lines.push(Line::from(Span::styled(bottom, border_style)));
+    raw.push(
+        Line::from(Span::styled(bottom, border_style)),
+        source(Some(block.closing)),
+    );
 }
 
-fn resolve_code_syntax<'a>(
+pub fn slugify_anchor(text: &str) -> String {
+    let mut out = String::new();
+    let mut last_dash = false;
+    for ch in text.chars().flat_map(char::to_lowercase) {
+        if ch.is_alphanumeric() {
+            out.push(ch);
+            last_dash = false;
+            continue;
+        }
+        if (ch.is_whitespace() || ch == '-' || ch == '_')
+            && !out.is_empty() && !last_dash
+        {
+            out.push('-');
+            last_dash = true;
+        }
+    }
+    out.trim_matches('-').to_string()
+}
+
+pub fn resolve_code_syntax<'a>(
     syntax_set: &'a SyntaxSet,
     lang: Option<&str>,
 ) -> &'a syntect::parsing::SyntaxReference {
@@ -1022,6 +2011,8 @@ impl StyleState {
 struct LineBuilder {
     spans: Vec<Span<'static>>,
     plain: String,
+    cols: usize,
+    anchors: Vec<SourceAnchor>,
 }
 
 impl LineBuilder {
@@ -1029,6 +2020,8 @@ impl LineBuilder {
         Self {
             spans: Vec::new(),
             plain: String::new(),
+            cols: 0,
+            anchors: Vec::new(),
         }
     }
 
@@ -1036,34 +2029,71 @@ impl LineBuilder {
         if self.plain.is_empty() && !prefix.is_empty() {
             self.spans.push(Span::styled(prefix.to_string(), style));
             self.plain.push_str(prefix);
+            self.cols += prefix.chars().count();
         }
     }
 
-    fn push_text(&mut self, text: &str, style: Style, tab_width: usize) {
+    fn push_text(&mut self, text: &str, style: Style, tab_width: usize, source: Option<usize>) {
         let expanded = expand_tabs(text, tab_width);
+        if let Some(offset) = source {
+            self.anchors.push(SourceAnchor {
+                col: self.cols,
+                offset,
+            });
+        }
+        self.cols += expanded.chars().count();
         self.spans.push(Span::styled(expanded.clone(), style));
         self.plain.push_str(&expanded);
     }
 
-    fn take_line(&mut self) -> Option<(Line<'static>, String)> {
+    fn take_line(&mut self) -> Option<(Line<'static>, Vec<SourceAnchor>)> {
         if self.plain.is_empty() {
             return None;
         }
         let line = Line::from(self.spans.drain(..).collect::<Vec<_>>());
-        let plain = std::mem::take(&mut self.plain);
-        Some((line, plain))
+        self.plain.clear();
+        self.cols = 0;
+        Some((line, std::mem::take(&mut self.anchors)))
     }
 }
 
-fn flush_line(builder: &mut LineBuilder, raw_lines: &mut Vec<Line<'static>>) {
-    if let Some((line, plain)) = builder.take_line() {
-        raw_lines.push(line);
-        let _ = plain;
+/// Rendered lines paired with the source location each one was produced from.
+#[derive(Default)]
+struct RawLines {
+    lines: Vec<Line<'static>>,
+    sources: Vec<Option<LineSource>>,
+    images: Vec<ImageRaw>,
+    code: Vec<Range<usize>>,
+    // Whether earlier blocks, parsed separately, already produced output.
+    preceded: bool,
+}
+
+impl RawLines {
+    fn push(&mut self, line: Line<'static>, source: Option<LineSource>) {
+        self.lines.push(line);
+        self.sources.push(source);
+    }
+
+    fn push_blank(&mut self) {
+        self.push(Line::from(""), None);
+    }
+
+    fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        !self.preceded && self.lines.is_empty()
     }
 }
 
-fn push_blank_line(raw_lines: &mut Vec<Line<'static>>) {
-    raw_lines.push(Line::from(""));
+fn flush_line(builder: &mut LineBuilder, raw: &mut RawLines, blocks: &[Range<usize>]) {
+    if let Some((line, anchors)) = builder.take_line() {
+        let source = blocks
+            .last()
+            .map(|range| LineSource::new(range.clone(), anchors));
+        raw.push(line, source);
+    }
 }
 
 fn list_prefix(stack: &mut [ListKind]) -> String {
@@ -1103,6 +2133,7 @@ fn expand_tabs(text: &str, tab_width: usize) -> String {
 struct HeadingBuilder {
     level: u8,
     text: String,
+    anchors: Vec<SourceAnchor>,
 }
 
 impl HeadingBuilder {
@@ -1110,17 +2141,45 @@ impl HeadingBuilder {
         Self {
             level,
             text: String::new(),
+            anchors: Vec::new(),
         }
     }
+
+    fn push_text(&mut self, text: &str, offset: usize) {
+        self.anchors.push(SourceAnchor {
+            col: self.text.chars().count(),
+            offset,
+        });
+        self.text.push_str(text);
+    }
+
+    /// Returns the trimmed title with anchors shifted to match the trimming.
+    fn finish(&self) -> (String, Vec<SourceAnchor>) {
+        let trimmed = self.text.trim_start();
+        let lead = self.text.chars().count() - trimmed.chars().count();
+        let anchors = self
+            .anchors
+            .iter()
+            .map(|a| SourceAnchor {
+                col: a.col.saturating_sub(lead),
+                offset: a.offset + lead.saturating_sub(a.col),
+            })
+            .collect();
+        (trimmed.trim_end().to_string(), anchors)
+    }
 }
 
 struct CodeBlock {
     language: Option<String>,
     text: String,
+    range: Range<usize>,
+    // Source offset of each code line, and of the closing fence.
+    line_starts: Vec<usize>,
+    closing: usize,
 }
 
 impl CodeBlock {
-    fn new(kind: CodeBlockKind) -> Self {
+    fn new(kind: CodeBlockKind, range: Range<usize>) -> Self {
         let language = match kind {
             CodeBlockKind::Fenced(lang) => {
                 let trimmed = lang.trim().to_string();
@@ -1135,8 +2194,22 @@ impl CodeBlock {
         Self {
             language,
             text: String::new(),
+            closing: range.end.saturating_sub(1),
+            range,
+            line_starts: Vec::new(),
         }
     }
+
+    fn push_text(&mut self, text: &str, offset: usize) {
+        let mut at_line_start = self.text.is_empty() || self.text.ends_with('\n');
+        for (idx, ch) in text.chars().enumerate() {
+            if at_line_start {
+                self.line_starts.push(offset + idx);
+            }
+            at_line_start = ch == '\n';
+        }
+        self.text.push_str(text);
+    }
 }
 
 #[derive(Clone)]
@@ -1149,12 +2222,14 @@ struct TableSpan {
 struct TableCell {
     text: String,
     spans: Vec<TableSpan>,
+    source: Option<usize>,
 }
 
 #[derive(Clone)]
 struct TableRow {
     cells: Vec<TableCell>,
     is_header: bool,
+    source: Option<Range<usize>>,
 }
 
 struct TableBuilder {
@@ -1163,6 +2238,9 @@ struct TableBuilder {
     current_row: Vec<TableCell>,
     current_cell: String,
     current_cell_spans: Vec<TableSpan>,
+    current_cell_source: Option<usize>,
+    row_source: Option<Range<usize>>,
+    range: Range<usize>,
     in_head: bool,
     saw_head: bool,
 }
@@ -1175,6 +2253,9 @@ impl TableBuilder {
             current_row: Vec::new(),
             current_cell: String::new(),
             current_cell_spans: Vec::new(),
+            current_cell_source: None,
+            row_source: None,
+            range: 0..0,
             in_head: false,
             saw_head: false,
         }
@@ -1184,24 +2265,30 @@ impl TableBuilder {
         self.current_row.clear();
         self.current_cell.clear();
         self.current_cell_spans.clear();
+        self.current_cell_source = None;
     }
 
     fn start_cell(&mut self) {
         self.current_cell.clear();
         self.current_cell_spans.clear();
+        self.current_cell_source = None;
     }
 
-    fn push_text(&mut self, text: &str, style: Style, tab_width: usize) {
+    fn push_text(&mut self, text: &str, style: Style, tab_width: usize, source: Option<usize>) {
         let expanded = expand_tabs(text, tab_width);
         if expanded.is_empty() {
             return;
         }
+        if self.current_cell.trim().is_empty() {
+            let lead = expanded.chars().take_while(|c| c.is_whitespace()).count();
+            self.current_cell_source = source.map(|offset| offset + lead);
+        }
         self.current_cell.push_str(&expanded);
-        if let Some(last) = self.current_cell_spans.last_mut() {
-            if last.style == style {
-                last.text.push_str(&expanded);
-                return;
-            }
+        if let Some(last) = self.current_cell_spans.last_mut()
+            && last.style == style
+        {
+            last.text.push_str(&expanded);
+            return;
         }
         self.current_cell_spans.push(TableSpan {
             text: expanded,
@@ -1211,14 +2298,18 @@ impl TableBuilder {
 
     fn push_break(&mut self, style: Style, tab_width: usize) {
         if !self.current_cell.ends_with(' ') {
-            self.push_text(" ", style, tab_width);
+            self.push_text(" ", style, tab_width, None);
         }
     }
 
     fn end_cell(&mut self) {
         let cell = self.current_cell.trim().to_string();
         let spans = trim_table_spans(&self.current_cell_spans);
-        self.current_row.push(TableCell { text: cell, spans });
+        self.current_row.push(TableCell {
+            text: cell,
+            spans,
+            source: self.current_cell_source.take(),
+        });
         self.current_cell.clear();
         self.current_cell_spans.clear();
     }
@@ -1233,6 +2324,7 @@ impl TableBuilder {
         let row = TableRow {
             cells: self.current_row.clone(),
             is_header: self.in_head,
+            source: self.row_source.take(),
         };
         self.rows.push(row);
         self.current_row.clear();
@@ -1244,6 +2336,7 @@ struct Token {
     text: String,
     style: Style,
     is_whitespace: bool,
+    col: usize,
 }
 
 #[derive(Clone, Copy)]
@@ -1322,11 +2415,13 @@ fn trim_table_spans(spans: &[TableSpan]) -> Vec<TableSpan> {
 #[cfg(test)]
 mod tests {
     use super::{
-        normalize_line_endings, render_table, wrap_line, MarkdownStyles, TableBuilder, TableCell,
-        TableRow, TableSpan,
+        normalize_line_endings, render_table, wrap_line, MarkdownStyles, RawLines, TableBuilder,
+        TableCell, TableRow, TableSpan, TextEdit,
     };
+    use crate::test_support::TempDir;
     use pulldown_cmark::Alignment;
     use ratatui::buffer::Buffer;
+    use ropey::Rope;
     use ratatui::layout::Rect;
     use ratatui::style::{Color, Modifier, Style};
     use ratatui::text::{Line, Span};
@@ -1361,6 +2456,7 @@ mod tests {
                 table_cell("Action", Style::default()),
             ],
             is_header: false,
+            source: None,
         });
         table.rows.push(TableRow {
             cells: vec![
@@ -1368,11 +2464,13 @@ mod tests {
                 table_cell("Add", Style::default()),
             ],
             is_header: false,
+            source: None,
         });
 
         let styles = test_styles();
-        let mut lines = Vec::new();
-        render_table(&table, &styles, &mut lines);
+        let mut raw = RawLines::default();
+        render_table(&table, &styles, &mut raw);
+        let lines = raw.lines;
         let rendered: Vec<String> = lines
             .iter()
             .map(|line| line.spans.iter().map(|s| s.content.as_ref()).collect())
@@ -1391,6 +2489,7 @@ mod tests {
                 table_cell("Action", Style::default()),
             ],
             is_header: true,
+            source: None,
         });
         table.rows.push(TableRow {
             cells: vec![
@@ -1398,12 +2497,14 @@ mod tests {
                 table_cell("Add", Style::default()),
             ],
             is_header: false,
+            source: None,
         });
 
         let mut styles = test_styles();
         styles.table_header = Style::default().add_modifier(Modifier::BOLD);
-        let mut lines = Vec::new();
-        render_table(&table, &styles, &mut lines);
+        let mut raw = RawLines::default();
+        render_table(&table, &styles, &mut raw);
+        let lines = raw.lines;
 
         let header_line = &lines[1];
         assert!(header_line.spans.iter().any(|span| {
@@ -1421,6 +2522,7 @@ mod tests {
                 table_cell("Action", Style::default()),
             ],
             is_header: true,
+            source: None,
         });
         table.rows.push(TableRow {
             cells: vec![
@@ -1428,11 +2530,13 @@ mod tests {
                 table_cell("", Style::default()),
             ],
             is_header: false,
+            source: None,
         });
 
         let styles = test_styles();
-        let mut lines = Vec::new();
-        render_table(&table, &styles, &mut lines);
+        let mut raw = RawLines::default();
+        render_table(&table, &styles, &mut raw);
+        let lines = raw.lines;
 
         assert!(lines.iter().any(|line| {
             line.spans.iter().any(|span| {
@@ -1489,7 +2593,7 @@ mod tests {
 
         let parsed = super::parse_markdown(markdown, &syntax_set, theme, &styles, 4)
             .expect("parse should succeed");
-        let bold_found = parsed.raw_lines.iter().any(|line| {
+        let bold_found = parsed.raw_lines().any(|line| {
             line.spans.iter().any(|span| {
                 span.content.contains("File Operations")
                     && span.style.add_modifier.contains(Modifier::BOLD)
@@ -1498,6 +2602,224 @@ mod tests {
         assert!(bold_found);
     }
 
+    #[test]
+    fn source_map_distinguishes_repeated_lines() {
+        let markdown = "# Title\n\nsame text\n\nsame text\n";
+        let rendered = render_test_document(markdown, 80);
+        let second = markdown.rfind("same").unwrap();
+        let (line, col) = rendered.locate_source(second + 2).expect("located");
+        assert_eq!(rendered.plain_lines[line], "same text");
+        assert_eq!(col, 2);
+        let (first_line, _) = rendered.locate_source(9).expect("located");
+        assert!(first_line < line);
+        assert_eq!(rendered.source_offset(line, 2), Some(second + 2));
+    }
+
+    #[test]
+    fn source_map_tracks_code_block_lines_and_inline_markup() {
+        let markdown = "Some **bold** word\n\n```rust\nlet a = 1;\nlet b = 2;\n```\n";
+        let rendered = render_test_document(markdown, 80);
+
+        let word = markdown.find("word").unwrap();
+        let (line, col) = rendered.locate_source(word).expect("located");
+        assert_eq!(&rendered.plain_lines[line][col..col + 4], "word");
+
+        let second = markdown.find("let b").unwrap();
+        let (line, col) = rendered.locate_source(second).expect("located");
+        assert!(rendered.plain_lines[line].contains("let b = 2;"));
+        assert_eq!(col, 2);
+        assert_eq!(rendered.source_offset(line, col), Some(second));
+    }
+
+    #[test]
+    fn source_map_survives_wrapping_and_crlf() {
+        let markdown = "alpha beta gamma delta\r\n\r\nnext\r\n";
+        let rendered = render_test_document(markdown, 11);
+        let gamma = markdown.find("gamma").unwrap();
+        let (line, col) = rendered.locate_source(gamma).expect("located");
+        assert!(rendered.plain_lines[line].starts_with("gamma"));
+        assert_eq!(col, 0);
+        let next = markdown.find("next").unwrap();
+        let (line, _) = rendered.locate_source(next).expect("located");
+        assert_eq!(rendered.plain_lines[line], "next");
+    }
+
+    #[test]
+    fn incremental_update_matches_full_parse() {
+        let base = "# Title\n\nFirst [link][ref] para.\n\n```rust\nfn main() {}\n```\n\n\
+                    - one\n- two\n\n## Next\n\nLast para.\n\n[ref]: https://example.com\n";
+        // The definition line continues the paragraph above it, so it defines nothing.
+        let joined = base.replacen("para.\n\n[ref]", "para.\n[ref]", 1);
+        // (text, from, to, whether the first block survives the update)
+        let edits: [(&str, &str, &str, bool); 9] = [
+            (base, "First", "Fist", false),
+            (base, "Last para.", "Last para.\n\nAnother", true),
+            (base, "- two", "- two\n  continued", true),
+            (base, "\n\n## Next", "\n## Next", true),
+            (base, "fn main() {}", "fn main() {}\n```\n\nopen\n```", true),
+            (base, "Last", "    Last", true),
+            // Edits next to the definition line that lose or gain the definition.
+            (base, "Last para.\n\n", "Last para.\n", false),
+            (base, "Last para.\n\n", "Last para.\n\ntext\n", false),
+            (&joined, "Last para.\n", "Last para.\n\n", false),
+        ];
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let themes = ThemeSet::load_defaults();
+        let theme = &themes.themes["base16-ocean.dark"];
+        let styles = test_styles();
+
+        let query = Some("para");
+        for (text, from, to, reuses_first) in edits {
+            let edited = text.replacen(from, to, 1);
+            let start = text[..text.find(from).unwrap()].chars().count();
+            let edit = TextEdit::new(start, from.chars().count(), to.chars().count());
+            let mut parsed = super::parse_markdown(text, &syntax_set, theme, &styles, 4).unwrap();
+            let previous = super::wrap_document(&parsed, 40, query, false, None);
+            let reused = parsed.blocks[0].id;
+            parsed
+                .update(&Rope::from_str(&edited), Some(edit), &syntax_set, theme, &styles, 4)
+                .unwrap();
+            let incremental =
+                super::rewrap_document(&parsed, Some(previous), 40, query, false, None);
+            let full = super::parse_markdown(&edited, &syntax_set, theme, &styles, 4).unwrap();
+            let expected = super::wrap_document(&full, 40, query, false, None);
+
+            assert_eq!(incremental.plain_lines, expected.plain_lines, "edit {from:?}");
+            let matches = |doc: &super::RenderedDocument| {
+                doc.matches
+                    .iter()
+                    .map(|m| (m.line, m.start, m.end))
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(matches(&incremental), matches(&expected), "edit {from:?}");
+            assert_eq!(incremental.sources, expected.sources, "edit {from:?}");
+            let headings = |doc: &super::RenderedDocument| {
+                doc.headings
+                    .iter()
+                    .map(|h| (h.level, h.title.clone(), h.line, h.rows))
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(headings(&incremental), headings(&expected), "edit {from:?}");
+            assert_eq!(incremental.code_blocks, expected.code_blocks, "edit {from:?}");
+            if reuses_first {
+                assert_eq!(parsed.blocks[0].id, reused, "edit {from:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn text_edits_merge_into_one_span() {
+        // Typing "ab" at 2, then deleting "123" from "01234xy56789".
+        let typed = TextEdit::new(2, 0, 1).then(TextEdit::new(3, 0, 1));
+        assert_eq!(typed, TextEdit::new(2, 0, 2));
+        let merged = TextEdit::new(5, 0, 2).then(TextEdit::new(1, 3, 0));
+        assert_eq!(merged, TextEdit::new(1, 4, 3));
+        let old = Rope::from_str("0123456789");
+        let new = Rope::from_str("04xy56789");
+        assert_eq!(TextEdit::between(&old, &new), Some(merged));
+    }
+
+    #[test]
+    fn images_reserve_fixed_rows() {
+        let base = TempDir::new("images");
+        std::fs::create_dir_all(base.join("img")).unwrap();
+        std::fs::write(base.join("img/logo.png"), "").unwrap();
+        let markdown = "Intro ![logo](img/logo.png) after\n\nNext\n";
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let themes = ThemeSet::load_defaults();
+        let theme = &themes.themes["base16-ocean.dark"];
+        let parsed = super::parse_markdown(markdown, &syntax_set, theme, &test_styles(), 4)
+            .expect("parse should succeed");
+        let boxes = super::ImageBoxes { rows: 3, base: &base };
+        let rendered = super::wrap_document(&parsed, 40, None, false, Some(boxes));
+
+        assert_eq!(rendered.images.len(), 1);
+        let image = &rendered.images[0];
+        assert_eq!(image.dest, "img/logo.png");
+        assert_eq!(image.rows, 3);
+        assert_eq!(rendered.plain_lines[image.line - 1], "Intro");
+        assert_eq!(rendered.plain_lines[image.line], "[image: logo]");
+        assert_eq!(rendered.plain_lines[image.line + 1], "");
+        assert_eq!(rendered.plain_lines[image.line + 3], "after");
+
+        let collapsed = super::wrap_document(&parsed, 40, None, false, None);
+        assert!(collapsed.images.is_empty());
+        assert_eq!(collapsed.plain_lines.len(), rendered.plain_lines.len() - 2);
+
+        // Badges, svgs and missing files can't be drawn and keep one line.
+        let markdown = "- ![build](https://img.shields.io/b.svg)\n\n> ![gone](img/gone.png)\n";
+        let parsed = super::parse_markdown(markdown, &syntax_set, theme, &test_styles(), 4)
+            .expect("parse should succeed");
+        let rendered = super::wrap_document(&parsed, 40, None, false, Some(boxes));
+        assert!(rendered.images.is_empty());
+        let lines: Vec<_> = rendered.plain_lines.iter().filter(|l| !l.is_empty()).collect();
+        assert_eq!(lines, ["• [image: build]", "│ [image: gone]"]);
+    }
+
+    #[test]
+    fn search_is_regex_with_smart_case_and_char_columns() {
+        let lines = vec![
+            "Straße STRASSE straße".to_string(),
+            "İstanbul and ıstanbul".to_string(),
+        ];
+        let cols = |query: &str, sensitive: bool| -> Vec<(usize, usize, usize)> {
+            super::find_matches(&lines, query, sensitive)
+                .iter()
+                .map(|m| (m.line, m.start, m.end))
+                .collect()
+        };
+
+        assert_eq!(cols("straße", false), vec![(0, 0, 6), (0, 15, 21)]);
+        assert_eq!(cols("Straße", false), vec![(0, 0, 6)]);
+        assert_eq!(cols("Straße\\c", false), vec![(0, 0, 6), (0, 15, 21)]);
+        assert_eq!(cols("\\Cstraße", false), vec![(0, 15, 21)]);
+        assert_eq!(cols("straße", true), vec![(0, 15, 21)]);
+        assert_eq!(cols("\\<and\\>", false), vec![(1, 9, 12)]);
+        assert_eq!(cols("\\<stan", false), Vec::new());
+        assert_eq!(cols("st\\w+", false).len(), 5);
+        assert!(cols("(unclosed", false).is_empty());
+
+        let line = Line::from(vec![Span::raw("é "), Span::raw("straße!")]);
+        let highlighted = super::apply_highlight(&line, &[0..1, 3..7]);
+        let texts: Vec<&str> = highlighted.spans.iter().map(|s| s.content.as_ref()).collect();
+        assert_eq!(texts, vec!["é", " ", "s", "traß", "e!"]);
+        assert!(highlighted.spans[0].style.add_modifier.contains(Modifier::REVERSED));
+        assert!(highlighted.spans[3].style.add_modifier.contains(Modifier::REVERSED));
+    }
+
+    #[test]
+    fn headings_and_code_blocks_report_their_wrapped_lines() {
+        let markdown = "# A heading long enough to wrap\n\nText\n\n\
+                        - item\n\n  ```\n  one\n  two\n  ```\n";
+        let rendered = render_test_document(markdown, 16);
+
+        let heading = &rendered.headings[0];
+        let title: Vec<&str> = rendered.plain_lines[heading.line..heading.line + heading.rows]
+            .iter()
+            .map(|line| line.trim_end())
+            .collect();
+        assert_eq!(title.join(" "), "A heading long enough to wrap");
+
+        assert_eq!(rendered.code_blocks.len(), 1);
+        let code: Vec<&str> = rendered.plain_lines[rendered.code_blocks[0].clone()]
+            .iter()
+            .map(|line| line.trim_matches(|c: char| c == '│' || c.is_whitespace()))
+            .collect();
+        assert_eq!(code, vec!["one", "two"]);
+    }
+
+    fn render_test_document(markdown: &str, width: u16) -> super::RenderedDocument {
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let themes = ThemeSet::load_defaults();
+        let theme = themes
+            .themes
+            .get("base16-ocean.dark")
+            .expect("default syntect theme");
+        let parsed = super::parse_markdown(markdown, &syntax_set, theme, &test_styles(), 4)
+            .expect("parse should succeed");
+        super::wrap_document(&parsed, width, None, false, None)
+    }
+
     fn table_cell(text: &str, style: Style) -> TableCell {
         TableCell {
             text: text.to_string(),
@@ -1509,6 +2831,7 @@ mod tests {
                     style,
                 }]
             },
+            source: None,
         }
     }
 
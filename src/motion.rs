@@ -0,0 +1,816 @@
+//! Vim motions and text objects, computed on the editor's `Rope` in char offsets.
+
+use regex::Regex;
+use ropey::Rope;
+use std::sync::OnceLock;
+
+/// How an operator treats the text between the cursor and a motion's target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MotionKind {
+    Exclusive,
+    Inclusive,
+    Linewise,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    WordForward { big: bool },
+    WordBackward { big: bool },
+    WordEnd { big: bool },
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+    Find { ch: char, forward: bool, till: bool },
+    ParagraphForward,
+    ParagraphBackward,
+    FileStart,
+    FileEnd,
+    /// `%`: the bracket matching the one under or after the cursor.
+    MatchPair,
+}
+
+impl Motion {
+    /// The motion bound to a single key, if any.
+    pub fn from_key(key: char) -> Option<Motion> {
+        Some(match key {
+            'h' => Motion::Left,
+            'l' => Motion::Right,
+            'k' => Motion::Up,
+            'j' => Motion::Down,
+            'w' => Motion::WordForward { big: false },
+            'W' => Motion::WordForward { big: true },
+            'b' => Motion::WordBackward { big: false },
+            'B' => Motion::WordBackward { big: true },
+            'e' => Motion::WordEnd { big: false },
+            'E' => Motion::WordEnd { big: true },
+            '0' => Motion::LineStart,
+            '^' => Motion::FirstNonBlank,
+            '$' => Motion::LineEnd,
+            '}' => Motion::ParagraphForward,
+            '{' => Motion::ParagraphBackward,
+            'G' => Motion::FileEnd,
+            '%' => Motion::MatchPair,
+            _ => return None,
+        })
+    }
+
+    pub fn kind(self) -> MotionKind {
+        match self {
+            Motion::Up | Motion::Down | Motion::FileStart | Motion::FileEnd => MotionKind::Linewise,
+            Motion::WordEnd { .. } | Motion::MatchPair => MotionKind::Inclusive,
+            Motion::Find { forward, .. } if forward => MotionKind::Inclusive,
+            _ => MotionKind::Exclusive,
+        }
+    }
+
+    /// Where the motion lands from `pos`, or `None` when it cannot move. `count`
+    /// repeats the motion, except for `gg`/`G` where it names a line.
+    pub fn apply(self, rope: &Rope, pos: usize, count: Option<usize>) -> Option<usize> {
+        let n = count.unwrap_or(1).max(1);
+        let pos = pos.min(rope.len_chars());
+        let line = rope.char_to_line(pos);
+        let line_start = rope.line_to_char(line);
+        let line_end = line_start + line_len(rope, line);
+        match self {
+            Motion::Left if pos > line_start => Some(pos.saturating_sub(n).max(line_start)),
+            Motion::Right if pos < line_end => Some((pos + n).min(line_end)),
+            Motion::Up if line > 0 => Some(rope.line_to_char(line.saturating_sub(n))),
+            Motion::Down if line < last_line(rope) => {
+                Some(rope.line_to_char((line + n).min(last_line(rope))))
+            }
+            Motion::Left | Motion::Right | Motion::Up | Motion::Down => None,
+            Motion::WordForward { big } => Some(repeat(n, pos, |p| word_forward(rope, p, big))),
+            Motion::WordBackward { big } => Some(repeat(n, pos, |p| word_backward(rope, p, big))),
+            Motion::WordEnd { big } => Some(repeat(n, pos, |p| word_end(rope, p, big))),
+            Motion::LineStart => Some(line_start),
+            Motion::FirstNonBlank => Some(first_non_blank(rope, line)),
+            Motion::LineEnd => {
+                let target = (line + n - 1).min(last_line(rope));
+                Some(rope.line_to_char(target) + line_len(rope, target))
+            }
+            Motion::Find { ch, forward, till } => find_in_line(rope, pos, ch, forward, till, n),
+            Motion::ParagraphForward => Some(paragraph_forward(rope, line, n)),
+            Motion::ParagraphBackward => Some(paragraph_backward(rope, line, n)),
+            Motion::FileStart => {
+                let target = count.map_or(0, |n| n.saturating_sub(1)).min(last_line(rope));
+                Some(first_non_blank(rope, target))
+            }
+            Motion::FileEnd => {
+                let last = last_line(rope);
+                let target = count.map_or(last, |n| n.saturating_sub(1)).min(last);
+                Some(first_non_blank(rope, target))
+            }
+            Motion::MatchPair => match_pair(rope, pos, line_end),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextObject {
+    Word { big: bool },
+    Quote(char),
+    Bracket(char, char),
+    Paragraph,
+    /// `[text](url)`: inner is the text, around is the whole link.
+    Link,
+    /// `[text](url)`: inner is the URL, around is the whole link.
+    LinkUrl,
+    ListItem,
+    /// A heading and everything up to the next heading of the same or higher level.
+    Section,
+    CodeBlock,
+}
+
+impl TextObject {
+    /// The object bound to the key typed after `i` or `a`.
+    pub fn from_key(key: char) -> Option<TextObject> {
+        Some(match key {
+            'w' => TextObject::Word { big: false },
+            'W' => TextObject::Word { big: true },
+            '"' | '\'' | '`' => TextObject::Quote(key),
+            '(' | ')' | 'b' => TextObject::Bracket('(', ')'),
+            '[' | ']' => TextObject::Bracket('[', ']'),
+            '{' | '}' | 'B' => TextObject::Bracket('{', '}'),
+            '<' | '>' => TextObject::Bracket('<', '>'),
+            'p' => TextObject::Paragraph,
+            'l' => TextObject::Link,
+            'u' => TextObject::LinkUrl,
+            'i' => TextObject::ListItem,
+            'h' => TextObject::Section,
+            'c' => TextObject::CodeBlock,
+            _ => return None,
+        })
+    }
+
+    /// The text the object covers around `pos`; `inner` picks `i` over `a`.
+    pub fn select(self, rope: &Rope, pos: usize, inner: bool) -> Option<Selection> {
+        if rope.len_chars() == 0 {
+            return None;
+        }
+        let pos = pos.min(rope.len_chars() - 1);
+        match self {
+            TextObject::Word { big } => select_word(rope, pos, big, inner),
+            TextObject::Quote(quote) => select_quote(rope, pos, quote, inner),
+            TextObject::Bracket(open, close) => select_bracket(rope, pos, open, close, inner),
+            TextObject::Paragraph => Some(select_paragraph(rope, pos, inner)),
+            TextObject::Link | TextObject::LinkUrl => {
+                select_link(rope, pos, inner, self == TextObject::LinkUrl)
+            }
+            TextObject::ListItem => select_list_item(rope, pos, inner),
+            TextObject::Section => select_section(rope, pos, inner),
+            TextObject::CodeBlock => select_code_block(rope, pos, inner),
+        }
+    }
+}
+
+/// A span picked by a text object. `end` is exclusive; linewise spans cover
+/// whole lines including the final newline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Selection {
+    pub start: usize,
+    pub end: usize,
+    pub linewise: bool,
+}
+
+/// Index of the last line, not counting the empty line after a trailing newline.
+pub fn last_line(rope: &Rope) -> usize {
+    let lines = rope.len_lines();
+    if lines > 1 && line_len(rope, lines - 1) == 0 {
+        lines - 2
+    } else {
+        lines.saturating_sub(1)
+    }
+}
+
+/// Length of a line in chars, without its line ending.
+pub fn line_len(rope: &Rope, line: usize) -> usize {
+    if line >= rope.len_lines() {
+        return 0;
+    }
+    let slice = rope.line(line);
+    let mut len = slice.len_chars();
+    if len > 0 && slice.char(len - 1) == '\n' {
+        len -= 1;
+    }
+    len
+}
+
+pub fn first_non_blank(rope: &Rope, line: usize) -> usize {
+    let start = rope.line_to_char(line);
+    let len = line_len(rope, line);
+    let blanks = rope
+        .line(line)
+        .chars()
+        .take(len)
+        .take_while(|c| c.is_whitespace())
+        .count();
+    start + blanks
+}
+
+/// Word class of a char: 0 for whitespace, 1 for word chars, 2 for punctuation.
+/// WORDs (`big`) only tell whitespace from everything else.
+fn class(ch: char, big: bool) -> u8 {
+    if ch.is_whitespace() {
+        0
+    } else if big || ch.is_alphanumeric() || ch == '_' {
+        1
+    } else {
+        2
+    }
+}
+
+fn repeat(n: usize, pos: usize, step: impl Fn(usize) -> usize) -> usize {
+    (0..n).fold(pos, |pos, _| step(pos))
+}
+
+fn is_empty_line_at(rope: &Rope, idx: usize) -> bool {
+    rope.char(idx) == '\n' && (idx == 0 || rope.char(idx - 1) == '\n')
+}
+
+fn word_forward(rope: &Rope, pos: usize, big: bool) -> usize {
+    let len = rope.len_chars();
+    let mut idx = pos;
+    if idx >= len {
+        return len;
+    }
+    let start_class = class(rope.char(idx), big);
+    if start_class != 0 {
+        while idx < len && class(rope.char(idx), big) == start_class {
+            idx += 1;
+        }
+    }
+    while idx < len && rope.char(idx).is_whitespace() {
+        if idx > pos && is_empty_line_at(rope, idx) {
+            return idx;
+        }
+        idx += 1;
+    }
+    idx
+}
+
+fn word_backward(rope: &Rope, pos: usize, big: bool) -> usize {
+    if pos == 0 {
+        return 0;
+    }
+    let mut idx = pos - 1;
+    while rope.char(idx).is_whitespace() {
+        if is_empty_line_at(rope, idx) || idx == 0 {
+            return idx;
+        }
+        idx -= 1;
+    }
+    let word_class = class(rope.char(idx), big);
+    while idx > 0 && class(rope.char(idx - 1), big) == word_class {
+        idx -= 1;
+    }
+    idx
+}
+
+fn word_end(rope: &Rope, pos: usize, big: bool) -> usize {
+    let len = rope.len_chars();
+    let mut idx = pos + 1;
+    while idx < len && rope.char(idx).is_whitespace() {
+        idx += 1;
+    }
+    if idx >= len {
+        return len.saturating_sub(1).max(pos);
+    }
+    let word_class = class(rope.char(idx), big);
+    while idx + 1 < len && class(rope.char(idx + 1), big) == word_class {
+        idx += 1;
+    }
+    idx
+}
+
+fn find_in_line(
+    rope: &Rope,
+    pos: usize,
+    ch: char,
+    forward: bool,
+    till: bool,
+    n: usize,
+) -> Option<usize> {
+    let line = rope.char_to_line(pos);
+    let start = rope.line_to_char(line);
+    let end = start + line_len(rope, line);
+    if forward {
+        let found = (pos + 1..end).filter(|&idx| rope.char(idx) == ch).nth(n - 1)?;
+        Some(if till { found - 1 } else { found })
+    } else {
+        let found = (start..pos).rev().filter(|&idx| rope.char(idx) == ch).nth(n - 1)?;
+        Some(if till { found + 1 } else { found })
+    }
+}
+
+fn match_pair(rope: &Rope, pos: usize, line_end: usize) -> Option<usize> {
+    let (at, ch) = (pos..line_end)
+        .map(|idx| (idx, rope.char(idx)))
+        .find(|(_, ch)| "()[]{}".contains(*ch))?;
+    match ch {
+        '(' => matching_close(rope, at, '(', ')'),
+        '[' => matching_close(rope, at, '[', ']'),
+        '{' => matching_close(rope, at, '{', '}'),
+        ')' => matching_open(rope, at, '(', ')'),
+        ']' => matching_open(rope, at, '[', ']'),
+        _ => matching_open(rope, at, '{', '}'),
+    }
+}
+
+fn is_blank_line(rope: &Rope, line: usize) -> bool {
+    rope.line(line).chars().all(char::is_whitespace)
+}
+
+fn paragraph_forward(rope: &Rope, mut line: usize, n: usize) -> usize {
+    let last = last_line(rope);
+    for _ in 0..n {
+        while line < last && is_blank_line(rope, line) {
+            line += 1;
+        }
+        while line <= last && !is_blank_line(rope, line) {
+            line += 1;
+        }
+        if line > last {
+            return rope.line_to_char(last) + line_len(rope, last);
+        }
+    }
+    rope.line_to_char(line)
+}
+
+fn paragraph_backward(rope: &Rope, mut line: usize, n: usize) -> usize {
+    for _ in 0..n {
+        while line > 0 && is_blank_line(rope, line - 1) {
+            line -= 1;
+        }
+        while line > 0 && !is_blank_line(rope, line - 1) {
+            line -= 1;
+        }
+        line = line.saturating_sub(1);
+        if line == 0 {
+            break;
+        }
+    }
+    rope.line_to_char(line)
+}
+
+fn lines_selection(rope: &Rope, first: usize, last: usize) -> Selection {
+    let end = if last + 1 >= rope.len_lines() {
+        rope.len_chars()
+    } else {
+        rope.line_to_char(last + 1)
+    };
+    Selection {
+        start: rope.line_to_char(first),
+        end,
+        linewise: true,
+    }
+}
+
+/// Widens an `a` selection by the whitespace after it, or before it when there is none.
+fn with_surrounding_space(rope: &Rope, start: usize, end: usize) -> Selection {
+    let line = rope.char_to_line(start);
+    let line_start = rope.line_to_char(line);
+    let line_end = line_start + line_len(rope, line);
+    let mut wide_end = end;
+    while wide_end < line_end && rope.char(wide_end).is_whitespace() {
+        wide_end += 1;
+    }
+    let mut wide_start = start;
+    if wide_end == end {
+        while wide_start > line_start && rope.char(wide_start - 1).is_whitespace() {
+            wide_start -= 1;
+        }
+    }
+    Selection {
+        start: wide_start,
+        end: wide_end,
+        linewise: false,
+    }
+}
+
+fn select_word(rope: &Rope, pos: usize, big: bool, inner: bool) -> Option<Selection> {
+    let line = rope.char_to_line(pos);
+    let line_start = rope.line_to_char(line);
+    let line_end = line_start + line_len(rope, line);
+    if pos >= line_end {
+        return None;
+    }
+    let word_class = class(rope.char(pos), big);
+    let mut start = pos;
+    while start > line_start && class(rope.char(start - 1), big) == word_class {
+        start -= 1;
+    }
+    let mut end = pos + 1;
+    while end < line_end && class(rope.char(end), big) == word_class {
+        end += 1;
+    }
+    if inner {
+        return Some(Selection {
+            start,
+            end,
+            linewise: false,
+        });
+    }
+    if word_class == 0 {
+        // `aw` on blanks takes the blanks and the word after them.
+        if end < line_end {
+            let next_class = class(rope.char(end), big);
+            while end < line_end && class(rope.char(end), big) == next_class {
+                end += 1;
+            }
+        }
+        return Some(Selection {
+            start,
+            end,
+            linewise: false,
+        });
+    }
+    Some(with_surrounding_space(rope, start, end))
+}
+
+fn select_quote(rope: &Rope, pos: usize, quote: char, inner: bool) -> Option<Selection> {
+    let line = rope.char_to_line(pos);
+    let line_start = rope.line_to_char(line);
+    let line_end = line_start + line_len(rope, line);
+    let quotes: Vec<usize> = (line_start..line_end)
+        .filter(|&idx| {
+            rope.char(idx) == quote && (idx == line_start || rope.char(idx - 1) != '\\')
+        })
+        .collect();
+    let (open, close) = quotes
+        .chunks_exact(2)
+        .map(|pair| (pair[0], pair[1]))
+        .find(|&(open, close)| open <= pos && pos <= close)
+        .or_else(|| {
+            quotes
+                .chunks_exact(2)
+                .map(|pair| (pair[0], pair[1]))
+                .find(|&(open, _)| open > pos)
+        })?;
+    if inner {
+        return Some(Selection {
+            start: open + 1,
+            end: close,
+            linewise: false,
+        });
+    }
+    Some(with_surrounding_space(rope, open, close + 1))
+}
+
+fn select_bracket(
+    rope: &Rope,
+    pos: usize,
+    open: char,
+    close: char,
+    inner: bool,
+) -> Option<Selection> {
+    let open_at = if rope.char(pos) == open {
+        pos
+    } else {
+        let mut depth = 0usize;
+        let mut found = None;
+        let scan_from = if rope.char(pos) == close { pos } else { pos + 1 };
+        for idx in (0..scan_from).rev() {
+            let ch = rope.char(idx);
+            if ch == close && idx != pos {
+                depth += 1;
+            } else if ch == open {
+                if depth == 0 {
+                    found = Some(idx);
+                    break;
+                }
+                depth -= 1;
+            }
+        }
+        found?
+    };
+    let close_at = matching_close(rope, open_at, open, close)?;
+    let selection = if inner {
+        Selection {
+            start: open_at + 1,
+            end: close_at,
+            linewise: false,
+        }
+    } else {
+        Selection {
+            start: open_at,
+            end: close_at + 1,
+            linewise: false,
+        }
+    };
+    Some(selection)
+}
+
+/// Offset of the bracket closing the one at `open_at`.
+fn matching_close(rope: &Rope, open_at: usize, open: char, close: char) -> Option<usize> {
+    let mut depth = 0usize;
+    for (offset, ch) in rope.chars_at(open_at + 1).enumerate() {
+        if ch == open {
+            depth += 1;
+        } else if ch == close {
+            if depth == 0 {
+                return Some(open_at + 1 + offset);
+            }
+            depth -= 1;
+        }
+    }
+    None
+}
+
+/// Offset of the bracket opening the one at `close_at`.
+fn matching_open(rope: &Rope, close_at: usize, open: char, close: char) -> Option<usize> {
+    let mut depth = 0usize;
+    for idx in (0..close_at).rev() {
+        let ch = rope.char(idx);
+        if ch == close {
+            depth += 1;
+        } else if ch == open {
+            if depth == 0 {
+                return Some(idx);
+            }
+            depth -= 1;
+        }
+    }
+    None
+}
+
+fn select_paragraph(rope: &Rope, pos: usize, inner: bool) -> Selection {
+    let last = last_line(rope);
+    let line = rope.char_to_line(pos).min(last);
+    let blank = is_blank_line(rope, line);
+    let mut first = line;
+    while first > 0 && is_blank_line(rope, first - 1) == blank {
+        first -= 1;
+    }
+    let mut end = line;
+    while end < last && is_blank_line(rope, end + 1) == blank {
+        end += 1;
+    }
+    if !inner && blank {
+        // `ap` on blank lines takes the paragraph after them.
+        while end < last && !is_blank_line(rope, end + 1) {
+            end += 1;
+        }
+    } else if !inner {
+        // `ap` on text takes the blank lines after it, or before it at the end.
+        let before = end;
+        while end < last && is_blank_line(rope, end + 1) {
+            end += 1;
+        }
+        if end == before {
+            while first > 0 && is_blank_line(rope, first - 1) {
+                first -= 1;
+            }
+        }
+    }
+    lines_selection(rope, first, end)
+}
+
+fn line_text(rope: &Rope, line: usize) -> String {
+    rope.line(line).chars().take(line_len(rope, line)).collect()
+}
+
+fn select_link(rope: &Rope, pos: usize, inner: bool, url: bool) -> Option<Selection> {
+    static LINK: OnceLock<Regex> = OnceLock::new();
+    let link = LINK.get_or_init(|| {
+        Regex::new(r#"!?\[([^\]]*)\]\(\s*([^)\s]*)(?:\s+"[^"]*")?\s*\)"#).expect("valid regex")
+    });
+    let line = rope.char_to_line(pos);
+    let line_start = rope.line_to_char(line);
+    let text = line_text(rope, line);
+    let col = pos - line_start;
+    let char_col = |byte: usize| text[..byte].chars().count();
+    let caps = link
+        .captures_iter(&text)
+        .find(|caps| {
+            let whole = caps.get(0).expect("group 0");
+            char_col(whole.start()) <= col && col < char_col(whole.end())
+        })
+        .or_else(|| {
+            link.captures_iter(&text)
+                .find(|caps| char_col(caps.get(0).expect("group 0").start()) > col)
+        })?;
+    let group = caps.get(if !inner { 0 } else if url { 2 } else { 1 })?;
+    Some(Selection {
+        start: line_start + char_col(group.start()),
+        end: line_start + char_col(group.end()),
+        linewise: false,
+    })
+}
+
+/// Indent and content column of a list item line (`- `, `* `, `+ `, `1. `, `1) `),
+/// skipping a task box.
+fn list_marker(text: &str) -> Option<(usize, usize)> {
+    let indent = text.chars().take_while(|c| *c == ' ' || *c == '\t').count();
+    let rest: String = text.chars().skip(indent).collect();
+    let digits = rest.chars().take_while(char::is_ascii_digit).count();
+    let marker = if rest.starts_with(['-', '*', '+']) {
+        1
+    } else if (1..=9).contains(&digits) && rest[digits..].starts_with(['.', ')']) {
+        digits + 1
+    } else {
+        return None;
+    };
+    let after = &rest[marker..];
+    if !after.is_empty() && !after.starts_with(' ') {
+        return None;
+    }
+    let mut content = indent + marker + after.chars().take_while(|c| *c == ' ').count();
+    let body = after.trim_start_matches(' ');
+    if ["[ ] ", "[x] ", "[X] "].iter().any(|task| body.starts_with(task)) {
+        content += 4;
+    }
+    Some((indent, content))
+}
+
+fn select_list_item(rope: &Rope, pos: usize, inner: bool) -> Option<Selection> {
+    let last = last_line(rope);
+    let line = rope.char_to_line(pos).min(last);
+    let indent_of = |line: usize| {
+        line_text(rope, line)
+            .chars()
+            .take_while(|c| c.is_whitespace())
+            .count()
+    };
+    let cursor_indent = indent_of(line);
+    let mut first = line;
+    let (item_indent, content) = loop {
+        if is_blank_line(rope, first) {
+            return None;
+        }
+        if let Some((indent, content)) = list_marker(&line_text(rope, first))
+            && indent <= cursor_indent
+        {
+            break (indent, content);
+        }
+        if first == 0 {
+            return None;
+        }
+        first -= 1;
+    };
+    let mut end = first;
+    while end < last && !is_blank_line(rope, end + 1) {
+        let next = line_text(rope, end + 1);
+        if list_marker(&next).is_some_and(|(indent, _)| indent <= item_indent) {
+            break;
+        }
+        end += 1;
+    }
+    if !inner {
+        return Some(lines_selection(rope, first, end));
+    }
+    Some(Selection {
+        start: rope.line_to_char(first) + content.min(line_len(rope, first)),
+        end: rope.line_to_char(end) + line_len(rope, end),
+        linewise: false,
+    })
+}
+
+/// Fenced code blocks as (opening fence line, closing fence line).
+fn code_fences(rope: &Rope) -> Vec<(usize, usize)> {
+    let last = last_line(rope);
+    let mut fences = Vec::new();
+    let mut open: Option<(usize, char, usize)> = None;
+    for line in 0..=last {
+        let text = line_text(rope, line);
+        let trimmed = text.trim_start();
+        let fence_char = trimmed.chars().next().filter(|c| *c == '`' || *c == '~');
+        let run = fence_char.map_or(0, |c| trimmed.chars().take_while(|x| *x == c).count());
+        match open {
+            None if run >= 3 => open = fence_char.map(|c| (line, c, run)),
+            Some((start, c, len))
+                if fence_char == Some(c)
+                    && run >= len
+                    && trimmed.trim_start_matches(c).trim().is_empty() =>
+            {
+                fences.push((start, line));
+                open = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some((start, _, _)) = open {
+        fences.push((start, last));
+    }
+    fences
+}
+
+fn heading_level(text: &str) -> Option<usize> {
+    let indent = text.chars().take_while(|c| *c == ' ').count();
+    if indent > 3 {
+        return None;
+    }
+    let rest = &text[indent..];
+    let level = rest.chars().take_while(|c| *c == '#').count();
+    let after = &rest[level..];
+    ((1..=6).contains(&level) && (after.is_empty() || after.starts_with([' ', '\t'])))
+        .then_some(level)
+}
+
+fn select_section(rope: &Rope, pos: usize, inner: bool) -> Option<Selection> {
+    let last = last_line(rope);
+    let line = rope.char_to_line(pos).min(last);
+    let fences = code_fences(rope);
+    let heading = |line: usize| {
+        if fences.iter().any(|&(open, close)| open <= line && line <= close) {
+            return None;
+        }
+        heading_level(&line_text(rope, line))
+    };
+    let (start, level) = (0..=line).rev().find_map(|l| heading(l).map(|lvl| (l, lvl)))?;
+    let end = (start + 1..=last)
+        .find(|&l| heading(l).is_some_and(|lvl| lvl <= level))
+        .map_or(last, |next| next - 1);
+    if !inner {
+        return Some(lines_selection(rope, start, end));
+    }
+    let mut body_start = start + 1;
+    let mut body_end = end;
+    while body_end > start && is_blank_line(rope, body_end) {
+        body_end -= 1;
+    }
+    while body_start <= body_end && is_blank_line(rope, body_start) {
+        body_start += 1;
+    }
+    (body_start <= body_end).then(|| lines_selection(rope, body_start, body_end))
+}
+
+fn select_code_block(rope: &Rope, pos: usize, inner: bool) -> Option<Selection> {
+    let line = rope.char_to_line(pos);
+    let (open, close) = code_fences(rope)
+        .into_iter()
+        .find(|&(open, close)| open <= line && line <= close)?;
+    if !inner {
+        return Some(lines_selection(rope, open, close));
+    }
+    (close > open + 1).then(|| lines_selection(rope, open + 1, close - 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Motion, TextObject};
+    use ropey::Rope;
+
+    fn select(text: &str, pos: usize, key: char, inner: bool) -> Option<String> {
+        let rope = Rope::from_str(text);
+        let selection = TextObject::from_key(key)?.select(&rope, pos, inner)?;
+        Some(rope.slice(selection.start..selection.end).to_string())
+    }
+
+    #[test]
+    fn word_motions_use_unicode_classes_and_stop_on_empty_lines() {
+        let rope = Rope::from_str("héllo, wörld_1 foo\n\nnext");
+        let word = |pos, key| Motion::from_key(key).unwrap().apply(&rope, pos, None).unwrap();
+        assert_eq!(word(0, 'w'), 5);
+        assert_eq!(word(5, 'w'), 7);
+        assert_eq!(word(0, 'W'), 7);
+        assert_eq!(word(7, 'e'), 13);
+        assert_eq!(word(15, 'w'), 19);
+        assert_eq!(word(19, 'w'), 20);
+        assert_eq!(word(20, 'b'), 19);
+        assert_eq!(word(19, 'b'), 15);
+        assert_eq!(word(13, 'b'), 7);
+        assert_eq!(Motion::from_key('w').unwrap().apply(&rope, 0, Some(3)), Some(15));
+
+        let code = Rope::from_str("f(a[1], {b}) x");
+        let jump = |pos| Motion::MatchPair.apply(&code, pos, None);
+        assert_eq!(jump(0), Some(11));
+        assert_eq!(jump(11), Some(1));
+        assert_eq!(jump(3), Some(5));
+        assert_eq!(jump(12), None);
+    }
+
+    #[test]
+    fn text_objects_pick_words_quotes_brackets_and_paragraphs() {
+        assert_eq!(select("say hello there", 5, 'w', true).as_deref(), Some("hello"));
+        assert_eq!(select("say hello there", 5, 'w', false).as_deref(), Some("hello "));
+        assert_eq!(select("a \"quoted\" word", 4, '"', true).as_deref(), Some("quoted"));
+        assert_eq!(select("f(a, (b), c)", 3, '(', true).as_deref(), Some("a, (b), c"));
+        assert_eq!(select("f(a, (b), c)", 6, 'b', false).as_deref(), Some("(b)"));
+        assert_eq!(select("one\ntwo\n\nthree\n", 4, 'p', true).as_deref(), Some("one\ntwo\n"));
+        assert_eq!(select("one\ntwo\n\nthree\n", 4, 'p', false).as_deref(), Some("one\ntwo\n\n"));
+    }
+
+    #[test]
+    fn markdown_objects_cover_links_items_sections_and_fences() {
+        let link = "See [the docs](https://x.io \"t\") now";
+        assert_eq!(select(link, 6, 'l', true).as_deref(), Some("the docs"));
+        assert_eq!(select(link, 6, 'u', true).as_deref(), Some("https://x.io"));
+        assert_eq!(select(link, 0, 'l', false).as_deref(), Some("[the docs](https://x.io \"t\")"));
+        assert_eq!(select("run `cargo test` now", 6, '`', true).as_deref(), Some("cargo test"));
+
+        let list = "- [ ] first\n  more\n  - nested\n- second\n";
+        assert_eq!(select(list, 14, 'i', true).as_deref(), Some("first\n  more\n  - nested"));
+        assert_eq!(select(list, 22, 'i', false).as_deref(), Some("  - nested\n"));
+
+        let doc = "# A\n\nintro\n\n## B\n\nbody\n\n```\n# not a heading\n```\n\n# C\n";
+        let section = "## B\n\nbody\n\n```\n# not a heading\n```\n";
+        assert_eq!(select(doc, 13, 'h', false), Some(format!("{section}\n")));
+        assert_eq!(select(doc, 13, 'h', true).as_deref(), section.strip_prefix("## B\n\n"));
+        assert_eq!(select(doc, 30, 'c', true).as_deref(), Some("# not a heading\n"));
+        assert_eq!(select(doc, 30, 'c', false).as_deref(), Some("```\n# not a heading\n```\n"));
+    }
+}
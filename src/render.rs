@@ -0,0 +1,207 @@
+use crate::config::Config;
+use crate::markdown::{parse_markdown, wrap_document};
+use crate::theme::{styles_from_palette, ThemeManager};
+use anyhow::{anyhow, Context, Result};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use std::fs;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderMode {
+    Color,
+    NoColor,
+    Plain,
+}
+
+pub fn render_to_stdout(
+    path: &Path,
+    width: Option<u16>,
+    theme: Option<&str>,
+    mode: RenderMode,
+    config: &Config,
+) -> Result<()> {
+    let input = if path == Path::new("-") {
+        let mut input = String::new();
+        io::stdin()
+            .read_to_string(&mut input)
+            .context("Failed to read markdown from stdin")?;
+        input
+    } else {
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?
+    };
+
+    let theme_manager = ThemeManager::load(config)?;
+    let theme_name = match theme {
+        Some(name) => {
+            if !theme_manager.theme_names().iter().any(|t| t == name) {
+                return Err(anyhow!("Unknown theme: {name}. Try `mark themes list`."));
+            }
+            name
+        }
+        None if theme_manager.theme_names().iter().any(|t| t == &config.theme) => {
+            config.theme.as_str()
+        }
+        None => theme_manager.fallback_name(),
+    };
+    let ui = theme_manager.ui_palette(theme_name);
+    let (_, markdown_styles) = styles_from_palette(ui);
+    let parsed = parse_markdown(
+        &input,
+        theme_manager.syntax_set(),
+        theme_manager.get(theme_name),
+        &markdown_styles,
+        config.tab_width,
+    )?;
+
+    let width = width.unwrap_or_else(default_width).max(1);
+    let width = if config.wrap { width } else { u16::MAX };
+    let rendered = wrap_document(&parsed, width, None, config.search_case_sensitive, None);
+
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+    let result = (|| -> io::Result<()> {
+        for (line, plain) in rendered.lines.iter().zip(&rendered.plain_lines) {
+            match mode {
+                RenderMode::Plain => writeln!(out, "{}", plain.trim_end())?,
+                RenderMode::Color | RenderMode::NoColor => {
+                    let text = line_to_ansi(line, mode == RenderMode::Color, ui.base_bg);
+                    writeln!(out, "{text}")?;
+                }
+            }
+        }
+        out.flush()
+    })();
+    match result {
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        other => other.context("Failed to write to stdout"),
+    }
+}
+
+fn default_width() -> u16 {
+    if io::stdout().is_terminal()
+        && let Ok((cols, _)) = crossterm::terminal::size()
+    {
+        return cols;
+    }
+    80
+}
+
+/// Serializes a rendered line as SGR escapes. Backgrounds that match the
+/// theme's base background are dropped so output blends into the terminal,
+/// and unstyled trailing padding is trimmed.
+fn line_to_ansi(line: &Line, color: bool, base_bg: Option<Color>) -> String {
+    let spans: Vec<(Style, &str)> = line
+        .spans
+        .iter()
+        .map(|span| {
+            let mut style = line.style.patch(span.style);
+            if !color {
+                style.fg = None;
+                style.bg = None;
+            } else if style.bg == Some(Color::Reset) || style.bg == base_bg {
+                style.bg = None;
+            }
+            (style, span.content.as_ref())
+        })
+        .collect();
+    let end = spans
+        .iter()
+        .rposition(|(style, text)| style.bg.is_some() || !text.trim_end().is_empty())
+        .map_or(0, |idx| idx + 1);
+
+    let mut out = String::new();
+    let mut current = Style::default();
+    for (style, text) in &spans[..end] {
+        if *style != current {
+            out.push_str("\x1b[0m");
+            out.push_str(&sgr(*style));
+            current = *style;
+        }
+        out.push_str(text);
+    }
+    if current != Style::default() {
+        out.push_str("\x1b[0m");
+    }
+    out
+}
+
+fn sgr(style: Style) -> String {
+    let mut codes: Vec<String> = Vec::new();
+    let modifiers = [
+        (Modifier::BOLD, "1"),
+        (Modifier::DIM, "2"),
+        (Modifier::ITALIC, "3"),
+        (Modifier::UNDERLINED, "4"),
+        (Modifier::REVERSED, "7"),
+        (Modifier::CROSSED_OUT, "9"),
+    ];
+    for (modifier, code) in modifiers {
+        if style.add_modifier.contains(modifier) {
+            codes.push(code.to_string());
+        }
+    }
+    if let Some(fg) = style.fg.and_then(|c| color_code(c, false)) {
+        codes.push(fg);
+    }
+    if let Some(bg) = style.bg.and_then(|c| color_code(c, true)) {
+        codes.push(bg);
+    }
+    if codes.is_empty() {
+        return String::new();
+    }
+    format!("\x1b[{}m", codes.join(";"))
+}
+
+fn color_code(color: Color, background: bool) -> Option<String> {
+    let offset = if background { 10 } else { 0 };
+    let basic = |code: u8| Some((code + offset).to_string());
+    match color {
+        Color::Reset => None,
+        Color::Black => basic(30),
+        Color::Red => basic(31),
+        Color::Green => basic(32),
+        Color::Yellow => basic(33),
+        Color::Blue => basic(34),
+        Color::Magenta => basic(35),
+        Color::Cyan => basic(36),
+        Color::Gray => basic(37),
+        Color::DarkGray => basic(90),
+        Color::LightRed => basic(91),
+        Color::LightGreen => basic(92),
+        Color::LightYellow => basic(93),
+        Color::LightBlue => basic(94),
+        Color::LightMagenta => basic(95),
+        Color::LightCyan => basic(96),
+        Color::White => basic(97),
+        Color::Rgb(r, g, b) => Some(format!("{};2;{r};{g};{b}", 38 + offset)),
+        Color::Indexed(i) => Some(format!("{};5;{i}", 38 + offset)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::line_to_ansi;
+    use ratatui::style::{Color, Modifier, Style};
+    use ratatui::text::{Line, Span};
+
+    #[test]
+    fn line_to_ansi_emits_sgr_and_respects_no_color() {
+        let title = Style::default()
+            .fg(Color::Rgb(1, 2, 3))
+            .add_modifier(Modifier::BOLD);
+        let line = Line::from(vec![
+            Span::styled("Title", title),
+            Span::styled(" body", Style::default().bg(Color::Rgb(9, 9, 9))),
+        ]);
+        assert_eq!(
+            line_to_ansi(&line, true, Some(Color::Rgb(9, 9, 9))),
+            "\x1b[0m\x1b[1;38;2;1;2;3mTitle\x1b[0m body"
+        );
+        assert_eq!(
+            line_to_ansi(&line, false, None),
+            "\x1b[0m\x1b[1mTitle\x1b[0m body"
+        );
+    }
+}
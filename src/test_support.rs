@@ -0,0 +1,53 @@
+//! Fixtures shared by the unit tests.
+
+use crate::theme::UiPalette;
+use ratatui::style::Color;
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// A fresh directory under the system temp dir, removed on drop so a failing
+/// assertion doesn't leave it behind.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// `mark-<name>-<pid>`, emptied if an earlier run left it around.
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("mark-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).expect("create temp dir");
+        Self(path)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A plain dark palette for tests that need one but don't look at colors.
+pub fn palette() -> UiPalette {
+    UiPalette {
+        base_fg: Color::Rgb(200, 200, 200),
+        base_bg: None,
+        accent: Color::Cyan,
+        muted: Color::DarkGray,
+        code_bg: None,
+        border: Color::DarkGray,
+    }
+}
@@ -1,6 +1,7 @@
 use crate::config::Config;
+use crate::markdown::MarkdownStyles;
 use anyhow::{Context, Result};
-use ratatui::style::Color;
+use ratatui::style::{Color, Modifier, Style};
 use std::path::PathBuf;
 use syntect::highlighting::{Theme, ThemeSet};
 use syntect::parsing::SyntaxSet;
@@ -32,12 +33,12 @@ impl ThemeManager {
             .clone();
         let mut extra = ThemeSet::new();
 
-        if let Some(dir) = resolve_bat_theme_dir(config) {
-            if dir.exists() {
-                extra
-                    .add_from_folder(&dir)
-                    .with_context(|| format!("Failed to load themes from {}", dir.display()))?;
-            }
+        if let Some(dir) = resolve_bat_theme_dir(config)
+            && dir.exists()
+        {
+            extra
+                .add_from_folder(&dir)
+                .with_context(|| format!("Failed to load themes from {}", dir.display()))?;
         }
 
         let mut theme_names: Vec<String> =
@@ -135,3 +136,110 @@ fn palette_from_theme(theme: &Theme) -> UiPalette {
 fn to_ratatui(color: syntect::highlighting::Color) -> Color {
     Color::Rgb(color.r, color.g, color.b)
 }
+
+pub fn styles_from_palette(ui: UiPalette) -> (Style, MarkdownStyles) {
+    let base_style = Style::default()
+        .fg(ui.base_fg)
+        .bg(bg_or_reset(ui.base_bg));
+
+    let heading = [
+        Style::default()
+            .fg(ui.accent)
+            .add_modifier(Modifier::BOLD | Modifier::UNDERLINED),
+        Style::default().fg(ui.accent).add_modifier(Modifier::BOLD),
+        Style::default().fg(ui.accent).add_modifier(Modifier::ITALIC),
+        Style::default().fg(ui.muted).add_modifier(Modifier::BOLD),
+        Style::default().fg(ui.muted),
+        Style::default().fg(ui.muted).add_modifier(Modifier::ITALIC),
+    ];
+
+    let inline_code_bg = tinted_code_bg(ui.code_bg.or(ui.base_bg), ui.base_fg)
+        .or(ui.base_bg)
+        .or_else(|| fallback_code_bg(ui.base_fg));
+    let code_block_bg = ui.base_bg;
+    let inline_code = Style::default()
+        .fg(ui.accent)
+        .bg(bg_or_reset(inline_code_bg));
+    let prefix = Style::default().fg(ui.muted);
+    let rule = Style::default().fg(ui.muted);
+    let code_border = Style::default().fg(ui.border).bg(bg_or_reset(code_block_bg));
+    let code_header = Style::default()
+        .fg(ui.accent)
+        .bg(bg_or_reset(code_block_bg))
+        .add_modifier(Modifier::BOLD);
+    let table_border = Style::default().fg(ui.border);
+    let table_header = Style::default().fg(ui.accent).add_modifier(Modifier::BOLD);
+
+    (
+        base_style,
+        MarkdownStyles {
+            base: base_style,
+            heading,
+            link_color: ui.accent,
+            inline_code,
+            prefix,
+            rule,
+            code_block_bg,
+            code_border,
+            code_header,
+            table_border,
+            table_header,
+        },
+    )
+}
+
+fn bg_or_reset(color: Option<Color>) -> Color {
+    color.unwrap_or(Color::Reset)
+}
+
+fn adjust_bg(color: Option<Color>, delta: f32) -> Option<Color> {
+    match color {
+        Some(Color::Rgb(r, g, b)) => {
+            let dr = adjust_channel(r, delta);
+            let dg = adjust_channel(g, delta);
+            let db = adjust_channel(b, delta);
+            Some(Color::Rgb(dr, dg, db))
+        }
+        _ => None,
+    }
+}
+
+fn tinted_code_bg(base: Option<Color>, tint: Color) -> Option<Color> {
+    let Some(Color::Rgb(r, g, b)) = base else {
+        return None;
+    };
+    let tinted = tint_color(Color::Rgb(r, g, b), tint, 0.06);
+    adjust_bg(Some(tinted), -0.12)
+}
+
+fn fallback_code_bg(fg: Color) -> Option<Color> {
+    let Color::Rgb(r, g, b) = fg else {
+        return None;
+    };
+    Some(Color::Rgb(
+        (r as f32 * 0.2) as u8,
+        (g as f32 * 0.2) as u8,
+        (b as f32 * 0.2) as u8,
+    ))
+}
+
+fn tint_color(base: Color, accent: Color, amount: f32) -> Color {
+    let Color::Rgb(r, g, b) = base else {
+        return base;
+    };
+    let Color::Rgb(ar, ag, ab) = accent else {
+        return base;
+    };
+    let mix = |base: u8, accent: u8| {
+        let base = base as f32;
+        let accent = accent as f32;
+        (base * (1.0 - amount) + accent * amount).round() as u8
+    };
+    Color::Rgb(mix(r, ar), mix(g, ag), mix(b, ab))
+}
+
+fn adjust_channel(value: u8, delta: f32) -> u8 {
+    let v = value as f32 / 255.0;
+    let adjusted = (v + delta).clamp(0.0, 1.0);
+    (adjusted * 255.0).round() as u8
+}
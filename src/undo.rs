@@ -0,0 +1,493 @@
+//! Branching undo history stored as text deltas, optionally saved per file.
+
+use crate::config::{config_path, ensure_config_dir};
+use anyhow::{Context, Result};
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::ops::Range;
+use std::path::{self, Path, PathBuf};
+
+/// One change: `removed` was replaced by `inserted` at char offset `at`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Delta {
+    at: usize,
+    removed: String,
+    inserted: String,
+}
+
+impl Delta {
+    /// The smallest delta turning `before` into `after`, found by trimming
+    /// their common prefix and suffix.
+    fn between(before: &Rope, after: &Rope) -> Option<Delta> {
+        let prefix = before
+            .chars()
+            .zip(after.chars())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let (before_len, after_len) = (before.len_chars(), after.len_chars());
+        if prefix == before_len && prefix == after_len {
+            return None;
+        }
+        let suffix = before
+            .chars_at(before_len)
+            .reversed()
+            .zip(after.chars_at(after_len).reversed())
+            .take(before_len.min(after_len) - prefix)
+            .take_while(|(a, b)| a == b)
+            .count();
+        Some(Delta {
+            at: prefix,
+            removed: before.slice(prefix..before_len - suffix).to_string(),
+            inserted: after.slice(prefix..after_len - suffix).to_string(),
+        })
+    }
+
+    fn apply(&self, rope: &mut Rope) {
+        rope.remove(self.at..self.at + self.removed.chars().count());
+        rope.insert(self.at, &self.inserted);
+    }
+
+    fn revert(&self, rope: &mut Rope) {
+        rope.remove(self.at..self.at + self.inserted.chars().count());
+        rope.insert(self.at, &self.removed);
+    }
+}
+
+/// A state reached by applying `edits`, in order, to the `parent` state.
+/// States are numbered in the order they were made, which is what `g-`/`g+`
+/// walk.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct UndoNode {
+    seq: usize,
+    parent: usize,
+    /// Where the cursor was before the change, restored by undo.
+    cursor: usize,
+    edits: Vec<Delta>,
+}
+
+impl UndoNode {
+    fn apply(&self, rope: &mut Rope) {
+        for edit in &self.edits {
+            edit.apply(rope);
+        }
+    }
+
+    fn revert(&self, rope: &mut Rope) {
+        for edit in self.edits.iter().rev() {
+            edit.revert(rope);
+        }
+    }
+}
+
+/// Undo history as a tree of deltas. Changes are recorded by diffing the
+/// buffer against the text of the current state, so callers only mark where
+/// a change starts (`begin`) and everything up to the next mark, undo or
+/// redo lands as one step. An insert session is therefore a single step.
+/// Changes spread over the buffer, like a substitute, go through `replace`
+/// so only the edited ranges are stored.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UndoTree {
+    /// Oldest reachable state; its own change has been pruned.
+    root: usize,
+    current: usize,
+    /// The state redo heads towards: the newest change or the last one jumped to.
+    tip: usize,
+    next_seq: usize,
+    nodes: Vec<UndoNode>,
+    #[serde(skip)]
+    baseline: Rope,
+    #[serde(skip)]
+    pending_cursor: Option<usize>,
+    /// Edits made through `replace` since the current state.
+    #[serde(skip)]
+    recorded: Vec<Delta>,
+    #[serde(skip)]
+    levels: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct UndoFile {
+    /// Hash of the text the saved `current` state matches.
+    text_hash: u64,
+    tree: UndoTree,
+}
+
+impl UndoTree {
+    /// An empty history for `rope`, keeping at most `levels` changes.
+    pub fn new(rope: &Rope, levels: usize) -> Self {
+        Self {
+            next_seq: 1,
+            baseline: rope.clone(),
+            levels,
+            ..Self::default()
+        }
+    }
+
+    /// Marks the start of a change made with the cursor at `cursor`.
+    pub fn begin(&mut self, rope: &Rope, cursor: usize) {
+        self.commit(rope);
+        self.pending_cursor = Some(cursor);
+    }
+
+    /// Whether a change was begun and not committed yet.
+    pub fn in_change(&self) -> bool {
+        self.pending_cursor.is_some()
+    }
+
+    /// Replaces `range` of `rope` with `text` and remembers the edit, so the
+    /// change doesn't have to be found by diffing.
+    pub fn replace(&mut self, rope: &mut Rope, range: Range<usize>, text: &str) {
+        let delta = Delta {
+            at: range.start,
+            removed: rope.slice(range).to_string(),
+            inserted: text.to_string(),
+        };
+        delta.apply(rope);
+        self.recorded.push(delta);
+    }
+
+    /// Records whatever changed since the current state as a new state.
+    pub fn commit(&mut self, rope: &Rope) {
+        let pending_cursor = self.pending_cursor.take();
+        let recorded = std::mem::take(&mut self.recorded);
+        let edits = if recorded.is_empty() {
+            Delta::between(&self.baseline, rope).into_iter().collect()
+        } else {
+            // Anything edited behind `replace`'s back falls back to a diff.
+            let mut replayed = self.baseline.clone();
+            recorded.iter().for_each(|edit| edit.apply(&mut replayed));
+            if replayed == *rope {
+                recorded
+            } else {
+                Delta::between(&self.baseline, rope).into_iter().collect()
+            }
+        };
+        let Some(first) = edits.first() else {
+            return;
+        };
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.nodes.push(UndoNode {
+            seq,
+            parent: self.current,
+            cursor: pending_cursor.unwrap_or(first.at),
+            edits,
+        });
+        self.current = seq;
+        self.tip = seq;
+        self.baseline = rope.clone();
+        self.prune(self.levels);
+    }
+
+    /// Reverts the current state's change. Returns the cursor to restore, or
+    /// `None` at the oldest state.
+    pub fn undo(&mut self, rope: &mut Rope) -> Option<usize> {
+        self.commit(rope);
+        let cursor = self.step_back(rope)?;
+        self.baseline = rope.clone();
+        Some(cursor)
+    }
+
+    /// Reapplies the change undone last, following the most recent branch.
+    pub fn redo(&mut self, rope: &mut Rope) -> Option<usize> {
+        self.commit(rope);
+        let mut child = self.tip;
+        while self.parent(child)? != self.current {
+            child = self.parent(child)?;
+        }
+        let cursor = self.step_forward(rope, child);
+        self.baseline = rope.clone();
+        cursor
+    }
+
+    /// Moves to the state made just before (`back`) or after the current one,
+    /// across branches. Returns the cursor to restore.
+    pub fn step_in_time(&mut self, rope: &mut Rope, back: bool) -> Option<usize> {
+        self.commit(rope);
+        let states = std::iter::once(self.root).chain(self.nodes.iter().map(|node| node.seq));
+        let target = if back {
+            states.filter(|&seq| seq < self.current).max()?
+        } else {
+            states.filter(|&seq| seq > self.current).min()?
+        };
+        let path = self.path_to_root(target);
+        let mut cursor = None;
+        while !path.contains(&self.current) {
+            cursor = self.step_back(rope);
+        }
+        let below = path.iter().position(|&seq| seq == self.current).unwrap_or(0);
+        for &seq in path[..below].iter().rev() {
+            cursor = self.step_forward(rope, seq);
+        }
+        self.tip = target;
+        self.baseline = rope.clone();
+        cursor
+    }
+
+    /// Sequence number of the current state, 0 before any change.
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    fn node(&self, seq: usize) -> Option<&UndoNode> {
+        let idx = self.nodes.binary_search_by_key(&seq, |node| node.seq).ok()?;
+        Some(&self.nodes[idx])
+    }
+
+    fn parent(&self, seq: usize) -> Option<usize> {
+        self.node(seq).map(|node| node.parent)
+    }
+
+    fn step_back(&mut self, rope: &mut Rope) -> Option<usize> {
+        let node = self.node(self.current)?;
+        node.revert(rope);
+        let (parent, cursor) = (node.parent, node.cursor);
+        self.current = parent;
+        Some(cursor)
+    }
+
+    fn step_forward(&mut self, rope: &mut Rope, seq: usize) -> Option<usize> {
+        let node = self.node(seq)?;
+        node.apply(rope);
+        let cursor = node.edits.first().map_or(0, |edit| edit.at);
+        self.current = seq;
+        Some(cursor)
+    }
+
+    /// `seq` and its ancestors, ending at the root.
+    fn path_to_root(&self, seq: usize) -> Vec<usize> {
+        let mut path = vec![seq];
+        let mut at = seq;
+        while let Some(parent) = self.parent(at) {
+            path.push(parent);
+            at = parent;
+        }
+        path
+    }
+
+    /// Drops the oldest changes until at most `levels` are kept. An oldest
+    /// change on the current branch becomes the new root and the branches
+    /// that split off before it go too; one off the current branch is dropped
+    /// with everything made on top of it. The current state is never dropped.
+    fn prune(&mut self, levels: usize) -> bool {
+        let before = self.nodes.len();
+        while self.nodes.len() > levels.max(1) {
+            let oldest = self.nodes[0].seq;
+            let mut subtree = HashSet::from([oldest]);
+            for node in &self.nodes {
+                if subtree.contains(&node.parent) {
+                    subtree.insert(node.seq);
+                }
+            }
+            if subtree.contains(&self.current) {
+                self.nodes.retain(|node| node.seq != oldest && subtree.contains(&node.seq));
+                self.root = oldest;
+            } else {
+                self.nodes.retain(|node| !subtree.contains(&node.seq));
+            }
+            if self.tip != self.root && self.node(self.tip).is_none() {
+                self.tip = self.current;
+            }
+        }
+        self.nodes.len() < before
+    }
+
+    /// Loads the history saved for `path`, if it was saved for this exact
+    /// text. Otherwise starts a fresh one.
+    pub fn load(path: &Path, rope: &Rope, levels: usize) -> Self {
+        match undo_path(path) {
+            Ok(file) => Self::load_from(&file, rope, levels),
+            Err(_) => Self::new(rope, levels),
+        }
+    }
+
+    fn load_from(file: &Path, rope: &Rope, levels: usize) -> Self {
+        let saved = fs::read_to_string(file)
+            .ok()
+            .and_then(|raw| toml::from_str::<UndoFile>(&raw).ok())
+            .filter(|saved| saved.text_hash == text_hash(rope));
+        match saved {
+            Some(UndoFile { mut tree, .. }) => {
+                tree.baseline = rope.clone();
+                tree.levels = levels;
+                tree.prune(levels);
+                tree
+            }
+            None => Self::new(rope, levels),
+        }
+    }
+
+    /// Saves the history for `path`, whose text is `rope`. Old changes are
+    /// left out of the file until it fits in `max_bytes`; the history in
+    /// memory keeps them.
+    pub fn save(&mut self, path: &Path, rope: &Rope, max_bytes: usize) -> Result<()> {
+        self.save_to(&undo_path(path)?, rope, max_bytes)
+    }
+
+    fn save_to(&mut self, file: &Path, rope: &Rope, max_bytes: usize) -> Result<()> {
+        self.commit(rope);
+        let text_hash = text_hash(rope);
+        let mut tree = self.snapshot();
+        let mut levels = tree.nodes.len();
+        loop {
+            let saved = UndoFile { text_hash, tree };
+            let text = toml::to_string(&saved).context("Failed to serialize undo history")?;
+            if text.len() <= max_bytes {
+                ensure_config_dir(file)?;
+                return fs::write(file, text)
+                    .with_context(|| format!("Failed to write {}", file.display()));
+            }
+            tree = saved.tree;
+            levels /= 2;
+            if levels == 0 || !tree.prune(levels) {
+                let _ = fs::remove_file(file);
+                return Ok(());
+            }
+        }
+    }
+
+    fn snapshot(&self) -> UndoTree {
+        UndoTree {
+            root: self.root,
+            current: self.current,
+            tip: self.tip,
+            next_seq: self.next_seq,
+            nodes: self.nodes.clone(),
+            ..UndoTree::default()
+        }
+    }
+}
+
+/// `undo/` next to the config file, one file per document named after its
+/// absolute path with `/` turned into `%`, like Vim's undo files.
+fn undo_path(path: &Path) -> Result<PathBuf> {
+    let absolute = path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let name = absolute.to_string_lossy().replace(['/', '\\'], "%");
+    Ok(config_path()?.with_file_name("undo").join(format!("{name}.toml")))
+}
+
+/// 64-bit FNV-1a of the text. It only depends on the bytes, not on how the
+/// rope happens to be chunked, and stays the same across Rust releases.
+fn text_hash(rope: &Rope) -> u64 {
+    rope.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UndoTree;
+    use crate::test_support::TempDir;
+    use ropey::Rope;
+
+    fn edit(tree: &mut UndoTree, rope: &mut Rope, at: usize, text: &str) {
+        tree.begin(rope, at);
+        rope.insert(at, text);
+    }
+
+    #[test]
+    fn undo_branches_and_walks_states_in_time() {
+        let mut rope = Rope::from_str("ab");
+        let mut tree = UndoTree::new(&rope, 100);
+        edit(&mut tree, &mut rope, 1, "1");
+        edit(&mut tree, &mut rope, 3, "2");
+        assert_eq!(rope.to_string(), "a1b2");
+
+        assert_eq!(tree.undo(&mut rope), Some(3));
+        assert_eq!(rope.to_string(), "a1b");
+        edit(&mut tree, &mut rope, 0, "X");
+        assert_eq!(tree.undo(&mut rope), Some(0));
+        assert_eq!(tree.redo(&mut rope), Some(0));
+        assert_eq!(rope.to_string(), "Xa1b");
+
+        assert_eq!(tree.step_in_time(&mut rope, true), Some(3));
+        assert_eq!(rope.to_string(), "a1b2");
+        assert_eq!(tree.step_in_time(&mut rope, true), Some(3));
+        assert_eq!(rope.to_string(), "a1b");
+        tree.step_in_time(&mut rope, false);
+        tree.step_in_time(&mut rope, false);
+        assert_eq!(rope.to_string(), "Xa1b");
+        assert_eq!(tree.step_in_time(&mut rope, false), None);
+
+        while tree.undo(&mut rope).is_some() {}
+        assert_eq!(rope.to_string(), "ab");
+    }
+
+    #[test]
+    fn prune_keeps_the_newest_changes_on_the_current_branch() {
+        let mut rope = Rope::from_str("");
+        let mut tree = UndoTree::new(&rope, 2);
+        for (at, text) in ["a", "b", "c", "d"].iter().enumerate() {
+            edit(&mut tree, &mut rope, at, text);
+        }
+        tree.commit(&rope);
+        assert_eq!(tree.nodes.len(), 2);
+        while tree.undo(&mut rope).is_some() {}
+        assert_eq!(rope.to_string(), "ab");
+
+        let text = toml::to_string(&tree.snapshot()).unwrap();
+        let mut saved: UndoTree = toml::from_str(&text).unwrap();
+        saved.baseline = rope.clone();
+        assert_eq!(saved.redo(&mut rope), Some(2));
+        assert_eq!(rope.to_string(), "abc");
+    }
+
+    #[test]
+    fn prune_drops_abandoned_branches() {
+        let mut rope = Rope::from_str("");
+        let mut tree = UndoTree::new(&rope, 3);
+        edit(&mut tree, &mut rope, 0, "a");
+        edit(&mut tree, &mut rope, 1, "b");
+        tree.undo(&mut rope);
+        for (at, text) in ["c", "d", "e", "f"].iter().enumerate() {
+            edit(&mut tree, &mut rope, at + 1, text);
+        }
+        tree.commit(&rope);
+        assert_eq!(tree.nodes.len(), 3);
+        while tree.undo(&mut rope).is_some() {}
+        assert_eq!(rope.to_string(), "ac");
+    }
+
+    #[test]
+    fn replaced_ranges_are_stored_on_their_own() {
+        let mut rope = Rope::from_str(&format!("first\n{}last\n", "middle\n".repeat(100)));
+        let mut tree = UndoTree::new(&rope, 100);
+        tree.begin(&rope, 0);
+        let end = rope.len_chars();
+        tree.replace(&mut rope, end - 5..end - 1, "LAST");
+        tree.replace(&mut rope, 0..5, "FIRST");
+        tree.commit(&rope);
+        let stored: usize = tree.nodes[0]
+            .edits
+            .iter()
+            .map(|edit| edit.removed.len() + edit.inserted.len())
+            .sum();
+        assert_eq!(stored, 18);
+        assert!(rope.to_string().starts_with("FIRST\n"));
+        tree.undo(&mut rope);
+        assert!(rope.to_string().starts_with("first\n"));
+        assert!(rope.to_string().ends_with("last\n"));
+    }
+
+    #[test]
+    fn saved_history_loads_for_the_same_text_however_it_is_chunked() {
+        let dir = TempDir::new("undo");
+        let file = dir.join("history.toml");
+        let mut rope = Rope::from_str(&"line of text\n".repeat(500));
+        let mut tree = UndoTree::new(&rope, 100);
+        for at in (0..6000).step_by(700) {
+            edit(&mut tree, &mut rope, at, "edit ");
+        }
+        tree.save_to(&file, &rope, 400).unwrap();
+        assert_eq!(tree.nodes.len(), 9);
+        tree.save_to(&file, &rope, 1 << 20).unwrap();
+
+        let mut fresh = Rope::from_str(&rope.to_string());
+        let mut loaded = UndoTree::load_from(&file, &fresh, 100);
+        assert_eq!(loaded.nodes.len(), 9);
+        while loaded.undo(&mut fresh).is_some() {}
+        assert_eq!(fresh.to_string(), "line of text\n".repeat(500));
+    }
+}